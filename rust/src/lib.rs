@@ -1,5 +1,7 @@
 #[macro_use]
 extern crate bitflags;
+#[macro_use]
+extern crate serde_derive;
 extern crate byteorder;
 extern crate unicorn;
 extern crate elf;
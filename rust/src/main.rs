@@ -10,6 +10,8 @@ extern crate scoped_threadpool;
 extern crate ctrlc;
 extern crate backtrace;
 extern crate chrono;
+#[macro_use]
+extern crate serde_derive;
 
 use self::chrono::prelude::*;
 use self::ansi_term::Colour::*;
@@ -27,7 +29,9 @@ use std::process;
 use std::process::{Command,exit};
 mod roper;
 
-use rand::{thread_rng,Rng};
+use rand::Rng;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering as AtomicOrdering;
 
 use std::path::{Path,PathBuf};
 use std::sync::{Arc,RwLock};
@@ -43,29 +47,211 @@ use roper::hatchery::*;
 use roper::phylostructs::*;
 use roper::ontostructs::*;
 use roper::csv_reader::*;
+use roper::profiler::*;
+use roper::gadgetdb::*;
+use roper::export::*;
+use roper::checkpoint::*;
+use roper::read_config::*;
+use roper::serial::load_from;
+use roper::firmware::*;
+use roper::snapshot::*;
+use roper::loader::*;
 
 fn print_usage (program: &str, opts: Options) {
-    let brief = format!("Usage: {} [options]", program);
+    let brief = format!("Usage: {} [harvest | evolve | replay <chain file> | inspect] [options]\n\n\
+                         harvest   reap and profile gadgets from the binary, save them to its gadget database\n\
+                         evolve    evolve chains for the task given (the default)\n\
+                         replay    run a saved chain verbosely in the debug engine, on the task if one is given\n\
+                         inspect   print the binary's segments and sections, and the memory map we emulate",
+                        program);
     print!("{}", opts.usage(&brief));
 }
 
+#[derive(PartialEq,Eq,Clone,Debug)]
+enum Subcommand {
+    Harvest,
+    Evolve,
+    Replay(String),
+    Inspect,
+}
 
+/* The first free argument names the subcommand. Without one, we
+ * evolve, as we always have. */
+fn subcommand (matches: &Matches) -> std::result::Result<Subcommand, String> {
+    if matches.opt_present("harvest") {
+        return Ok(Subcommand::Harvest);
+    };
+    let mut free = matches.free.iter();
+    match free.next().map(|s| s.as_str()) {
+        None | Some("evolve") => Ok(Subcommand::Evolve),
+        Some("harvest")       => Ok(Subcommand::Harvest),
+        Some("inspect")       => Ok(Subcommand::Inspect),
+        Some("replay")        => match free.next() {
+            Some(p) => Ok(Subcommand::Replay(p.clone())),
+            None    => Err("replay needs the path to a saved chain".to_string()),
+        },
+        Some(x)               => Err(format!("unknown subcommand {}", x)),
+    }
+}
 
+/* The chain has to start on a word boundary, with room on the stack
+ * for at least its first word. From a snapshot's stack pointer, the
+ * room is up to the snapshot, and hatch_chain checks it there. */
+fn check_payload_offset (params: &Params) {
+    if let Some(o) = params.payload_offset {
+        let ws = params.mode.word_size();
+        if o % ws != 0 {
+            panic!("payload_offset {} isn't a multiple of the word size, {}", o, ws);
+        };
+        if params.snapshot.is_none() && o + ws > params.stack_size {
+            panic!("payload_offset {} leaves no room for a word on a stack of {:x} bytes",
+                   o, params.stack_size);
+        };
+    };
+}
 
-/*
-fn get_gba_addr_data (path: &str) -> Vec<(u64, Vec<u8>)> {
-    let addr = GBA_CARTRIDGE_ROM_START;
-    let data = load_file(path);
-    vec![(addr,data)]
+/* Build the gadget database ahead of time, and do nothing else.
+ * Later runs on the same binary will pick it up. */
+fn harvest (elf_path: &str, params: &mut Params) {
+    params.binary_path = elf_path.to_string();
+    let objects = load_process_image(&params.image(), &params.libraries, params.mode);
+    params.code = image_code_regions(&objects);
+    params.modules = image_modules(&objects);
+    let mut machinery : Machinery
+        = Machinery::new(&params.image(), &params.libraries, params.mode,
+                         &params.emu_settings(), 1, false);
+    let clumps = build_gadget_db(params, &mut machinery.cluster[0]);
+    let uc = machinery.cluster[0].unwrap();
+    for clump in &clumps {
+        println!("{} {:?} sp{:+} {}",
+                 mod_offset(&params.modules, clump.addr()),
+                 clump.kind, clump.sp_delta, disas_clump(&uc, clump));
+    }
+    print!("{}", capability_summary(&clumps));
 }
-    */                  
 
-//const GBA_CARTRIDGE_ROM_START : u64 = 0x08000000;
+/* Where everything in the binary and its libraries is, and where it
+ * ends up in the engine that init_engine builds from them, with the
+ * snapshot, if there is one, laid over it. */
+fn inspect (image: &ImageSpec,
+            libs: &Vec<(String,u64)>,
+            mode: MachineMode,
+            settings: &EmuSettings,
+            snapshot: &Option<String>) {
+    let objects = load_process_image(image, libs, mode);
+    let secs : Vec<Sec> = objects.iter()
+                                 .flat_map(|o| o.sections.iter().cloned())
+                                 .collect();
+    let segs : Vec<Seg> = objects.iter()
+                                 .flat_map(|o| o.segments.iter().cloned())
+                                 .collect();
+    let mut uc = init_engine(&secs, &segs, mode, settings);
+    for o in &objects {
+        println!("\n****************** {} ({:?}) ******************", o.module.path, mode);
+        println!("\nMODULE {} at {:08x}: {:08x} -- {:08x}",
+                 o.module.name, o.module.bias, o.module.lo, o.module.hi);
+        if let Some(e) = o.entry {
+            println!("ENTRY {:08x}", e);
+        };
+        println!("\nSEGMENTS:");
+        for (j, seg) in o.segments.iter().enumerate() {
+            println!("  [{:2}] {:<12} {:08x} -- {:08x}  filesz {:8x}  memsz {:8x}  {:?}",
+                     j, format!("{:?}", seg.segtype),
+                     seg.addr, seg.addr + seg.memsz as u64,
+                     seg.data.len(), seg.memsz, seg.perm);
+        }
+        println!("\nSECTIONS:");
+        for sec in &o.sections {
+            let end = sec.addr + sec.data.len() as u64;
+            let seg = o.segments.iter()
+                                .position(|g| g.segtype == SegType::Load
+                                              && sec.addr >= g.addr
+                                              && end <= g.addr + g.memsz as u64);
+            let seg = match seg {
+                Some(j) if sec.alloc     => format!("in segment {}", j),
+                _                        => "unmapped".to_string(),
+            };
+            println!("  {:<24} {:08x} -- {:08x}  {:8x} bytes  {}",
+                     sec.name, sec.addr, end, sec.data.len(), seg);
+        }
+        println!("\nCODE REGIONS:");
+        for r in o.code_regions() {
+            println!("  {:<24} {:08x} -- {:08x}",
+                     r.name, r.addr, r.addr + r.data.len() as u64);
+        }
+        if !o.imports.is_empty() {
+            println!("\nUNRESOLVED IMPORTS:");
+            for r in &o.imports {
+                println!("  {:<24} at {:08x}  {:?}", r.sym_name, r.addr, r.kind);
+            }
+        };
+    }
+    if let Some(ref p) = *snapshot {
+        let snap = load_snapshot(p, mode);
+        restore_snapshot(&mut uc, &snap);
+        println!("\n****************** SNAPSHOT {} ******************", p);
+        println!("\nREGIONS:");
+        for r in &snap.regions {
+            println!("  {:08x} -- {:08x}  {:?}  {:8x} bytes  {}",
+                     r.begin, r.end, r.perm, r.data.len(), r.name);
+        }
+        println!("\nREGISTERS:");
+        for (j, v) in snap.regs.iter().enumerate() {
+            println!("  {:<6} {:08x}", reg_name(j, mode), v);
+        }
+    };
+    println!("\nMEMORY MAP:");
+    let stack = uc.stack();
+    for region in uc.mem_regions().expect("Failed to read memory regions") {
+        println!("  {:08x} -- {:08x}  {:?}{}",
+                 region.begin, region.end, region.perms,
+                 if region.begin == stack.begin { "  (stack)" } else { "" });
+    }
+}
+
+/* Run a saved chain in the debug engine, with every instruction
+ * shown. Given a task, we evaluate it as evolve would; otherwise we
+ * just run it once, from zeroed registers, or the snapshot's. */
+fn replay (path: &str, params: &Params) {
+    let chain : Chain = load_from(path)
+                            .unwrap_or_else(|e| panic!("Failed to load chain: {}", e));
+    let mut debug_machinery : Machinery
+        = Machinery::new(&params.image(), &params.libraries, params.mode,
+                         &params.emu_settings(), 1, true);
+    if let Some(ref p) = params.snapshot {
+        debug_machinery.load_snapshot(&load_snapshot(p, params.mode));
+    };
+    let mut uc = debug_machinery.cluster[0].unwrap_mut();
+    add_debug_hooks(&mut uc);
+    println!("[*] Replaying {} from {}:\n{}", chain.name, path, chain);
+    if params.io_targets.len() == 0 {
+        let input = vec![0; params.inregs.len()];
+        let res = hatch_chain(&mut uc, &chain, &input, &params.inregs, true);
+        println!("\n{}", res);
+        for &(addr, ref bytes) in &res.mem_writes {
+            println!("MEM: {:08x} <- {}", addr, hexvec(&bytes.iter().map(|&b| b as u64).collect()));
+        }
+    } else {
+        let mut rng = seeded_rng(params.seed);
+        let res = evaluate_fitness(&mut uc, &chain, params, Batch::TESTING, true, &mut rng);
+        println!("\n[*] Fitness: {:2.6}, absolute fitness: {:2.6}",
+                 res.fitness, res.ab_fitness);
+        println!("[*] Crashes: {:?}", res.crashes);
+    }
+}
+
+
+
+
+/* Set by the Ctrl-C handler; the main loop checkpoints and stops */
+static INTERRUPTED : AtomicBool = AtomicBool::new(false);
 
 #[derive(PartialEq,Eq,Clone,Debug)]
 enum Challenge {
     Data(String),
     Pattern(String),
+    Syscall(String),
+    Memory(String),
     Game(String),
     Kafka,
     Undecided,
@@ -79,13 +265,14 @@ fn main() {
     let script_dir = "/home/vagrant/ROPER/scripts/";
         
     
-/*
     ctrlc::set_handler(move || {
-        println!("Goodbye!\n");
-        std::process::exit(1);
+        if INTERRUPTED.swap(true, AtomicOrdering::SeqCst) {
+            println!("Goodbye!\n");
+            std::process::exit(1);
+        }
+        println!("\n[!] Interrupted. Checkpointing at the end of this pass; again to quit now.");
     }).expect("Error setting ctrlc handler");
-    
-*/
+
     let mut opts = Options::new();
     opts.parsing_style(ParsingStyle::FloatingFrees);
 
@@ -96,9 +283,27 @@ fn main() {
     opts.optflag("V", "noviscosity", "do not use viscosity modulations to encourage gene linkage");
     opts.optflag("h", "help", "print this help menu");
     opts.optflag("H", "homo", "enable homologous crossover");
+    opts.optflag("", "harvest", "the same as the harvest subcommand");
     opts.optflag("y", "dynamic_crash_penalty", "dynamically adjust the crash penalty in response to the population's crash rate");
 
     opts.optopt("0", "crash_penalty", "penalty to additively apply to crashing chains", "<float>");
+    opts.optopt("", "bad_bytes", "bytes the packed payload must not contain", "<comma-separated hex bytes>");
+    opts.optopt("", "max_payload", "maximum length of the packed payload", "<bytes>");
+    opts.optopt("", "load_base", "address at which to load a position-independent binary or raw image (default 10000)", "<hex address>");
+    opts.optopt("", "format", "format of the binary, if guessing from its contents won't do", "<auto|elf|raw|ihex|srec|memmap>");
+    opts.optopt("", "entry", "entry point of a firmware image that doesn't give one", "<hex address>");
+    opts.optopt("", "arch", "architecture and mode of a firmware image, which doesn't say; overrides an ELF header", "<arm|thumb|aarch64|mips|mipsel>");
+    opts.optopt("", "max_steps", "stop each run of a chain after so many instructions", "<positive integer>");
+    opts.optopt("", "stop_addr", "stop a run of a chain when it reaches this address (default 0)", "<hex address>");
+    opts.optopt("", "hook_base", "bottom of the memory that the code and write hooks watch (by default, whatever takes in every object loaded)", "<hex address>");
+    opts.optopt("", "hook_size", "size of the memory that the code and write hooks watch (by default, whatever takes in every object loaded)", "<hex bytes>");
+    opts.optopt("", "deref_size", "bytes of memory to read behind each register once a chain has run (default 512)", "<bytes>");
+    opts.optopt("", "stack_base", "address at which to map the stack, rather than just above the binary", "<hex address>");
+    opts.optopt("", "stack_size", "size of the stack, rounded up to a page", "<hex bytes>");
+    opts.optopt("", "payload_offset", "where to write the chain, in bytes from the bottom of the stack, rather than in the middle, or from the stack pointer of a snapshot", "<bytes>");
+    opts.optopt("", "stack_fill", "bytes to fill the stack with before each run, repeated as needed, rather than zeroes", "<hex string>");
+    opts.optopt("", "snapshot", "run chains in the state of a live process: an ELF core file, or a /proc/pid/maps dump with memory and register files beside it", "<path>");
+    opts.optmulti("", "lib", "load a library alongside the binary, at the given address; may be repeated", "<path>@<hex address>");
     opts.optopt("A", "apples", "number of apples, used for snek", "<integer>");
     opts.optopt("C", "cacti", "number of cacti, used for snek", "<integer>");
     opts.optopt("D", "demes", "set number of subpopulations", "<positive integer>");
@@ -111,6 +316,7 @@ fn main() {
     opts.optopt("T", "tsize", "set tournament size", "<positive integer>");
     opts.optopt("a", "address", "address and port of a game server to interact with", "<address:port>");
     opts.optopt("b", "binary", "select binary file to search for gadgets", "<path to binary file>");
+    opts.optopt("", "config", "read parameters from an s-expression config file; command line options take precedence", "<path to config file>");
     opts.optopt("c", "crossover", "set crossover (vs. clone+mutate) rate", "<float between 0.0 and 1.0>");
     opts.optopt("d", "data", "set data path", "<path to data file>");
     opts.optopt("e", "edirate", "set initial explicitly defined introns rate", "<float between 0.0 and 1.0>");
@@ -120,11 +326,25 @@ fn main() {
     opts.optopt("m", "migration", "set migration rate", "<float between 0.0 and 1.0>");
     opts.optopt("n", "game_seeds", "number of unique random seeds to use for game", "<integer>");
     opts.optopt("o", "logs", "set log directory", "<directory>");
-    opts.optopt("p", "pattern", "set target pattern", "<register pattern>");
+    opts.optopt("p", "pattern", "set target pattern, either hex values and underscores, or equations like r0=&\"/bin/sh\", r7=0xb, r2=r1, r3 in [0x1000,0x2000)", "<register pattern>");
+    opts.optopt("", "memory", "set target memory contents, as semicolon-separated hex address=value pairs, where a value is a \"string\", @symbol or hex word", "<address=value;...>");
+    opts.optopt("", "syscall", "set target syscall, either \"execve\" or a hex syscall number and argument pattern", "<name | number:pattern>");
+    opts.optopt("", "resume", "resume an evolutionary run from a checkpoint, ignoring all other parameters", "<path to checkpoint>");
+    opts.optopt("", "save_period", "checkpoint the run every so many iterations (0 to disable)", "<integer>");
     opts.optopt("r", "radius", "game board radius, used for snek", "<integer of 3 or greater>");
     opts.optopt("s", "sample_ratio", "set ratio of samples to evaluate on per training cycle", "<float > 0.0 and <= 1.0>");
     opts.optopt("t", "threads", "set number of threads", "<positive integer>");
+    opts.optopt("", "seed", "set the master random seed; the same seed and thread count reproduce a run", "<integer>");
     opts.optopt("v", "ttl", "set initial clump TTL", "<positive integer>");
+    opts.optopt("", "brood_size", "set number of offspring per mating, of which the fittest are kept", "<positive integer>");
+    opts.optopt("", "cuckoo_rate", "set rate at which offspring are laid in other demes", "<float between 0.0 and 1.0>");
+    opts.optopt("", "max_len", "set maximum chain length, in clumps", "<positive integer>");
+    opts.optopt("", "min_start_len", "set minimum length of chains in the initial population", "<positive integer>");
+    opts.optopt("", "max_start_len", "set maximum length of chains in the initial population", "<positive integer>");
+    opts.optopt("", "max_iterations", "stop after so many iterations", "<positive integer>");
+    opts.optopt("", "selection_method", "select parents by tournament or by roulette", "<tournament | roulette>");
+    opts.optopt("", "visitation_diversity_weight", "set weight given to diversity of visited addresses", "<float>");
+    opts.optopt("", "season_divisor", "set the divisor for season length", "<positive integer>");
     let matches = match opts.parse(&args[1..]) {
         Ok(m)  => { m },
         Err(f) => { panic!(f.to_string()) },
@@ -137,30 +357,49 @@ fn main() {
         return;
     }
 
-    let homologous_crossover = matches.opt_present("H");
+    let subcommand = match subcommand(&matches) {
+        Ok(sc) => sc,
+        Err(e) => {
+            println!("[!] {}", e);
+            print_usage(&program, opts);
+            return;
+        },
+    };
+
+    /* The config file fills in whatever the command line leaves out */
+    let config = match matches.opt_str("config") {
+        None    => Config::new(),
+        Some(p) => {
+            println!("[*] Reading parameters from {}", p);
+            Config::load(&p).unwrap_or_else(|e| panic!("Failed to load config: {}", e))
+        },
+    };
+
+    let homologous_crossover = matches.opt_present("H")
+        || config.get("homologous_crossover", false);
 
     let ttl = match matches.opt_str("v") {
-        None => 16,
+        None => config.get("ttl", 16),
         Some(n) => n.parse::<usize>().expect("Failed to parse ttl"),
     };
 
     let stack_input_sampling = match matches.opt_str("I") {
-        None => 0.0,
+        None => config.get("stack_input_sampling", 0.0),
         Some(n) => n.parse::<f32>().expect("Failed to parse stack_input_sampling"),
     };
     
     let comment = match matches.opt_str("X") {
-        None => "".to_string(),
+        None => config.get("comment", "".to_string()),
         Some(s) => s.to_string(),
     };
 
     let num_attrs = match matches.opt_str("N") {
-        None => 4,
+        None => config.get("num_attrs", 4),
         Some(n) => n.parse::<usize>().expect("Failed to parse num_attrs"),
     };
 
     let num_classes = match matches.opt_str("Z") {
-        None => 3,
+        None => config.get("num_classes", 3),
         Some(n) => n.parse::<usize>().expect("Failed to parse num_classes"),
     };
     
@@ -170,28 +409,32 @@ fn main() {
         challenge = Challenge::Kafka;
     }
 
-    let use_viscosity = ! matches.opt_present("V");
+    let use_viscosity = ! matches.opt_present("V")
+        && config.get("use_viscosity", true);
     
-    let use_edis = matches.opt_present("E");
+    let use_edis = matches.opt_present("E")
+        || config.get("use_edis", false);
 
     let edirate = match matches.opt_str("e") {
-        None => 0.10,
+        None => config.get("initial_edi_rate", 0.10),
         Some(n) => n.parse::<f32>().expect("Failed to parse edirate (-e)"),
     };
 
     let edi_toggle_rate = match matches.opt_str("+") {
-        None => 0.01,
+        None => config.get("edi_toggle_rate", 0.01),
         Some(n) => n.parse::<f32>().expect("Failed to parse edi_toggle_rate (-+)"),
     };
 
     let crash_penalty = match matches.opt_str("0") {
-        None => 0.2,
+        None => config.get("crash_penalty", 0.2),
         Some(n) => n.parse::<f32>().expect("Failed to parse crash_penalty"),
     };
 
-    let use_dynamic_crash_penalty = matches.opt_present("y");
+    let use_dynamic_crash_penalty = matches.opt_present("y")
+        || config.get("use_dynamic_crash_penalty", false);
 
-    let random_override = matches.opt_present("O");
+    let random_override = matches.opt_present("O")
+        || config.get("random_override", false);
       
     let game_seeds = match matches.opt_str("n") {
         None => 9,
@@ -219,7 +462,7 @@ fn main() {
     };
 
     let host_port = match matches.opt_str("a") {
-        None    => "".to_string(),
+        None    => config.get("host_port", "".to_string()),
         Some(s) => {
             challenge = Challenge::Game(s.to_string());
             s.to_string()
@@ -227,46 +470,76 @@ fn main() {
     };
     
     let crossover_rate = match matches.opt_str("c") {
-        None => 0.5,
+        None => config.get("crossover_rate", 0.5),
         Some(n) => n.parse::<f32>().expect("Failed to parse crossover rate"),
     };
     let sample_ratio = match matches.opt_str("s") {
-        None => 1.0,
+        None => config.get("sample_ratio", 1.0),
         Some(n) => n.parse::<f32>().expect("Failed to parse sample ratio"),
     };
     let popsize = match matches.opt_str("P") {
-        None => 2000,
+        None => config.get("population_size", 2000),
         Some(n) => n.parse::<usize>().expect("Failed to parse population size"),
     };
     let migration = match matches.opt_str("m") {
-        None => 0.1,
+        None => config.get("migration", 0.1),
         Some(n) => n.parse::<f32>().expect("Failed to parse migration rate"),
     };
     let num_demes = match matches.opt_str("D") {
-        None => 4,
+        None => config.get("num_demes", 4),
         Some(n) => n.parse::<usize>().expect("Failed to parse number of demes"),
     };
     let label = match matches.opt_str("L") {
-        None => "roper".to_string(),
+        None => config.get("label", "roper".to_string()),
         Some(n) => n.to_string(),
     };
     if let Some(rp) = matches.opt_str("p") {
         challenge = Challenge::Pattern(rp.to_string())
     };
+    if let Some(sc) = matches.opt_str("syscall") {
+        challenge = Challenge::Syscall(sc.to_string())
+    };
+    if let Some(mg) = matches.opt_str("memory") {
+        challenge = Challenge::Memory(mg.to_string())
+    };
 
-    let fitness_sharing = matches.opt_present("S");
+    let fitness_sharing = matches.opt_present("S")
+        || config.get("fitness_sharing", false);
 
     match matches.opt_str("d") {
         None => (),
         Some(d) => challenge = Challenge::Data(d.clone()),
     };
+    /* the config's task, in the same order of precedence, but only
+     * if the command line didn't name one */
+    if challenge == Challenge::Undecided {
+        if config.get("kafka", false) {
+            challenge = Challenge::Kafka;
+        };
+        match config.get("host_port", "".to_string()) {
+            ref hp if hp.is_empty() => (),
+            hp => challenge = Challenge::Game(hp),
+        };
+        if let Some(rp) = config.get_opt("pattern") {
+            challenge = Challenge::Pattern(rp)
+        };
+        if let Some(sc) = config.get_opt("syscall") {
+            challenge = Challenge::Syscall(sc)
+        };
+        if let Some(mg) = config.get_opt("memory") {
+            challenge = Challenge::Memory(mg)
+        };
+        if let Some(d) = config.get_opt("data") {
+            challenge = Challenge::Data(d)
+        };
+    };
 
 
     let threads : usize = match matches.opt_str("t") {
-        None => 8,
+        None => config.get("threads", 8),
         Some(n) => n.parse::<usize>().unwrap(),
     };
-    let log_dir      = match matches.opt_str("o") {
+    let log_dir      = match matches.opt_str("o").or(config.get_opt("log_dir")) {
         None    => {
             let p = Path::new("./logs/");
             if p.is_dir() { 
@@ -278,21 +551,211 @@ fn main() {
         Some(p) => p,
     };
     let t_size = match matches.opt_str("T") {
-        None => 4,
+        None => config.get("t_size", 4),
         Some(p) => p.parse::<usize>()
                                 .expect("Couldn't parse t_size parameter."),
     };
     let goal : f32 = match matches.opt_str("g") {
-        None => 0.11,
+        None => config.get("fit_goal", 0.11),
         Some(s) => s.parse::<f32>()
                                 .expect("Error parsing fitness goal"),
     };
+    let bad_bytes : Vec<u8> = match matches.opt_str("bad_bytes") {
+        None => config.get("bad_bytes", Vec::new()),
+        Some(s) => s.split(',')
+                    .filter(|b| !b.is_empty())
+                    .map(|b| u8::from_str_radix(b.trim().trim_left_matches("0x"), 16)
+                                .expect("Failed to parse bad byte"))
+                    .collect(),
+    };
+    let max_packed_len : usize = match matches.opt_str("max_payload") {
+        None => config.get("max_packed_len", 0),
+        Some(s) => s.parse::<usize>()
+                                .expect("Error parsing max_payload"),
+    };
+    let load_base : u64 = match matches.opt_str("load_base") {
+        None => config.get("load_base", PIE_BASE),
+        Some(s) => u64::from_str_radix(s.trim_left_matches("0x"), 16)
+                                .expect("Error parsing load_base"),
+    };
+    let image_format : ImageFormat = match matches.opt_str("format") {
+        None => config.get("image_format", ImageFormat::Auto),
+        Some(s) => ImageFormat::from_sexp(&Sexp::Atom(s))
+                                .expect("Error parsing format"),
+    };
+    let entry : Option<u64> = match matches.opt_str("entry") {
+        None => config.get("entry", None),
+        Some(s) => Some(u64::from_str_radix(s.trim_left_matches("0x"), 16)
+                                .expect("Error parsing entry")),
+    };
+    /* An ELF header says what the binary is for; firmware doesn't */
+    let arch : Option<MachineMode> = match matches.opt_str("arch") {
+        None => config.get_opt("mode"),
+        Some(s) => Some(MachineMode::from_sexp(&Sexp::Atom(s))
+                                .expect("Error parsing arch")),
+    };
+    /* The unicorn bindings can't open every mode we can name (see
+     * MachineMode::uc), and it's better to hear so now than from
+     * deep inside engine setup */
+    let mode_of = |path: &str| {
+        let mode = image_machine_mode(&ImageSpec::new(path, image_format, load_base, entry),
+                                      arch);
+        if mode.uc().is_none() {
+            panic!("{} is {:?}, which the unicorn bindings can't open an engine for",
+                   path, mode);
+        };
+        mode
+    };
+    let snapshot : Option<String> = match matches.opt_str("snapshot") {
+        None => config.get("snapshot", None),
+        s    => s,
+    };
+    let max_steps : usize = match matches.opt_str("max_steps") {
+        None => config.get("max_steps", MAX_STEPS),
+        Some(s) => s.parse::<usize>()
+                                .expect("Error parsing max_steps"),
+    };
+    let stop_addr : u64 = match matches.opt_str("stop_addr") {
+        None => config.get("stop_addr", STOP_ADDR),
+        Some(s) => u64::from_str_radix(s.trim_left_matches("0x"), 16)
+                                .expect("Error parsing stop_addr"),
+    };
+    let hook_base : u64 = match matches.opt_str("hook_base") {
+        None => config.get("hook_base", BASE_ADDR),
+        Some(s) => u64::from_str_radix(s.trim_left_matches("0x"), 16)
+                                .expect("Error parsing hook_base"),
+    };
+    let hook_size : usize = match matches.opt_str("hook_size") {
+        None => config.get("hook_size", MEM_SIZE),
+        Some(s) => usize::from_str_radix(s.trim_left_matches("0x"), 16)
+                                .expect("Error parsing hook_size"),
+    };
+    let deref_size : usize = match matches.opt_str("deref_size") {
+        None => config.get("deref_size", DEREF_SIZE),
+        Some(s) => s.parse::<usize>()
+                                .expect("Error parsing deref_size"),
+    };
+    let stack_base : Option<u64> = match matches.opt_str("stack_base") {
+        None => config.get("stack_base", None),
+        Some(s) => Some(u64::from_str_radix(s.trim_left_matches("0x"), 16)
+                                .expect("Error parsing stack_base")),
+    };
+    if let Some(b) = stack_base {
+        if b & 0xFFF != 0 {
+            panic!("stack_base must be page-aligned, not {:x}", b);
+        };
+    };
+    let stack_size : usize = match matches.opt_str("stack_size") {
+        None => config.get("stack_size", STACK_SIZE),
+        Some(s) => usize::from_str_radix(s.trim_left_matches("0x"), 16)
+                                .expect("Error parsing stack_size"),
+    };
+    let payload_offset : Option<usize> = match matches.opt_str("payload_offset") {
+        None => config.get("payload_offset", None),
+        Some(s) => Some(s.parse::<usize>()
+                                .expect("Error parsing payload_offset")),
+    };
+    let stack_fill : Vec<u8> = match matches.opt_str("stack_fill") {
+        None => config.get("stack_fill", Vec::new()),
+        Some(s) => decode_hex(s.trim_left_matches("0x"))
+                                .expect("Error parsing stack_fill"),
+    };
+    let libraries : Vec<(String,u64)> = match matches.opt_strs("lib") {
+        ref v if v.is_empty() => config.get("libraries", Vec::new()),
+        v => v.iter().map(|l| {
+                 let at = l.rfind('@').expect("Expected --lib <path>@<hex address>");
+                 (l[..at].to_string(),
+                  u64::from_str_radix(l[at+1..].trim_left_matches("0x"), 16)
+                      .expect("Error parsing library address"))
+             }).collect(),
+    };
+    let seed : Option<u64> = match matches.opt_str("seed") {
+        None => None,
+        Some(s) => Some(s.parse::<u64>()
+                                .expect("Error parsing seed")),
+    };
+    let save_period : Option<usize> = match matches.opt_str("save_period") {
+        None => None,
+        Some(s) => Some(s.parse::<usize>()
+                                .expect("Error parsing save_period")),
+    };
+    let season_divisor : usize = match matches.opt_str("season_divisor") {
+        None => config.get("season_divisor", 1),
+        Some(s) => s.parse::<usize>()
+                                .expect("Error parsing season_divisor"),
+    };
+    let binary : Option<String> = matches.opt_str("b")
+                                         .or(config.get_opt("binary_path"));
+    /* Only evolve and a task-bound replay need anything past here */
+    match subcommand {
+        Subcommand::Evolve => (),
+        Subcommand::Replay(_) if challenge != Challenge::Undecided => (),
+        _ => {
+            let elf_path = match binary {
+                None    => { print_usage(&program, opts); return; },
+                Some(p) => p,
+            };
+            let mut params : Params = Params::new(&label);
+            config.apply(&mut params);
+            if let Some(s) = seed {
+                params.seed = s;
+            };
+            params.binary_path = elf_path.clone();
+            params.load_base = load_base;
+            params.libraries = libraries.clone();
+            params.image_format = image_format;
+            params.entry = entry;
+            params.snapshot = snapshot.clone();
+            params.max_steps = max_steps;
+            params.stop_addr = stop_addr;
+            params.hook_base = hook_base;
+            params.hook_size = hook_size;
+            params.deref_size = deref_size;
+            params.stack_base = stack_base;
+            params.stack_size = stack_size;
+            params.payload_offset = payload_offset;
+            params.stack_fill = stack_fill.clone();
+            params.mode = mode_of(&elf_path);
+            check_payload_offset(&params);
+            match subcommand {
+                Subcommand::Harvest        => harvest(&elf_path, &mut params),
+                Subcommand::Inspect        => inspect(&params.image(), &libraries,
+                                                      params.mode,
+                                                      &params.emu_settings(),
+                                                      &snapshot),
+                Subcommand::Replay(ref p)  => {
+                    params.modules = image_modules(&load_process_image(&params.image(),
+                                                                       &libraries,
+                                                                       params.mode));
+                    replay(p, &params)
+                },
+                Subcommand::Evolve         => (),
+            };
+            return;
+        },
+    };
+    /* Everything but the engines comes from the checkpoint, which
+     * takes precedence over the rest of the command line. */
+    let resumed : Option<Checkpoint> = match matches.opt_str("resume") {
+        None    => None,
+        Some(p) => {
+            println!("[*] Resuming from checkpoint {}", p);
+            Some(Checkpoint::load(&p)
+                            .unwrap_or_else(|e| panic!("Failed to load checkpoint: {}", e)))
+        },
+    };
     // ugly kludge here
   
     let mut params : Params = Params::new(&label);
+    config.apply(&mut params);
+    if let Some(s) = seed {
+        params.seed = s;
+    };
+    /* The master RNG. Everything random in the run descends from it. */
+    let mut rng = seeded_rng(params.seed);
     let io_targets = match &challenge {
         &Challenge::Data(ref dp) => {
-            let io = process_data2(&dp, num_attrs, num_classes, &mut params).shuffle();
+            let io = process_data2(&dp, num_attrs, num_classes, &mut params).shuffle(&mut rng);
             params.outregs = (0..(num_classes)).collect(); //vec![5,6,7];
             params.inregs  = (num_classes..(num_classes+num_attrs)).collect(); //vec![1,2,3,4];
             println!(">> inregs: {:?}\n>> outregs: {:?}", 
@@ -301,10 +764,39 @@ fn main() {
             io
         },
         &Challenge::Pattern(ref pat) => {
+            /* register names in equations depend on the machine */
+            let mode = match binary {
+                None        => { print_usage(&program, opts); return; },
+                Some(ref p) => mode_of(p),
+            };
             // outregs are actually ignored now, when dealing with RPattern tasks
             params.outregs = vec![0,1,2,3,4,5,6,7,8,9,10,11,12,13,14];
             IoTargets::from_vec(TargetKind::PatternMatch,
-                vec![Problem::new(vec![0;16], mk_pattern(&pat))],
+                vec![Problem::new(vec![0;16], mk_pattern(&pat, mode))],
+                1)
+        },
+        &Challenge::Syscall(ref sc) => {
+            /* syscall numbers and argument registers depend on the
+             * machine, so we need to peek at the binary first */
+            let mode = match binary {
+                None        => { print_usage(&program, opts); return; },
+                Some(ref p) => mode_of(p),
+            };
+            params.outregs = vec![0,1,2,3,4,5,6,7,8,9,10,11,12,13,14];
+            IoTargets::from_vec(TargetKind::Syscall,
+                vec![Problem::new(vec![0;16], mk_syscall(&sc, mode))],
+                1)
+        },
+        &Challenge::Memory(ref mg) => {
+            /* symbols and word layout come from the binary */
+            let path = match binary {
+                None        => { print_usage(&program, opts); return; },
+                Some(ref p) => p.clone(),
+            };
+            let mode = mode_of(&path);
+            params.outregs = vec![0,1,2,3,4,5,6,7,8,9,10,11,12,13,14];
+            IoTargets::from_vec(TargetKind::Memory,
+                vec![Problem::new(vec![0;16], mk_memgoal(&mg, &path, load_base, mode))],
                 1)
         },
         &Challenge::Game(ref hostport) => {
@@ -330,7 +822,10 @@ fn main() {
                                 vec![Problem::new_kafkaesque()],
                                 1)
         },
-        &Challenge::Undecided => panic!("Challenge type undecided. Specify one."),
+        &Challenge::Undecided => match resumed {
+            Some(ref ck) => ck.population.params.io_targets.clone(),
+            None         => panic!("Challenge type undecided. Specify one."),
+        },
     };
 
     let (testing,training) = (io_targets.clone(), io_targets.clone()); //io_targets.split_at(io_targets.len()/3);
@@ -350,49 +845,74 @@ fn main() {
   */ 
     
     
-    let elf_path = match matches.opt_str("b") {
-        None    => { print_usage(&program, opts); return; },
-        Some(p) => p,
+    let elf_path = match (binary, &resumed) {
+        (_, &Some(ref ck)) => ck.population.params.binary_path.clone(),
+        (Some(p), _)       => p,
+        (None, _)          => { print_usage(&program, opts); return; },
     };
   
     
-    let (secs,segs) = get_elf_addr_data(&elf_path);
-    println!("****************** ELF {} **********************", elf_path);
-    let exec_secs : Vec<&Sec> = secs.iter()
-                                    .filter(|&s| sec_is_exec(s, &segs))
-                                    .collect();
-
-    let text_sec : &Sec = secs.iter()
-                              .find(|s| &(s.name) == ".text" )
-                              .expect("Couldn't find .text section...");
-    /* do this the smart way now */
-   
-    /* that's better. later try extracting gadgets from other exec-mapped
-     * sections as well, and see what happens. */
-    let text_addr = text_sec.addr;
-    let text_data = &text_sec.data;
+    println!("****************** IMAGE {} **********************", elf_path);
+    let (image, libs, mode) = match resumed {
+        Some(ref ck) => {
+            let p = &ck.population.params;
+            (p.image(), p.libraries.clone(), p.mode)
+        },
+        None         => (ImageSpec::new(&elf_path, image_format, load_base, entry),
+                         libraries.clone(),
+                         mode_of(&elf_path)),
+    };
+    let objects = load_process_image(&image, &libs, mode);
+    /* .text, .init, .fini, .plt, and whatever else is mapped 
+     * executable, including anonymous segments, in the binary and
+     * in each library */
+    let code_regions = image_code_regions(&objects);
+    if code_regions.is_empty() {
+        panic!("Couldn't find any executable code in {}", elf_path);
+    }
+    for r in &code_regions {
+        println!("[*] Code region {}:{} at {:08x}, {} bytes", 
+                 r.module, r.name, r.addr, r.data.len());
+    }
     //let rodata_addr = elf_addr_data[1].addr;
     //let rodata_data = &elf_addr_data[1].data;
-    
-    let mode = MachineMode::ARM;
 
     /* FIXME make sure that all of the params are actually passed and set here.
      * I don't think they currently are. 
      */
-    let constants = suggest_constants(&io_targets);
-    params.code = text_data.clone();
-    params.code_addr = text_addr as u32;
+    let constants = suggest_constants(&io_targets, &mut rng);
+    params.code = code_regions;
+    params.modules = image_modules(&objects);
+    params.mode = mode;
     // params.data = vec![rodata_data.clone()];
     // params.data_addrs   = vec![rodata_addr as u32];
     params.comment      = comment.clone();
-    params.constants    = constants.iter().map(|&x| x as u32).collect();
+    params.constants    = config.get("constants",
+                                     constants.iter().map(|&x| x as u32 as u64).collect());
     params.t_size       = t_size;
     params.fitness_sharing = fitness_sharing;
     params.io_targets   = training;
     params.test_targets = testing;
     params.fit_goal     = goal;
+    params.bad_bytes    = bad_bytes;
+    params.max_packed_len = max_packed_len;
+    params.load_base    = load_base;
+    params.libraries    = libraries;
+    params.image_format = image_format;
+    params.entry        = entry;
+    params.snapshot     = snapshot;
+    params.max_steps    = max_steps;
+    params.stop_addr    = stop_addr;
+    params.hook_base    = hook_base;
+    params.hook_size    = hook_size;
+    params.deref_size   = deref_size;
+    params.stack_base   = stack_base;
+    params.stack_size   = stack_size;
+    params.payload_offset = payload_offset;
+    params.stack_fill   = stack_fill;
+    check_payload_offset(&params);
     params.migration    = migration;
-    params.verbose      = verbose;
+    params.verbose      = config.get("verbose", verbose);
     params.threads      = threads;
     params.num_demes    = num_demes;
     params.use_viscosity = use_viscosity;
@@ -403,7 +923,7 @@ fn main() {
     params.binary_path = elf_path.clone();
     params.host_port = host_port; 
     params.homologous_crossover = homologous_crossover;
-    params.season_divisor = 1;
+    params.season_divisor = season_divisor;
     params.random_override = random_override;
     params.set_init_difficulties();
     params.use_edis = use_edis;
@@ -414,11 +934,74 @@ fn main() {
     params.stack_input_sampling = stack_input_sampling;
     params.ttl = ttl;
     
+    if let Some(sp) = save_period {
+        params.save_period = sp;
+    };
+    /* These have no short options, and default to what Params::new
+     * or the config file gave us. */
+    if let Some(n) = matches.opt_str("brood_size") {
+        params.brood_size = n.parse::<usize>().expect("Failed to parse brood_size");
+    };
+    if let Some(n) = matches.opt_str("cuckoo_rate") {
+        params.cuckoo_rate = n.parse::<f32>().expect("Failed to parse cuckoo_rate");
+    };
+    if let Some(n) = matches.opt_str("max_len") {
+        params.max_len = n.parse::<usize>().expect("Failed to parse max_len");
+    };
+    if let Some(n) = matches.opt_str("min_start_len") {
+        params.min_start_len = n.parse::<usize>().expect("Failed to parse min_start_len");
+    };
+    if let Some(n) = matches.opt_str("max_start_len") {
+        params.max_start_len = n.parse::<usize>().expect("Failed to parse max_start_len");
+    };
+    if let Some(n) = matches.opt_str("max_iterations") {
+        params.max_iterations = n.parse::<usize>().expect("Failed to parse max_iterations");
+    };
+    if let Some(n) = matches.opt_str("selection_method") {
+        params.selection_method = SelectionMethod::from_sexp(&Sexp::Atom(n))
+                                      .expect("Failed to parse selection_method");
+    };
+    if let Some(n) = matches.opt_str("visitation_diversity_weight") {
+        params.visitation_diversity_weight = n.parse::<f32>()
+                                              .expect("Failed to parse visitation_diversity_weight");
+    };
+    
     if !use_edis {
         params.initial_edi_rate = 0.0;
         params.edi_toggle_rate  = 0.0;
     };
 
+    if let Some(ref ck) = resumed {
+        params = ck.population.params.clone();
+    };
+    let mode      = params.mode;
+    let threads   = params.threads;
+    let num_demes = params.num_demes;
+    let label     = params.label.clone();
+    let comment   = params.comment.clone();
+
+    if let Subcommand::Replay(ref path) = subcommand {
+        replay(path, &params);
+        return;
+    };
+
+    /* Record what we're actually running with, as a config that can
+     * be handed back to --config to run it again. */
+    let task : Vec<(String, String)> = match &challenge {
+        &Challenge::Data(ref d)     => vec![("data".to_string(), d.to_sexp()),
+                                            ("num_attrs".to_string(), num_attrs.to_sexp()),
+                                            ("num_classes".to_string(), num_classes.to_sexp())],
+        &Challenge::Pattern(ref p)  => vec![("pattern".to_string(), p.to_sexp())],
+        &Challenge::Syscall(ref sc) => vec![("syscall".to_string(), sc.to_sexp())],
+        &Challenge::Memory(ref mg)  => vec![("memory".to_string(), mg.to_sexp())],
+        &Challenge::Kafka           => vec![("kafka".to_string(), true.to_sexp())],
+        _                           => Vec::new(), /* host_port is in params */
+    };
+    let config_path = format!("{}/{}_{}.config.lisp",
+                              params.log_dir, label, params.timestamp);
+    write_config(&params, &task, &config_path);
+    println!("[*] Parameters written to {}", config_path);
+
     //params.io_targets.num_classes = params.outregs.len();
     // add string search function
     // find string addresses in rodata
@@ -427,29 +1010,54 @@ fn main() {
     println!("PARAMETERS:\n{}", params);
 
     let mut machinery : Machinery
-        = Machinery::new(&elf_path,
+        = Machinery::new(&params.image(),
+                         &params.libraries,
                          mode,
+                         &params.emu_settings(),
                          threads,
                          false);
     
-    let population = Population::new(&params, &mut machinery.cluster[0]);
+    let (population, mut champion, mut i, mut crash_rate) : (Population, Option<Chain>, usize, f32)
+        = match resumed {
+            Some(ck) => {
+                rng = rng_from_seed(ck.rng_seed);
+                (ck.population, ck.champion, ck.passes, ck.crash_rate)
+            },
+            None     => (Population::new(&params, &mut machinery.cluster[0], &mut rng),
+                         None,
+                         0,
+                         0.5),
+        };
+    /* Gadgets are harvested and profiled in the clean image, so
+     * that the gadget database doesn't depend on the snapshot */
+    let snap : Option<Snapshot> = params.snapshot
+                                        .as_ref()
+                                        .map(|p| load_snapshot(p, mode));
+    if let Some(ref s) = snap {
+        machinery.load_snapshot(s);
+    };
+    let ckpt_path = checkpoint_path(&params);
+    let save_period = params.save_period;
+    let mut next_checkpoint = population.iteration + save_period;
 
     let mut debug_machinery : Machinery 
-        = Machinery::new(&elf_path,
+        = Machinery::new(&params.image(),
+                         &params.libraries,
                          mode,
+                         &params.emu_settings(),
                          1,
                          true);
+    if let Some(ref s) = snap {
+        debug_machinery.load_snapshot(s);
+    };
     add_debug_hooks(&mut debug_machinery.cluster[0].unwrap_mut());
     let printevery = 1;
-    let mut champion : Option<Chain> = None;
-    let mut season = 0;
+    let mut season = population.season;
     let max_iterations = params.max_iterations;
     let pop_rw  = RwLock::new(population);
     let pop_arc = Arc::new(pop_rw); 
     let pop_local = pop_arc.clone();
-    let mut first_log = true;
-    let mut i = 0; 
-    let mut crash_rate : f32 = 0.5;
+    let mut first_log = i == 0;
     
     let peek_path = format!("/tmp/roper/{}.peek", label);
     let peek_path = Path::new(&peek_path);
@@ -458,8 +1066,8 @@ fn main() {
     /***************************
       * The Main Evolution Loop *
       ***************************/
-    let mut heatmap : HashMap<u32,usize> = HashMap::new();
-    let mut all_heatmaps : Vec<HashMap<u32,usize>> = Vec::new();
+    let mut heatmap : HashMap<u64,usize> = HashMap::new();
+    let mut all_heatmaps : Vec<HashMap<u64,usize>> = Vec::new();
     while i < max_iterations
         && (champion == None 
         || champion.as_ref()
@@ -478,25 +1086,34 @@ fn main() {
         let mut pool  = Pool::new(n_workers);
         let challenge = challenge.clone();
         pool.scoped(|scope| {
-            let mut vdeme = thread_rng().gen::<usize>() % num_demes;
-            for e in machinery.cluster.iter_mut() {
+            let mut vdeme = rng.gen::<usize>() % num_demes;
+            for (w, e) in machinery.cluster.iter_mut().enumerate() {
                 let tx = tx.clone();
                 let p = pop_arc.clone();
                 let verbose = false; //vdeme == 0 && season > 1 && iteration % show_every == show_every % threads;
+                /* each worker gets its own stream, dealt out in order */
+                let mut wrng = child_rng(&mut rng);
                 scope.execute(move || {
                     let t = tournament(&p.read()
                                          .expect("Failed to open read lock on tournament"),
                                         e,
                                         Batch::TRAINING,
                                         vdeme,
-                                        verbose);
-                    tx.send(t).expect("Failed to sent tournament result down channel");
+                                        verbose,
+                                        &mut wrng);
+                    tx.send((w, t)).expect("Failed to sent tournament result down channel");
                 });
                 vdeme = (vdeme + 1) % num_demes;
             }
-            let mut trs : Vec<TournamentResult> = rx.iter()
-                                                    .take(n_jobs)
-                                                    .collect();
+            /* results arrive in whatever order the workers finish, so
+             * put them back in worker order before ranking them */
+            let mut wtrs : Vec<(usize, TournamentResult)> = rx.iter()
+                                                              .take(n_jobs)
+                                                              .collect();
+            wtrs.sort_by_key(|&(w, _)| w);
+            let mut trs : Vec<TournamentResult> = wtrs.into_iter()
+                                                      .map(|(_, t)| t)
+                                                      .collect();
             trs.sort_by(|a,b| b.best.ab_fitness
                                .partial_cmp(&a.best.ab_fitness)
                                .unwrap_or(Ordering::Equal));
@@ -516,6 +1133,16 @@ fn main() {
                                                                &mut heatmap);
                     if updated != None {
                         champion = updated.clone();
+                        let path = format!("{}/{}_champion_{}_{}",
+                                           params.log_dir,
+                                           label,
+                                           &params.timestamp,
+                                           iteration);
+                        export_chain(champion.as_ref()
+                                             .expect("failed to unwrap champ to export"),
+                                     &debug_machinery.cluster[0].unwrap(),
+                                     &params,
+                                     &path);
                     };
                     //let mean_fit_deltas = mean(&fit_deltas);
                     if updated != None || (peek_path.exists() && champion != None) {
@@ -539,7 +1166,8 @@ fn main() {
                                 &champion.expect("Failed to unwrap champion"),
                                          &params,
                                          Batch::TESTING,
-                                         true);
+                                         true,
+                                         &mut rng);
                     }
                     /* TODO: try commenting out the next line to hold crash penalty constant */
                     if mut_pop.params.use_dynamic_crash_penalty {
@@ -657,6 +1285,30 @@ fn main() {
             //println!("------------------------------------------------");
         }); // END POOL SCOPE
         i += 1;
+        let interrupted = INTERRUPTED.load(AtomicOrdering::SeqCst);
+        let iteration = pop_local.read()
+                                 .expect("Failed to open read lock on pop_local")
+                                 .iteration;
+        if interrupted || (save_period > 0 && iteration >= next_checkpoint) {
+            let ck = Checkpoint {
+                population: pop_local.read()
+                                     .expect("Failed to open read lock on pop_local")
+                                     .clone(),
+                champion:   champion.clone(),
+                rng_seed:   reseed_for_checkpoint(&mut rng),
+                passes:     i,
+                crash_rate: crash_rate,
+            };
+            match ck.save(&ckpt_path) {
+                Ok(())  => println!("[*] Checkpoint saved to {}", ckpt_path),
+                Err(e)  => println!("[!] Failed to save checkpoint: {}", e),
+            };
+            next_checkpoint = iteration + save_period;
+        }
+        if interrupted {
+            println!("[*] Resume with --resume {}", ckpt_path);
+            return;
+        }
     } // END OF MAIN LOOP
     println!("=> {} ITERATIONS",
                       pop_local.read()
@@ -674,7 +1326,8 @@ fn main() {
                          &mut champion.as_mut().unwrap(),
                          &pop_local.read().unwrap().params,
                          Batch::TRAINING, // there's a bug right now causing the testing set to be empty. fix it. 
-                         true);
+                         true,
+                         &mut rng);
     //champion.unwrap().dump("stdout", 
     //                       &params.binary_path, 
     //                       debug_machinery.cluster[0].unwrap(),
@@ -690,6 +1343,16 @@ fn main() {
     };
              
 
+    let export_path = format!("{}/{}_champion_{}_final",
+                              params.log_dir,
+                              label,
+                              &params.timestamp);
+    export_chain(champion.as_ref().unwrap(),
+                 &debug_machinery.cluster[0].unwrap(),
+                 &params,
+                 &export_path);
+    println!("[*] Exported champion to {}.{{py,c,bin,json}}", export_path);
+
     println!("\n{}", pop_local.read().unwrap().best.clone().unwrap());
     println!("[*] Absolute fitness of champion on testing run: {:2.6}",
                       testing_res.ab_fitness);
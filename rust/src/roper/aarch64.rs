@@ -0,0 +1,161 @@
+// Gadget reaping for AArch64. There's no pop-into-pc here, so what
+// we look for are function epilogues that reload the link register
+// from the stack and return through it:
+//
+//     ldp x29, x30, [sp], #n ; ret
+//     ldp x29, x30, [sp, #k] ; add sp, sp, #n ; ret
+//     ldr x30, [sp], #n      ; ret
+//
+// All instructions are 32 bits wide, and we only handle little-endian
+// code for now.
+#![allow(bad_style)]
+
+use roper::util::*;
+use roper::phylostructs::*;
+
+static LR : u32 = 30;
+static SP : u32 = 31;
+
+const MAX_WALK : usize = 8;
+
+// ret, through x30. ret xN for other N is a plain jump through xN,
+// which we don't bother with.
+pub fn a64_is_ret (w: u32) -> bool {
+        w == 0xD65F03C0
+}
+
+fn rt (w: u32) -> u32 { w & 0x1F }
+fn rn (w: u32) -> u32 { (w >> 5) & 0x1F }
+fn rt2 (w: u32) -> u32 { (w >> 10) & 0x1F }
+
+// The 7-bit signed, scaled offset of a 64-bit ldp/stp, in bytes.
+fn ldp_imm (w: u32) -> i32 {
+        ((((w >> 15) & 0x7F) << 25) as i32 >> 25) * 8
+}
+
+// ldp xT, x30, [sp], #n
+fn ldp_lr_post (w: u32) -> Option<i32> {
+        if w & 0xFFC00000 == 0xA8C00000 && rt2(w) == LR && rn(w) == SP {
+            Some(ldp_imm(w))
+        } else {
+            None
+        }
+}
+
+// ldp xT, x30, [sp, #k]
+fn ldp_lr_offset (w: u32) -> Option<i32> {
+        if w & 0xFFC00000 == 0xA9400000 && rt2(w) == LR && rn(w) == SP {
+            Some(ldp_imm(w))
+        } else {
+            None
+        }
+}
+
+// ldr x30, [sp], #n
+fn ldr_lr_post (w: u32) -> Option<i32> {
+        if w & 0xFFE00C00 == 0xF8400400 && rt(w) == LR && rn(w) == SP {
+            Some((((w >> 12) & 0x1FF) << 23) as i32 >> 23)
+        } else {
+            None
+        }
+}
+
+// add sp, sp, #n (unshifted)
+fn add_sp_imm (w: u32) -> Option<i32> {
+        if w & 0xFFC003FF == 0x910003FF {
+            Some(((w >> 10) & 0xFFF) as i32)
+        } else {
+            None
+        }
+}
+
+pub fn a64_is_ctrl (w: u32) -> bool {
+        w == 0                                  // udf
+        || w & 0x7C000000 == 0x14000000         // b, bl
+        || w & 0xFF000010 == 0x54000000         // b.cond
+        || w & 0x7E000000 == 0x34000000         // cbz, cbnz
+        || w & 0x7E000000 == 0x36000000         // tbz, tbnz
+        || w & 0xFE000000 == 0xD6000000         // br, blr, ret, eret
+        || w & 0xFF000000 == 0xD4000000         // svc, hvc, brk, ...
+}
+
+// Rough, like its ARM counterpart, but errs on the side of yes.
+pub fn a64_moves_sp (w: u32) -> bool {
+        let rd = rt(w);
+        let s_bit = w & (1 << 29) != 0;
+        (w & 0x1F000000 == 0x11000000 && rd == SP && !s_bit)    // add/sub imm
+        || (w & 0x1FE00000 == 0x0B200000 && rd == SP && !s_bit) // add/sub ext
+        || (w & 0x3B800000 == 0x28800000 && rn(w) == SP)        // ldp/stp post
+        || (w & 0x3B800000 == 0x29800000 && rn(w) == SP)        // ldp/stp pre
+        || (w & 0x3B200400 == 0x38000400 && rn(w) == SP)        // ldr/str pre, post
+}
+
+// Find the instruction that reloads x30 for the ret at ws[j], and
+// work out the stack layout. The clump's word is the index of that
+// instruction, and ret_addr the index of the ret, as with the ARM
+// jump-register gadgets.
+fn epilogue_clump (ws: &Vec<u32>, j: usize) -> Option<Clump> {
+        let mk = |o: usize, sp_delta: i32, lr_slot: i32| {
+            if sp_delta <= 0 || lr_slot <= 0 || lr_slot > sp_delta { return None };
+            Some(Clump {
+                exchange:   false,
+                sp_delta:   sp_delta,
+                ret_offset: lr_slot as usize,
+                words:      vec![o as u64],
+                ret_addr:   j as u64,
+                mode:       MachineMode::AARCH64,
+                kind:       GadgetKind::JumpReg,
+                ..Default::default()
+            })
+        };
+        if j < 1 { return None };
+        if let Some(n) = ldp_lr_post(ws[j-1]) {
+            return mk(j-1, n / 8, 2);
+        }
+        if let Some(n) = ldr_lr_post(ws[j-1]) {
+            return mk(j-1, n / 8, 1);
+        }
+        if j < 2 { return None };
+        match (ldp_lr_offset(ws[j-2]), add_sp_imm(ws[j-1])) {
+            (Some(k), Some(n)) => mk(j-2, n / 8, k / 8 + 2),
+            _                  => None,
+        }
+}
+
+fn a64_scan_for_rets (ws: &Vec<u32>) -> Vec<Clump> {
+        (0..ws.len()).filter(|&i| a64_is_ret(ws[i]))
+                     .filter_map(|i| epilogue_clump(ws, i))
+                     .collect()
+}
+
+pub fn reap_aarch64_gadgets (code: &Vec<u8>,
+                             start_addr: u64)
+                             -> Vec<Clump> {
+        let mut gads : Vec<Clump> = Vec::new();
+        let insts : Vec<u32>      = u8s_to_u32s(code, Endian::LITTLE);
+        for clump in a64_scan_for_rets(&insts) {
+            // walk up from the reload of x30 until we hit a control
+            // instruction, or something that moves sp out from under
+            // the epilogue. Every step is a candidate entry point.
+            let from = clump.words[0] as usize;
+            let exit = clump.ret_addr;
+            let mut o = from;
+            loop {
+                gads.push(Clump {
+                    sp_delta   : clump.sp_delta,
+                    ret_offset : clump.ret_offset,
+                    words      : vec![start_addr + (4 * o as u64)],
+                    ret_addr   : start_addr + (4 * exit),
+                    mode       : clump.mode,
+                    exchange   : clump.exchange,
+                    kind       : clump.kind,
+                    ..Default::default()
+                });
+                if o == 0 || o <= from.saturating_sub(MAX_WALK) { break };
+                let w = insts[o-1];
+                if a64_is_ctrl(w) || a64_moves_sp(w) { break };
+                o -= 1;
+            }
+        }
+        gads
+}
@@ -84,6 +84,55 @@ static MASK_VEC : &'static [(u32,u32,Lay)] = &[
           Lay::SWI),
 ];
 
+/* The condition field, bits 31:28. NV is really the unconditional
+ * instruction space on ARMv5 and up, which our layout table knows
+ * nothing about. */
+#[derive(Eq,PartialEq,Debug,Clone,Copy,Serialize,Deserialize)]
+pub enum Condition {
+        EQ, NE, CS, CC, MI, PL, VS, VC,
+        HI, LS, GE, LT, GT, LE, AL, NV,
+}
+
+pub static CONDITIONS : [Condition; 16] = [
+        Condition::EQ, Condition::NE, Condition::CS, Condition::CC,
+        Condition::MI, Condition::PL, Condition::VS, Condition::VC,
+        Condition::HI, Condition::LS, Condition::GE, Condition::LT,
+        Condition::GT, Condition::LE, Condition::AL, Condition::NV,
+];
+
+pub const FLAG_N : u8 = 0b1000;
+pub const FLAG_Z : u8 = 0b0100;
+pub const FLAG_C : u8 = 0b0010;
+pub const FLAG_V : u8 = 0b0001;
+
+impl Condition {
+        /// The flags this condition is decided by
+        pub fn reads (&self) -> u8 {
+            match *self {
+                Condition::EQ | Condition::NE => FLAG_Z,
+                Condition::CS | Condition::CC => FLAG_C,
+                Condition::MI | Condition::PL => FLAG_N,
+                Condition::VS | Condition::VC => FLAG_V,
+                Condition::HI | Condition::LS => FLAG_C | FLAG_Z,
+                Condition::GE | Condition::LT => FLAG_N | FLAG_V,
+                Condition::GT | Condition::LE => FLAG_N | FLAG_Z | FLAG_V,
+                Condition::AL | Condition::NV => 0,
+            }
+        }
+}
+
+pub fn flags_str (f: u8) -> String {
+        let mut s = String::new();
+        for &(bit,c) in [(FLAG_N,'N'),(FLAG_Z,'Z'),(FLAG_C,'C'),(FLAG_V,'V')].iter() {
+            s.push(if f & bit != 0 { c } else { '-' });
+        }
+        s
+}
+
+pub fn condition (w: u32) -> Condition {
+        CONDITIONS[(w >> 28) as usize]
+}
+
 fn what_layout (w: u32) -> Lay
 {
         if _DEBUG >= 3 {
@@ -183,18 +232,25 @@ fn dp_dst_reg (w: u32) -> usize {
 
 pub fn is_ctrl (w: u32) -> bool {
         // check for control-flow instructions
-        // this leaves out edge cases where PC is directly manipulated
-        // but that's ok. we're not aiming for exactness.
+        // this includes anything that writes PC, conditionally
+        // or not, since a conditional write to PC is a branch
+        // we can't see past.
         // ** add disas hook for debugging
         //return false; // let's see what this does
+        if condition(w) == Condition::NV {
+            return true;
+        }
+        let load = w & (1 << 20) != 0;
         let res = match what_layout(w) {
             Lay::BX  => true,
             Lay::BR  => true,
             //Lay::BDT => bdt_stack_direction(w) != 0,
+            Lay::BDT => load && bdt_rlist(w).contains(&PC),
+            Lay::SDT => load && dp_dst_reg(w) == PC,
             Lay::SWI => true,
             Lay::UNDEF => true,
             Lay::RAWDATA => true,
-            //Lay::DP  => special_reg(dp_dst_reg(w)),
+            Lay::DP  => dp_writes(w) && dp_dst_reg(w) == PC,
             _ => false,
         };
         if _DEBUG >= 2 && res {
@@ -203,6 +259,124 @@ pub fn is_ctrl (w: u32) -> bool {
         res
 }
 
+// bx rN and blx rN both fall under Lay::BX. Bit 5 tells them apart.
+fn bx_reg (w: u32) -> usize {
+        (w & 0x0F) as usize
+}
+
+fn is_blx (w: u32) -> bool {
+        w & (1 << 5) != 0
+}
+
+// mov pc, rN, with no shift. From ARMv7 on this interworks, like bx.
+// The S bit is in the mask: movs pc, rN is an exception return.
+fn mov_pc_reg (w: u32) -> Option<usize> {
+        if w & 0x0FFFFFF0 == 0x01A0F000 {
+            Some((w & 0x0F) as usize)
+        } else {
+            None
+        }
+}
+
+// ldr rN, [sp], #4
+fn ldr_pop_reg (w: u32) -> Option<usize> {
+        if w & 0x0FFF0FFF == 0x049D0004 {
+            Some(dp_dst_reg(w))
+        } else {
+            None
+        }
+}
+
+/// Registers popped off the stack by w, in the order they're
+/// taken from it, if w is a pop of some kind.
+fn pop_rlist (w: u32) -> Option<Vec<usize>> {
+        match ldr_pop_reg(w) {
+            Some(r) => return Some(vec![r]),
+            None    => (),
+        }
+        match what_layout(w) {
+            Lay::BDT if w & (1 << 20) != 0 => {
+                let (d, rs) = sp_delta_bdt(w);
+                if d > 0 { Some(rs) } else { None }
+            },
+            _ => None,
+        }
+}
+
+fn moves_sp (w: u32) -> bool {
+        let rn = ((w >> 16) & 0x0F) as usize;
+        let wback = w & (1 << 21) != 0;
+        match what_layout(w) {
+            Lay::BDT => bdt_stack_direction(w) != 0,
+            Lay::SDT | Lay::HDT_R | Lay::HDT_I 
+                     => rn == SP && (wback || w & (1 << 24) == 0),
+            Lay::DP  => dp_writes(w) && dp_dst_reg(w) == SP,
+            _        => false,
+        }
+}
+
+// TST, TEQ, CMP and CMN only set flags
+fn dp_writes (w: u32) -> bool {
+        let opcode = (w >> 21) & 0x0F;
+        opcode < 8 || opcode > 11
+}
+
+// Rough, but errs on the side of saying yes.
+fn writes_reg (w: u32, r: usize) -> bool {
+        let load = w & (1 << 20) != 0;
+        let rn = ((w >> 16) & 0x0F) as usize;
+        let wback = w & (1 << 21) != 0 || w & (1 << 24) == 0;
+        match what_layout(w) {
+            Lay::DP     => dp_writes(w) && dp_dst_reg(w) == r,
+            Lay::MULT   => rn == r,
+            Lay::MULT_L => rn == r || dp_dst_reg(w) == r,
+            Lay::SDS    => dp_dst_reg(w) == r,
+            Lay::SDT | Lay::HDT_R | Lay::HDT_I 
+                        => (load && dp_dst_reg(w) == r) || (wback && rn == r),
+            Lay::BDT    => (load && bdt_rlist(w).contains(&r)) 
+                           || (w & (1 << 21) != 0 && rn == r),
+            _           => false,
+        }
+}
+
+// msr cpsr_f, or cpsr_all
+fn is_msr_flags (w: u32) -> bool {
+        w & 0x0DB0F000 == 0x0120F000 && w & (1 << 19) != 0 && w & (1 << 22) == 0
+}
+
+/// The flags w sets, if it's executed.
+pub fn sets_flags (w: u32) -> u8 {
+        if is_msr_flags(w) {
+            return FLAG_N | FLAG_Z | FLAG_C | FLAG_V;
+        }
+        let s_bit = w & (1 << 20) != 0;
+        match what_layout(w) {
+            Lay::DP if s_bit => {
+                match (w >> 21) & 0x0F {
+                    // logical ops only touch C through the shifter
+                    0x0 | 0x1 | 0x8 | 0x9 | 0xC | 0xD | 0xE | 0xF 
+                        => FLAG_N | FLAG_Z | FLAG_C,
+                    _   => FLAG_N | FLAG_Z | FLAG_C | FLAG_V,
+                }
+            },
+            Lay::MULT | Lay::MULT_L if s_bit => FLAG_N | FLAG_Z,
+            _ => 0,
+        }
+}
+
+/// (flags needed, flags set) over a run of instructions, in
+/// execution order. A flag only counts as needed if something
+/// reads it before the gadget sets it itself.
+pub fn flag_usage (ws: &[u32]) -> (u8, u8) {
+        let mut needs = 0;
+        let mut sets  = 0;
+        for &w in ws {
+            needs |= condition(w).reads() & !sets;
+            sets  |= sets_flags(w);
+        }
+        (needs, sets)
+}
+
 pub fn is_arith (w: u32) -> bool {
         let res = match what_layout(w) {
             Lay::DP => true,
@@ -229,15 +403,38 @@ fn arm_scan_for_rets (ws: &Vec<u32>)
         while i > 0 {
             i -= 1;
             match what_layout(ws[i]) {
-                Lay::BDT => {
+                Lay::BX => match jop_clump(ws, i, bx_reg(ws[i])) {
+                    Some(mut c) => {
+                        if is_blx(ws[i]) { c.kind = GadgetKind::CallReg };
+                        rets.push(c)
+                    },
+                    None => (),
+                },
+                Lay::DP if mov_pc_reg(ws[i]).is_some() => {
+                    let r = mov_pc_reg(ws[i]).unwrap();
+                    match jop_clump(ws, i, r) {
+                        Some(c) => rets.push(c),
+                        None    => (),
+                    }
+                },
+                Lay::BDT if condition(ws[i]) != Condition::NV => {
                     let (d, rs) = sp_delta_bdt(ws[i]);
                     if d > 0 && rs.contains(&PC) {
+                        // LDM into PC interworks from ARMv5T on, so the
+                        // next gadget may just as well be Thumb.
+                        // If it's conditional, and the condition fails,
+                        // we fall through to whatever comes next.
+                        let kind = match condition(ws[i]) {
+                            Condition::AL => GadgetKind::Ret,
+                            c             => GadgetKind::CondRet(c),
+                        };
                         rets.push(Clump {
-                            exchange:   false,
+                            exchange:   true,
                             sp_delta:   rs.len() as i32,
                             ret_offset: rs.len(),
-                            words:      vec![i as u32],
+                            words:      vec![i as u64],
                             mode:       MachineMode::ARM,
+                            kind:       kind,
                             ..Default::default()
                             });
                     } else {
@@ -250,12 +447,47 @@ fn arm_scan_for_rets (ws: &Vec<u32>)
         }
         rets
 }
+// Given a jump through r at ws[j], look back for the pop that
+// loads r from the stack. Nothing in between may touch r or sp,
+// or transfer control. The clump's single word is the index of
+// the pop, since gadgets have to start at or above it.
+fn jop_clump (ws: &Vec<u32>, j: usize, r: usize) -> Option<Clump> {
+        // a conditional jump is just a branch we can't predict
+        if special_reg(r) || condition(ws[j]) != Condition::AL { return None };
+        let mut o = j;
+        while o > 0 && o > j.saturating_sub(16) {
+            o -= 1;
+            let w = ws[o];
+            match pop_rlist(w) {
+                Some(ref rs) if rs.contains(&PC) => return None,
+                Some(ref rs) if rs.contains(&r)  => {
+                    if condition(w) != Condition::AL { return None };
+                    return Some(Clump {
+                        exchange:   true,
+                        sp_delta:   rs.len() as i32,
+                        ret_offset: rs.index_of(r) + 1,
+                        words:      vec![o as u64],
+                        ret_addr:   j as u64,
+                        mode:       MachineMode::ARM,
+                        kind:       GadgetKind::JumpReg,
+                        ..Default::default()
+                    });
+                },
+                _ => (),
+            }
+            if is_ctrl(w) || moves_sp(w) || writes_reg(w, r) {
+                return None;
+            }
+        }
+        None
+}
+
 // Consider just having this function return an unsaturated
 // clump. Follow it up with an "expand clump" function, that
 // will do the backwards walk, and then a "saturate clump"
 // function that will populate words.
 pub fn reap_arm_gadgets (code: &Vec<u8>, 
-                                                      start_addr: u32) 
+                                                      start_addr: u64) 
                                                           -> Vec<Clump> {
         let mut gads : Vec<Clump> = Vec::new();
         let insts : Vec<u32>      = u8s_to_u32s(code, Endian::LITTLE);
@@ -265,27 +497,44 @@ pub fn reap_arm_gadgets (code: &Vec<u8>,
         // rets = Vec<(prior padding, offset, post padding)>
         for clump in rets {
             // now start walking up from offset until you hit a 
-            // control instruction
+            // control instruction. For jump-register gadgets, from
+            // is the pop that loads the register, and ret_addr 
+            // holds the index of the jump itself.
             let mut arith_count = 0;
-            let from : u32 = clump.words[0];
+            let from : u64 = clump.words[0];
+            let exit : u64 = match clump.kind {
+                GadgetKind::Ret |
+                GadgetKind::CondRet(_) => from,
+                _               => clump.ret_addr,
+            };
+            let mk = |o: usize| {
+                let (needs, sets) = flag_usage(&insts[o..(exit as usize)+1]);
+                Clump { 
+                    sp_delta     : clump.sp_delta,
+                    ret_offset   : clump.ret_offset,
+                    words        : vec![start_addr + (4 * o as u64)],
+                    ret_addr     : start_addr + (4 * exit),
+                    mode         : clump.mode,
+                    exchange     : clump.exchange,
+                    kind         : clump.kind,
+                    flags_needed : needs,
+                    flags_set    : sets,
+                    ..Default::default()
+                }
+            };
+            // the pop itself is worth an entry if there's
+            // arithmetic between it and the jump
+            if (from+1..exit).any(|k| is_arith(insts[k as usize])) {
+                gads.push(mk(from as usize));
+            }
             let mut o = from.clone() as usize;
-            while o > 0 && o > (from as usize - 16) && !is_ctrl(insts[o-1]) {
+            while o > 0 && o > (from as usize).saturating_sub(16) && !is_ctrl(insts[o-1]) {
                 o -= 1;
                 if is_arith(insts[o]) {
                     arith_count += 1;
                     println!("## insts[o] = {}; incrementing arith_count to {}",
                                       disas32(insts[o], MachineMode::ARM), arith_count);
-                    let a = start_addr + (4 * o as u32);
-                    let c = Clump { 
-                        sp_delta   : clump.sp_delta,
-                        ret_offset : clump.ret_offset,
-                        words      : vec![a],
-                        ret_addr   : start_addr + (4 * from),
-                        mode       : clump.mode,
-                        exchange   : clump.exchange,
-                        ..Default::default()
-                    };
-                    gads.push(c);
+                    gads.push(mk(o));
                 };
             }
         }
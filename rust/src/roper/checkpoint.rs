@@ -0,0 +1,64 @@
+// Checkpoints of a running evolution, so that a crash, a Ctrl-C or
+// a reboot doesn't cost us days of work. A checkpoint is taken
+// between passes of the main loop, when nothing is in flight, and
+// holds everything the loop needs to carry on: the population (with
+// its demes, best, iteration and season, and the params, which carry
+// the problems' difficulty state), the champion, and the state of
+// the main loop's RNG. The engines aren't saved; they're rebuilt
+// from params.binary_path on resume.
+//
+// A resumed run only carries on exactly where the interrupted one
+// left off if every random draw comes from that RNG, or from the
+// worker streams dealt out of it, and none from thread_rng. Anything
+// that draws from elsewhere makes resuming approximate, and so does
+// a game server, whose state isn't ours to save.
+extern crate rand;
+
+use std::fs;
+use rand::{Rng,SeedableRng};
+
+use roper::util::RoperRng;
+use roper::phylostructs::*;
+use roper::serial::*;
+
+#[derive(Serialize,Deserialize)]
+pub struct Checkpoint {
+        pub population: Population,
+        pub champion:   Option<Chain>,
+        pub rng_seed:   [u32; 4],
+        pub passes:     usize, // of the main loop, so far
+        #[serde(with = "::roper::serial::float")]
+        pub crash_rate: f32,
+}
+
+/* The RNG won't tell us its state, so at each checkpoint we
+ * draw a fresh seed from it and reseed it with that. The run
+ * carries on from the seed we saved, whether or not it's resumed. */
+pub fn reseed_for_checkpoint (rng: &mut RoperRng) -> [u32; 4] {
+        let mut seed : [u32; 4] = rng.gen();
+        if seed == [0; 4] { seed[0] = 1 }; // xorshift's one bad seed
+        rng.reseed(seed);
+        seed
+}
+
+pub fn rng_from_seed (seed: [u32; 4]) -> RoperRng {
+        RoperRng::from_seed(seed)
+}
+
+pub fn checkpoint_path (params: &Params) -> String {
+        format!("{}/{}_{}.checkpoint", params.log_dir, params.label, params.timestamp)
+}
+
+impl Checkpoint {
+        /* Written beside the target and renamed into place, so that
+         * dying mid-write leaves the last good checkpoint alone. */
+        pub fn save (&self, path: &str) -> SerialResult<()> {
+            let tmp = format!("{}.tmp", path);
+            save_to(self, &tmp)?;
+            fs::rename(&tmp, path).map_err(|e| format!("{}: {}", path, e))
+        }
+
+        pub fn load (path: &str) -> SerialResult<Checkpoint> {
+            load_from(path)
+        }
+}
@@ -1,7 +1,7 @@
 use std::io::{BufReader};
 use std::io::prelude::*;
 use std::fs::File;
-use roper::util::{Indexable};
+use roper::util::{Indexable,seeded_rng};
 use roper::phylostructs::*;
 
 
@@ -13,7 +13,7 @@ pub fn process_data2 (path: &str,
                       -> IoTargets {
         let file = File::open(path).unwrap();
         let rdr = BufReader::new(file);
-        let class_masks = class_masks_randomized(num_classes);
+        let class_masks = class_masks_randomized(num_classes, &mut seeded_rng(params.seed));
         params.class_masks = class_masks.clone();
         let mut ids : Vec<String> = Vec::new();
         let mut io_targets : IoTargets = IoTargets::new(TargetKind::Classification);
@@ -13,8 +13,6 @@ use std::io::prelude::*;
 use std::collections::{HashMap};
 use rand::distributions::*;
 use rand::Rng;
-use rand::ThreadRng;
-use rand::thread_rng;
 use unicorn::*;
   
 use std::cmp::*;
@@ -29,11 +27,12 @@ use roper::util::{pack_word32le,
                   max_bin,
                   mang,
                   Mangler,
+                  RoperRng,
                   Indexable,
+                  word_is_clean,
                   deref_mang};
 //use roper::hooks::*;
-use roper::thumb::{reap_thumb_gadgets};
-use roper::arm::{reap_arm_gadgets};
+use roper::isa::Isa;
 use roper::ontostructs::*;
 
 const LINK_FIT_ALPHA : f32 = 0.4;
@@ -52,7 +51,7 @@ fn calc_link_fit (clump: &Clump, c_fit: f32) -> Option<f32>{
   * 2. offset bloat (or encourage bloat! we'll see...)
   */
 
-fn mutate_addr (clump: &mut Clump, rng: &mut ThreadRng) {
+fn mutate_addr (clump: &mut Clump, rng: &mut RoperRng) {
         if clump.ret_addr < clump.words[0] {
             println!("[WARNING] clump.ret_addr = {:08x}\nclump.words[0] = {:08x}",
                               clump.ret_addr, clump.words[0]);
@@ -60,11 +59,7 @@ fn mutate_addr (clump: &mut Clump, rng: &mut ThreadRng) {
         }
 
         let d = clump.ret_addr - clump.words[0];
-        let inst_size = if clump.mode == MachineMode::ARM {
-            4
-        } else {
-            2
-        };
+        let inst_size = clump.mode.inst_align() as u64;
         if d > inst_size || rng.gen::<bool>() {
             clump.words[0] += inst_size;
         } else if d < (inst_size * 8) {
@@ -74,8 +69,8 @@ fn mutate_addr (clump: &mut Clump, rng: &mut ThreadRng) {
 
 fn mutate(chain: &mut Chain, 
           params: &Params, 
-          uc: &unicorn::CpuARM, 
-          rng: &mut ThreadRng) {
+          uc: &Emu, 
+          rng: &mut RoperRng) {
         /* mutations will only affect the immediate part of the clump */
         /* we'll let shufflefuck handle the rest. */
         /* Add permutation operation, shuffling immeds */
@@ -100,12 +95,27 @@ fn mutate(chain: &mut Chain,
         let mut clump = chain[cl_idx].clone();
         clump.ttl = params.ttl;
         assert!(clump.size() > 0);
-        let idx : usize   = 1 + (rng.gen::<usize>() % (clump.size() - 1));
+        /* if profiling tells us which words actually end up in
+         * registers, aim for those half the time */
+        let live : Vec<usize> = match clump.profile {
+            Some(ref p) => p.loads()
+                            .iter()
+                            .filter_map(|&(_,k)| clump.word_at_slot(k))
+                            .filter(|&i| i > 0 && i < clump.size())
+                            .collect(),
+            None        => Vec::new(),
+        };
+        let idx : usize   = if !live.is_empty() && rng.gen::<bool>() {
+            live[rng.gen::<usize>() % live.len()]
+        } else {
+            1 + (rng.gen::<usize>() % (clump.size() - 1))
+        };
         let mut_kind : u8 = rng.gen::<u8>() % 6;
         match mut_kind {
             0 => clump.words[idx] = mang(clump.words[idx].clone(), rng),
             1 => mutate_addr(&mut clump, rng),
-            2 => match deref(&(uc.emu()), clump.words[idx].clone()) {
+            2 => match deref(&(uc.emu()), clump.words[idx].clone(),
+                             uc.word_size(), uc.endian()) {
                 Some(x) => { 
                    // println!("==> deref mutation: {:x} -> {:x}", clump.words[idx], x);
                     clump.words[idx] = x; 
@@ -115,7 +125,7 @@ fn mutate(chain: &mut Chain,
             3 => match uc_seek_word(clump.words[idx].clone(), uc) {
                 Some(x) => {
                    // println!("<== indirection mutation: {:x} -> {:x}", clump.words[idx], x);
-                    clump.words[idx] = x as u32;
+                    clump.words[idx] = x;
                 },
                 None    => (),
             },
@@ -133,11 +143,15 @@ fn mutate(chain: &mut Chain,
                 clump.words[other_idx] = tmp;
             },
         };
+        /* don't let mutation smuggle bad bytes into a clean clump */
+        if !clump.is_clean(&params.bad_bytes) && chain[cl_idx].is_clean(&params.bad_bytes) {
+            return;
+        }
         /* oh christ, the mutated clumps were never entered back in the chain! */
         chain[cl_idx] = clump;
 }
 
-fn mutate_edi (chain: &mut Chain, params: &Params, rng: &mut ThreadRng) {
+fn mutate_edi (chain: &mut Chain, params: &Params, rng: &mut RoperRng) {
       if rng.gen::<f32>() < params.edi_toggle_rate {
           let i = rng.gen::<usize>() % chain.size();
           chain[i].enabled = !chain[i].enabled; 
@@ -146,13 +160,13 @@ fn mutate_edi (chain: &mut Chain, params: &Params, rng: &mut ThreadRng) {
 
 fn clone_and_mutate (parents: &Vec<&Chain>,
                      params:  &Params,
-                     uc:      &unicorn::CpuARM,
-                     rng:     &mut ThreadRng) -> Vec<Chain> {
+                     uc:      &Emu,
+                     rng:     &mut RoperRng) -> Vec<Chain> {
         let mut brood : Vec<Chain> = Vec::new();
         let n = params.brood_size;
         for i in 0..n {
             let spawnclumps = parents[i % 2].clumps.clone();
-            let mut spawn = Chain::new(spawnclumps);
+            let mut spawn = Chain::new(spawnclumps, rng);
             mutate(&mut spawn, &params, uc, rng);
             if params.use_edis { mutate_edi(&mut spawn, &params, rng); };
             /* remember: headless chicken parents won't have a fitness,
@@ -169,8 +183,8 @@ fn clone_and_mutate (parents: &Vec<&Chain>,
 
 fn mate (parents: &Vec<&Chain>, 
          params:  &Params, 
-         rng:     &mut ThreadRng,
-         uc:      &mut CpuARM,
+         rng:     &mut RoperRng,
+         uc:      &mut Emu,
          ooze:    &Vec<Clump>) -> Vec<Chain> {
         /* adjust ttls for crashes */
         let mut brood = if rng.gen::<f32>() < params.crossover_rate {
@@ -185,7 +199,7 @@ fn mate (parents: &Vec<&Chain>,
                              uc,
                              rng)
         };
-        cull_brood(&mut brood, 2, uc, &params);
+        cull_brood(&mut brood, 2, uc, &params, rng);
         brood
 }
 
@@ -195,8 +209,8 @@ pub struct EvalCaseResult {
         pub ab_fitness : f32,
         pub counter : usize,
         pub crashes : Vec<usize>,
-        pub visited : Vec<u32>,
-        pub registers : Vec<u32>,
+        pub visited : Vec<u64>,
+        pub registers : Vec<u64>,
         pub reg_deref : Vec<Option<Vec<u8>>>,
 }
 #[derive(Debug,PartialEq)]
@@ -207,8 +221,8 @@ pub struct EvalResult {
         pub counter : usize,
         pub crashes : Vec<usize>,
         pub visitation_diversity : f32,
-        pub visited_map : HashMap<Problem, Vec<u32>>,
-        pub register_map : HashMap<Problem, (Vec<u32>,Vec<Option<Vec<u8>>>)>,
+        pub visited_map : HashMap<Problem, Vec<u64>>,
+        pub register_map : HashMap<Problem, (Vec<u64>,Vec<Option<Vec<u8>>>)>,
         pub difficulties : Option<HashMap<Problem, f32>>,
 }
 
@@ -216,11 +230,12 @@ pub struct EvalResult {
   * kinds of evaluation at once. due for a major rewrite. 
   * NB: dropped support for games. do that in another eval func, not this one.
   */
-fn eval_case (uc: &mut CpuARM,
+fn eval_case (uc: &mut Emu,
               chain: &Chain,
               problem: &Problem,
               params: &Params,
-              verbose: bool) -> EvalCaseResult { 
+              verbose: bool,
+              rng: &mut RoperRng) -> EvalCaseResult { 
     let inregs = &params.inregs;
     let outregs = &params.outregs;
     let target = &problem.target;
@@ -233,9 +248,9 @@ fn eval_case (uc: &mut CpuARM,
                              &inregs,
                              reset);
     let (af,rf) = problem.assess_output(&outregs, 
-                                        &result.registers,
-                                        &result.reg_deref, 
-                                        uc);
+                                        &result,
+                                        uc,
+                                        rng);
     let counter = result.counter;
     let crash = result.error != None || result.isnull();
     
@@ -263,11 +278,12 @@ fn adj_score_for_difficulty (score: f32,
 }
 */
 pub const VARIABLE_FITNESS : bool = true;
-pub fn evaluate_fitness (uc: &mut CpuARM,
+pub fn evaluate_fitness (uc: &mut Emu,
                          chain: &Chain, 
                          params: &Params,
                          batch: Batch,
-                         verbose: bool)
+                         verbose: bool,
+                         rng: &mut RoperRng)
                          -> EvalResult //(f32,Option<usize>)
 {
         /* Empty chains can be discarded immediately */
@@ -307,8 +323,8 @@ pub fn evaluate_fitness (uc: &mut CpuARM,
         let mut anycrash = false;
         let mut all_crashes = Vec::new();
         let mut difficulties : HashMap<Problem,f32> = HashMap::new();
-        let mut visited_map  : HashMap<Problem,Vec<u32>> = HashMap::new();
-        let mut register_map : HashMap<Problem,(Vec<u32>,Vec<Option<Vec<u8>>>)>
+        let mut visited_map  : HashMap<Problem,Vec<u64>> = HashMap::new();
+        let mut register_map : HashMap<Problem,(Vec<u64>,Vec<Option<Vec<u8>>>)>
             = HashMap::new(); 
         let mut ratio_run_vec = Vec::new();
         for problem in io_targets.iter() {
@@ -316,7 +332,8 @@ pub fn evaluate_fitness (uc: &mut CpuARM,
                                                  chain,
                                                  problem,
                                                  &params,
-                                                 verbose);
+                                                 verbose,
+                                                 rng);
             let p = problem.clone();
             //println!(">> dif = {}", dif);
             //let dif = if res.fingerprint[0] {1.0} else {0.0};
@@ -353,6 +370,11 @@ pub fn evaluate_fitness (uc: &mut CpuARM,
         };
         let ab_fitness = mean(&abfit_vec);
         let mut fitness =  mean(&fit_vec); /* experimental TODO */
+        /* Bad bytes or an oversized payload make a chain useless as
+         * an exploit, however well it runs. */
+        let violation = chain.constraint_violation(params);
+        let ab_fitness = ab_fitness + (1.0 - ab_fitness) * violation;
+        fitness = fitness + (1.0 - fitness) * violation;
         if ab_fitness <= params.fit_goal {
             // adjustment to preserve elites
             fitness /= 2.0;
@@ -361,7 +383,7 @@ pub fn evaluate_fitness (uc: &mut CpuARM,
         let mut fitness = f32::min(1.0, fitness);
         let mut divers = 0.0; 
         if  io_targets.len() > 1 && params.reward_visitation_diversity {
-            let mut visits : Vec<Vec<u32>> = visited_map.values()
+            let mut visits : Vec<Vec<u64>> = visited_map.values()
                                                         .map(|x| x.clone())
                                                         .collect();
             let total : f32 = visits.len() as f32;
@@ -405,8 +427,8 @@ pub struct FitUpdate {
         pub ratio_run   : f32,
         pub runtime     : Option<f32>,
         pub visitation_diversity : f32,
-        pub visited_map : HashMap<Problem, Vec<u32>>,
-        pub register_map : HashMap<Problem, (Vec<u32>,Vec<Option<Vec<u8>>>)>,
+        pub visited_map : HashMap<Problem, Vec<u64>>,
+        pub register_map : HashMap<Problem, (Vec<u64>,Vec<Option<Vec<u8>>>)>,
 }
 
 #[derive(Debug,Clone)]
@@ -457,7 +479,7 @@ pub fn update_difficulties (params: &mut Params,
 pub fn patch_population (tr: &TournamentResult,
                          population: &mut Population,
                          verbose: bool,
-                         heatmap: &mut HashMap<u32,usize>) 
+                         heatmap: &mut HashMap<u64,usize>) 
                          -> (Option<Chain>, Vec<f32>) 
 {
         assert_eq!(tr.graves.len(), tr.spawn.len());
@@ -504,7 +526,8 @@ pub fn patch_population (tr: &TournamentResult,
 pub fn lexicase_rpat (population: &Population,
                       engine: &mut Engine,
                       batch: Batch,
-                      vdeme: usize)
+                      vdeme: usize,
+                      rng: &mut RoperRng)
                       -> ()
 {
         // for register patterns
@@ -520,13 +543,12 @@ pub fn lexicase_rpat (population: &Population,
             _   => panic!("Not that kind of lexicase, yet."),
         };
 
-        let mut rng = thread_rng(); // switch to seedable
         let deme = rng.gen::<usize>() % n_demes;
         let mut lots : Vec<usize> = 
             ((deme * d_size)..(deme * (d_size+1))).collect();
         rng.shuffle(&mut lots);
         
-        let rpvec = rpat.shuffle_vec();
+        let rpvec = rpat.shuffle_vec(rng);
         for (reg, val, dif) in rpvec {
             while lots.len() > 2 {
               // do the stuff 
@@ -538,14 +560,14 @@ pub fn tournament (population: &Population,
                    engine: &mut Engine,
                    batch: Batch,
                    vdeme: usize,
-                   verbose: bool)
+                   verbose: bool,
+                   rng: &mut RoperRng)
                    -> TournamentResult 
 {
         let season = population.season;
         let mut lots : Vec<usize> = Vec::new();
         let mut contestants : Vec<(Chain,usize)> = Vec::new();
         let mut uc = engine.unwrap_mut(); //(machinery.cluster[0].unwrap_mut()); // bandaid
-        let mut rng = thread_rng(); //&mut(machinery.rng);
         let mut t_size = population.params.t_size;
         let mut cflag = false;
         //  let io_targets = &(population.params.io_targets);
@@ -581,7 +603,8 @@ pub fn tournament (population: &Population,
                                        &specimen,
                                        &population.params,
                                        batch,
-                                       verbose); // verbose
+                                       verbose, // verbose
+                                       rng);
             let ratio_run : f32 = res.mean_ratio_run;
             let crash   = res.crashes.clone();
             //println!("==> ratio_run = {:1.6}, crash? {:?}",ratio_run, crash);
@@ -642,7 +665,7 @@ pub fn tournament (population: &Population,
         //select_mates(&mut specimens, true); //.sort();
         let (mother,m_idx) = specimens[0].clone();
         let (father,f_idx) = if cflag {
-            (population.random_spawn(), 0)
+            (population.random_spawn(rng), 0)
         } else { 
             specimens[1].clone()
         };
@@ -680,7 +703,7 @@ pub fn tournament (population: &Population,
         let parents : Vec<&Chain> = vec![&mother,&father];
         let offspring = mate(&parents,
                              &population.params,
-                             &mut rng,
+                             rng,
                              &mut uc,
                              &population.primordial_ooze);
         let t_best = specimens[0].0.clone();
@@ -725,8 +748,9 @@ fn select_mates(specimens: &mut Vec<(Chain,usize)>,
 
 fn cull_brood (brood: &mut Vec<Chain>, 
                               n: usize,
-                              uc: &mut CpuARM,
-                              params: &Params) {
+                              uc: &mut Emu,
+                              params: &Params,
+                              rng: &mut RoperRng) {
         /* Sort by fitness - most to least */
         let mut i = 0;
         if brood.len() <= n { return; };
@@ -741,7 +765,8 @@ fn cull_brood (brood: &mut Vec<Chain>,
                              &spawn, 
                              &params, 
                              Batch::TRAINING,
-                             false); 
+                             false,
+                             rng); 
         }
         brood.sort();
         /* Now eliminate the least fit */
@@ -770,7 +795,7 @@ match clump.link_fit {
 }
 
 fn splice_point (chain: &Chain, 
-                 rng: &mut ThreadRng,
+                 rng: &mut RoperRng,
                  use_viscosity: bool) -> usize {
     let mut wheel : Vec<Weighted<usize>> = Vec::new();
     let mut i : usize = 0;
@@ -799,7 +824,7 @@ fn splice_point (chain: &Chain,
 
 fn shufflefuck (parents:    &Vec<&Chain>, 
                 params:     &Params,
-                rng:        &mut ThreadRng,
+                rng:        &mut RoperRng,
                 ooze:       &Vec<Clump>) -> Vec<Chain> {
         let brood_size = params.brood_size;
         let max_len    = params.max_len;
@@ -919,7 +944,7 @@ fn shufflefuck (parents:    &Vec<&Chain>,
             if child_clumps.len() == 0 {
                 panic!("child_clumps.len() == 0. Stopping.");
             }
-            let mut child : Chain = Chain::new(child_clumps);
+            let mut child : Chain = Chain::new(child_clumps, rng);
             child.generation = max(mother.generation, father.generation)+1;
             child.p_fitness = {
                 /* vector of parents' fitness*/
@@ -937,7 +962,7 @@ fn shufflefuck (parents:    &Vec<&Chain>,
 
 pub fn saturate_clumps <'a,I> (unsat: &mut Vec<Clump>,
                                pool:  &mut I)  //Vec<u32>,
-        where I: Iterator <Item=u32> {
+        where I: Iterator <Item=u64> {
 let mut u : usize = 0;
 //let mut sat: Vec<Clump> = Vec::new();
 while u < unsat.len() {
@@ -957,12 +982,26 @@ while u < unsat.len() {
 
 }
 
+/* How many times to draw from the pool for a word without bad
+ * bytes before settling for a dirty one */
+pub const CLEAN_DRAWS : usize = 8;
+
 pub fn saturate_clump <'a,I> (unsat: &mut Clump,
-                              pool:  &mut I)  //Vec<u32>,
-        where I: Iterator <Item=u32> {
+                              pool:  &mut I,  //Vec<u32>,
+                              bad:   &Vec<u8>)
+        where I: Iterator <Item=u64> {
 let needs = (unsat.sp_delta-1) as usize;
+let width = unsat.mode.word_size();
         for _ in 0..needs {
-            match pool.next() {
+            let mut word = None;
+            for _ in 0..CLEAN_DRAWS {
+                word = pool.next();
+                match word {
+                    Some(x) if !word_is_clean(x, width, bad) => continue,
+                    _ => break,
+                }
+            }
+            match word {
                 Some(x) => unsat.push(x),
                 _       => break 
             }
@@ -971,13 +1010,11 @@ let needs = (unsat.sp_delta-1) as usize;
 
 // replace mode str with mode enum at some point
 pub fn reap_gadgets (code: &Vec<u8>, 
-                     start_addr: u32, 
+                     start_addr: u64, 
                      mode: MachineMode) 
                      -> Vec<Clump> {
-        match mode {
-            MachineMode::THUMB => reap_thumb_gadgets(code, start_addr),
-            MachineMode::ARM   => reap_arm_gadgets(code, start_addr),
-        } // .iter().filter(|c| c.size() >= 2).collect()
+        mode.reap_gadgets(code, start_addr)
+        // .iter().filter(|c| c.size() >= 2).collect()
 }
 
 /* TODO: try holding crash penalty constant */
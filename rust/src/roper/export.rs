@@ -0,0 +1,147 @@
+// Turning a chain into something that can be fired at a real target.
+// We write the same payload three ways: a standalone Python script
+// that rebuilds it with struct.pack, and that says what each word
+// is for; a C array, for pasting into an exploit; and the raw bytes.
+// All three are laid out by phylostructs::layout, the same as
+// Chain::pack, so they always agree with what was evaluated.
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+
+use roper::util::*;
+use roper::hatchery::*;
+use roper::phylostructs::*;
+use roper::ontostructs::*;
+use roper::isa::Isa;
+use roper::loader::{module_of,mod_offset};
+use roper::serial::save_to;
+
+/* Disassemble the whole gadget, from its entry point down to and
+ * including the instruction that hands over to the next one. */
+pub fn disas_clump (uc: &Emu, clump: &Clump) -> String {
+        let mode = clump.mode;
+        let addr = clump.addr();
+        if clump.ret_addr < addr {
+            return disas_addr(uc, addr);
+        }
+        let last = mode.inst_size(uc.emu(), clump.ret_addr);
+        let len  = (clump.ret_addr - addr) as usize + last;
+        match uc.mem_read(addr, len) {
+            Ok(v)  => disas(&v, mode),
+            Err(_) => "unknown".to_string(),
+        }
+}
+
+fn word_role (clump: &Clump, k: usize) -> String {
+        if k == 0 {
+            return "gadget".to_string();
+        }
+        match clump.input_slots.iter().find(|&&(off, _)| off == k) {
+            Some(&(_, inp)) => format!("input slot {}", inp),
+            None            => "immediate".to_string(),
+        }
+}
+
+fn struct_fmt (chain: &Chain) -> String {
+        let e = match chain.endian() {
+            Endian::BIG => ">",
+            _           => "<",
+        };
+        let w = match chain.word_size() {
+            8 => "Q",
+            _ => "I",
+        };
+        format!("{}{}", e, w)
+}
+
+pub fn export_python (chain: &Chain, uc: &Emu, params: &Params) -> String {
+        let words = chain.stack_words();
+        let hexw  = chain.word_size() * 2;
+        let mut s = String::new();
+        s.push_str("#! /usr/bin/env python\n");
+        s.push_str(&format!("# ROPER chain {} for {}\n", chain.name, params.binary_path));
+        s.push_str(&format!("# Absolute fitness: {:?}, generation {}\n",
+                            chain.ab_fitness, chain.generation));
+        s.push_str("# Words marked as input slots are overwritten with the\n");
+        s.push_str("# problem's inputs before the chain is run.\n");
+        s.push_str("import struct\nimport sys\n\n");
+        /* gadgets in a library are written relative to its base, so
+         * the chain can be rebased if the library is loaded elsewhere */
+        if !params.modules.is_empty() {
+            s.push_str("base = {\n");
+            for m in &params.modules {
+                s.push_str(&format!("    '{}': 0x{:x},\n", m.name, m.bias));
+            }
+            s.push_str("}\n\n");
+        }
+        s.push_str(&format!("chain = [0] * {}\n", words.len()));
+        let mut last = None;
+        for (j, k, t, w) in layout(&chain.clumps) {
+            let clump = &chain.clumps[j];
+            if last != Some(j) {
+                s.push_str(&format!("\n# clump {} @ {} [{:?}]: {}\n",
+                                    j, mod_offset(&params.modules, clump.addr()),
+                                    clump.mode, disas_clump(uc, clump)));
+                last = Some(j);
+            }
+            match module_of(&params.modules, w) {
+                Some(m) if k == 0 && m.bias != 0 =>
+                    s.push_str(&format!("chain[{}] = base['{}'] + 0x{:x} # {}\n",
+                                        t, m.name, w - m.bias, word_role(clump, k))),
+                _ =>
+                    s.push_str(&format!("chain[{}] = 0x{:0width$x} # {}\n",
+                                        t, w, word_role(clump, k), width = hexw)),
+            }
+        }
+        s.push_str(&format!("\npayload = b''.join(struct.pack('{}', w) for w in chain)\n\n",
+                            struct_fmt(chain)));
+        s.push_str("if len(sys.argv) > 1:\n");
+        s.push_str("    with open(sys.argv[1], 'wb') as f:\n");
+        s.push_str("        f.write(payload)\n");
+        s.push_str("else:\n");
+        s.push_str("    getattr(sys.stdout, 'buffer', sys.stdout).write(payload)\n");
+        s
+}
+
+pub fn export_c (chain: &Chain) -> String {
+        let packed = chain.pack();
+        /* names are hyphenated after every other syllable */
+        let ident = chain.name.replace("-", "_");
+        let mut s = String::new();
+        s.push_str(&format!("/* ROPER chain {}, {} bytes */\n", chain.name, packed.len()));
+        s.push_str(&format!("unsigned char chain_{}[{}] = {{", ident, packed.len()));
+        let mut j = 0;
+        for b in &packed {
+            if j % 12 == 0 { s.push_str("\n  "); }
+            s.push_str(&format!("0x{:02x},", b));
+            j += 1;
+            if j % 12 != 0 { s.push_str(" "); }
+        }
+        s.push_str("\n};\n");
+        s.push_str(&format!("unsigned int chain_{}_len = {};\n", ident, packed.len()));
+        s
+}
+
+fn write_file (path: &str, bytes: &[u8]) {
+        let mut file = OpenOptions::new()
+                        .truncate(true)
+                        .write(true)
+                        .create(true)
+                        .open(path)
+                        .expect(&format!("Failed to open {} for export", path));
+        file.write_all(bytes)
+            .expect(&format!("Failed to write {}", path));
+        file.flush().unwrap();
+}
+
+/* Writes basepath.py, basepath.c and basepath.bin, along with the
+ * chain itself as basepath.json, so it can be loaded again later. */
+pub fn export_chain (chain: &Chain, uc: &Emu, params: &Params, basepath: &str) {
+        write_file(&format!("{}.py", basepath),
+                   export_python(chain, uc, params).as_bytes());
+        write_file(&format!("{}.c", basepath),
+                   export_c(chain).as_bytes());
+        write_file(&format!("{}.bin", basepath),
+                   &chain.pack());
+        save_to(chain, &format!("{}.json", basepath))
+            .expect("Failed to save chain as JSON");
+}
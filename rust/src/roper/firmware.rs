@@ -0,0 +1,567 @@
+// Images that aren't ELF. Router and IoT firmware often comes as a
+// raw dump of flash, as an Intel HEX or Motorola S-record file from
+// a vendor's update tool, or as several of these that only make sense
+// together. None of them say what machine they're for, and a raw dump
+// doesn't even say where it goes, so the architecture comes from
+// --arch or the config, and a raw image is put at load_base (a GBA
+// cartridge, for instance, at 8000000). A memory map ties several
+// pieces together, as a file of s-expressions like a config:
+//
+//   ;; a Cortex-M part: flash, SRAM and a bootloader
+//   (arch :thumb)
+//   (entry #x08000101)
+//   (region :name "flash" :addr #x08000000 :file "app.bin" :perms "r-x")
+//   (region :name "boot" :file "boot.hex" :perms "r-x")
+//   (region :name "sram" :addr #x20000000 :size #x8000 :perms "rw-")
+//
+// A region's :file may be raw, in which case :offset and :size pick
+// out a slice of it, and :size past the end of the file is zeroes;
+// or HEX or S-record, which carry their own addresses. A region with
+// no file is :size zeroes. Relative paths are relative to the map.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use std::result;
+
+use unicorn::*;
+
+use roper::ontostructs::*;
+use roper::loader::Module;
+use roper::phylostructs::{Endian,MachineMode};
+use roper::read_config::{Sexp,FromSexp,read_sexps};
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Serialize,Deserialize)]
+pub enum ImageFormat {
+        Auto, /* look at the file and guess */
+        Elf,
+        Raw,
+        IHex,
+        SRec,
+        MemMap,
+}
+
+/* ELF has its magic number. HEX and S-record files are lines of
+ * hex digits after a : or an S and a record type, and a memory map
+ * opens with a form or a comment, and reads as one. Anything else is
+ * raw, including code that happens to start with a ( or a ; byte. */
+pub fn sniff_format (path: &str) -> ImageFormat {
+        let mut head = vec![0; 512];
+        let n = File::open(path)
+                    .and_then(|mut f| f.read(&mut head))
+                    .expect(&format!("Failed to read {}", path));
+        head.truncate(n);
+        if head.starts_with(b"\x7fELF") {
+            return ImageFormat::Elf;
+        };
+        let text = String::from_utf8_lossy(&head);
+        let text = text.trim_left();
+        let line = text.lines().next().unwrap_or("").trim();
+        let hexdigits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_digit(16));
+        if line.starts_with(':') && hexdigits(&line[1..]) {
+            ImageFormat::IHex
+        } else if line.starts_with('S') && hexdigits(&line[1..]) {
+            ImageFormat::SRec
+        } else if (text.starts_with('(') || text.starts_with(';')) && is_memmap(path) {
+            ImageFormat::MemMap
+        } else {
+            ImageFormat::Raw
+        }
+}
+
+fn is_memmap (path: &str) -> bool {
+        let mut bytes = Vec::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .expect(&format!("Failed to read {}", path));
+        bytes.iter().all(|&b| b.is_ascii_graphic() || b.is_ascii_whitespace())
+            && read_memmap(path).is_ok()
+}
+
+pub fn resolve_format (path: &str, format: ImageFormat) -> ImageFormat {
+        match format {
+            ImageFormat::Auto => sniff_format(path),
+            f                 => f,
+        }
+}
+
+/* Bytes and the addresses they go at, with where to start, if the
+ * file says */
+pub type Chunks = (Vec<(u64, Vec<u8>)>, Option<u64>);
+
+pub fn decode_hex (s: &str) -> Option<Vec<u8>> {
+        if s.len() % 2 != 0 || !s.is_ascii() {
+            return None;
+        }
+        (0..s.len()/2).map(|i| u8::from_str_radix(&s[2*i..2*i+2], 16).ok())
+                      .collect()
+}
+
+fn be_uint (bytes: &[u8]) -> u64 {
+        bytes.iter().fold(0, |a, &b| (a << 8) | b as u64)
+}
+
+/* :LLAAAATT<data>CC, where 02 and 04 records set the upper bits
+ * of the addresses that follow, and 03 and 05 give the entry point */
+pub fn read_ihex (text: &str) -> result::Result<Chunks, String> {
+        let mut chunks = Vec::new();
+        let mut entry  = None;
+        let mut upper : u64 = 0;
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() { continue };
+            let err = |what: &str| format!("line {}: {}", n + 1, what);
+            if !line.starts_with(':') {
+                return Err(err("expected a record starting with :"));
+            }
+            let b = decode_hex(&line[1..]).ok_or(err("bad hex"))?;
+            if b.len() < 5 || b.len() != b[0] as usize + 5 {
+                return Err(err("wrong record length"));
+            }
+            if b.iter().fold(0u8, |a, &x| a.wrapping_add(x)) != 0 {
+                return Err(err("bad checksum"));
+            }
+            let addr = be_uint(&b[1..3]);
+            let data = &b[4..b.len()-1];
+            let want = match b[3] { 0x02 | 0x04 => 2, 0x03 | 0x05 => 4, _ => data.len() };
+            if data.len() != want {
+                return Err(err("wrong length for the record type"));
+            }
+            match b[3] {
+                0x00 => chunks.push((upper + addr, data.to_vec())),
+                0x01 => break,
+                0x02 => upper = be_uint(data) << 4,
+                0x03 => entry = Some((be_uint(&data[..2]) << 4) + be_uint(&data[2..])),
+                0x04 => upper = be_uint(data) << 16,
+                0x05 => entry = Some(be_uint(data)),
+                t    => return Err(err(&format!("unknown record type {:02x}", t))),
+            }
+        }
+        Ok((chunks, entry))
+}
+
+/* S<t><count><address><data><checksum>, where the record type
+ * gives the width of the address: S1, S2 and S3 carry data, S9, S8
+ * and S7 the entry point, and the rest we can pass over */
+pub fn read_srec (text: &str) -> result::Result<Chunks, String> {
+        let mut chunks = Vec::new();
+        let mut entry  = None;
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() { continue };
+            let err = |what: &str| format!("line {}: {}", n + 1, what);
+            if !line.starts_with('S') || line.len() < 2 || !line.is_ascii() {
+                return Err(err("expected a record starting with S"));
+            }
+            let t = line[1..2].parse::<u8>().map_err(|_| err("bad record type"))?;
+            let b = decode_hex(&line[2..]).ok_or(err("bad hex"))?;
+            if b.is_empty() || b.len() != b[0] as usize + 1 {
+                return Err(err("wrong record length"));
+            }
+            if b.iter().fold(0u8, |a, &x| a.wrapping_add(x)) != 0xFF {
+                return Err(err("bad checksum"));
+            }
+            let alen = match t {
+                0 | 1 | 5 | 9 => 2,
+                2 | 6 | 8     => 3,
+                3 | 7         => 4,
+                _             => return Err(err(&format!("unknown record type S{}", t))),
+            };
+            if b.len() < alen + 2 {
+                return Err(err("record too short for its address"));
+            }
+            let addr = be_uint(&b[1..1+alen]);
+            let data = &b[1+alen..b.len()-1];
+            match t {
+                1 | 2 | 3 => chunks.push((addr, data.to_vec())),
+                7 | 8 | 9 => entry = Some(addr),
+                _         => (),
+            }
+        }
+        Ok((chunks, entry))
+}
+
+fn read_text (path: &str) -> String {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .expect(&format!("Failed to read {}", path));
+        text
+}
+
+pub fn read_chunks (path: &str, format: ImageFormat) -> Chunks {
+        let res = match format {
+            ImageFormat::IHex => read_ihex(&read_text(path)),
+            ImageFormat::SRec => read_srec(&read_text(path)),
+            f => panic!("{} isn't a HEX or S-record file, but {:?}", path, f),
+        };
+        res.unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e))
+}
+
+/* Records come in whatever order, and usually a line's worth at a
+ * time. Runs that would land on the same page are merged, with
+ * zeroes in any gap, since each segment is mapped on its own. */
+pub fn chunks_to_segments (chunks: &Vec<(u64, Vec<u8>)>, perm: Protection) -> Vec<Seg> {
+        let mut sorted = chunks.clone();
+        sorted.sort_by_key(|&(a, _)| a);
+        let mut segs : Vec<Seg> = Vec::new();
+        for (addr, data) in sorted {
+            if let Some(seg) = segs.last_mut() {
+                if addr < seg.floor() + seg.size() as u64 {
+                    let off = (addr - seg.addr) as usize;
+                    if seg.data.len() < off + data.len() {
+                        seg.data.resize(off + data.len(), 0);
+                    };
+                    seg.data[off..off + data.len()].copy_from_slice(&data);
+                    seg.memsz = seg.data.len();
+                    continue;
+                };
+            };
+            segs.push(Seg {
+                addr:    addr,
+                memsz:   data.len(),
+                perm:    perm,
+                segtype: SegType::Load,
+                data:    data,
+            });
+        }
+        segs
+}
+
+pub fn parse_perms (s: &str) -> Option<Protection> {
+        let mut p = PROT_NONE;
+        for c in s.chars() {
+            match c {
+                'r' => p |= PROT_READ,
+                'w' => p |= PROT_WRITE,
+                'x' => p |= PROT_EXEC,
+                '-' => (),
+                _   => return None,
+            }
+        }
+        Some(p)
+}
+
+fn file_name (path: &str) -> String {
+        Path::new(path).file_name()
+                       .map(|n| n.to_string_lossy().to_string())
+                       .unwrap_or(path.to_string())
+}
+
+/* Each segment of an image without section headers gets a section
+ * of the same name, so that it shows up as a code region by name. */
+fn image_object (path: &str,
+                 bias: u64,
+                 segments: Vec<Seg>,
+                 names: Vec<String>,
+                 entry: Option<u64>,
+                 word_size: usize,
+                 endian: Endian) -> ElfObject {
+        let sections = segments.iter()
+                               .zip(names.into_iter())
+                               .map(|(g, name)| Sec {
+                                   name:  name,
+                                   addr:  g.addr,
+                                   data:  g.data.clone(),
+                                   perm:  g.perm,
+                                   alloc: true,
+                               })
+                               .collect();
+        let module = Module::new(&file_name(path), path, bias, &segments);
+        ElfObject {
+            module:    module,
+            sections:  sections,
+            segments:  segments,
+            imports:   Vec::new(),
+            exports:   HashMap::new(),
+            entry:     entry,
+            word_size: word_size,
+            endian:    endian,
+        }
+}
+
+/* Flash, as it is, at base. Firmware mixes code and data freely,
+ * so all of it is taken to be executable. */
+pub fn load_raw_image (path: &str,
+                       base: u64,
+                       entry: Option<u64>,
+                       word_size: usize,
+                       endian: Endian) -> ElfObject {
+        let data = load_file(path);
+        println!("[*] Loading raw image {} at {:08x}, {} bytes", path, base, data.len());
+        let seg = Seg {
+            addr:    base,
+            memsz:   data.len(),
+            perm:    PROT_READ|PROT_EXEC,
+            segtype: SegType::Load,
+            data:    data,
+        };
+        image_object(path, base, vec![seg], vec![".raw".to_string()],
+                     entry.or(Some(base)), word_size, endian)
+}
+
+pub fn load_hex_image (path: &str,
+                       format: ImageFormat,
+                       entry: Option<u64>,
+                       word_size: usize,
+                       endian: Endian) -> ElfObject {
+        let (chunks, file_entry) = read_chunks(path, format);
+        let segs = chunks_to_segments(&chunks, PROT_READ|PROT_EXEC);
+        println!("[*] Loading {:?} image {}: {} records in {} segments",
+                 format, path, chunks.len(), segs.len());
+        let names = segs.iter().map(|g| format!(".hex@{:08x}", g.addr)).collect();
+        image_object(path, 0, segs, names, entry.or(file_entry), word_size, endian)
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct MapRegion {
+        pub name:   String,
+        pub addr:   Option<u64>,
+        pub file:   Option<String>,
+        pub offset: usize,
+        pub size:   Option<usize>,
+        pub perm:   Protection,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct MemMap {
+        pub regions: Vec<MapRegion>,
+        pub entry:   Option<u64>,
+        pub mode:    Option<MachineMode>,
+}
+
+fn map_value <T: FromSexp> (form: &Sexp, key: &str, v: &Sexp) -> result::Result<T, String> {
+        T::from_sexp(v).ok_or(format!("bad value for {} in {}", key, form))
+}
+
+fn read_region (form: &Sexp, args: &[Sexp], dir: &Path) -> result::Result<MapRegion, String> {
+        let mut r = MapRegion {
+            name:   String::new(),
+            addr:   None,
+            file:   None,
+            offset: 0,
+            size:   None,
+            perm:   PROT_NONE,
+        };
+        let mut perms : Option<String> = None;
+        if args.len() % 2 != 0 {
+            return Err(format!("expected :key value pairs in {}", form));
+        }
+        for kv in args.chunks(2) {
+            let key = match kv[0] {
+                Sexp::Atom(ref k) => k.trim_left_matches(':').to_lowercase(),
+                _ => return Err(format!("expected a :key in {}", form)),
+            };
+            let v = &kv[1];
+            match key.as_str() {
+                "name"   => r.name   = map_value(form, &key, v)?,
+                "addr"   => r.addr   = Some(map_value(form, &key, v)?),
+                "offset" => r.offset = map_value(form, &key, v)?,
+                "size"   => r.size   = Some(map_value(form, &key, v)?),
+                "perms"  => perms    = Some(map_value(form, &key, v)?),
+                "file"   => {
+                    let f : String = map_value(form, &key, v)?;
+                    r.file = Some(dir.join(f).to_string_lossy().to_string());
+                },
+                _ => return Err(format!("unknown key :{} in {}", key, form)),
+            }
+        }
+        r.perm = match perms {
+            Some(p) => parse_perms(&p).ok_or(format!("bad perms {:?} in {}", p, form))?,
+            None if r.file.is_some() => PROT_READ|PROT_EXEC,
+            None => PROT_READ|PROT_WRITE,
+        };
+        if r.file.is_none() && (r.addr.is_none() || r.size.is_none()) {
+            return Err(format!("a region with no :file needs :addr and :size: {}", form));
+        }
+        if r.name.is_empty() {
+            r.name = match (&r.file, r.addr) {
+                (&Some(ref f), _) => file_name(f),
+                (_, Some(a))      => format!("region@{:08x}", a),
+                _                 => "region".to_string(),
+            };
+        };
+        Ok(r)
+}
+
+pub fn read_memmap (path: &str) -> result::Result<MemMap, String> {
+        let dir = Path::new(path).parent().unwrap_or(Path::new("."));
+        let mut map = MemMap { regions: Vec::new(), entry: None, mode: None };
+        for form in read_sexps(&read_text(path))? {
+            let v = match form {
+                Sexp::List(ref v) if !v.is_empty() => v.clone(),
+                ref x => return Err(format!("expected a form, got {}", x)),
+            };
+            let head = match v[0] {
+                Sexp::Atom(ref h) => h.to_lowercase(),
+                _ => return Err(format!("expected a form, got {}", form)),
+            };
+            match (head.as_str(), v.get(1)) {
+                ("region", _)      => map.regions.push(read_region(&form, &v[1..], dir)?),
+                ("entry", Some(x)) => map.entry = Some(map_value(&form, "entry", x)?),
+                ("arch", Some(x)) |
+                ("mode", Some(x))  => map.mode = Some(map_value(&form, "arch", x)?),
+                _ => return Err(format!("unknown form {}", form)),
+            }
+        }
+        Ok(map)
+}
+
+pub fn memmap_mode (path: &str) -> Option<MachineMode> {
+        read_memmap(path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e))
+                         .mode
+}
+
+pub fn load_memmap_image (path: &str,
+                          entry: Option<u64>,
+                          word_size: usize,
+                          endian: Endian) -> ElfObject {
+        let map = read_memmap(path).unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e));
+        let mut segs  = Vec::new();
+        let mut names = Vec::new();
+        for r in &map.regions {
+            let fmt = r.file.as_ref().map(|f| sniff_format(f));
+            match (&r.file, fmt) {
+                (&Some(ref f), Some(ImageFormat::IHex)) |
+                (&Some(ref f), Some(ImageFormat::SRec)) => {
+                    if r.addr.is_some() {
+                        panic!("{}: region {} is in {}, which gives its own addresses",
+                               path, r.name, f);
+                    };
+                    let (chunks, _) = read_chunks(f, fmt.unwrap());
+                    let rsegs = chunks_to_segments(&chunks, r.perm);
+                    for (j, g) in rsegs.into_iter().enumerate() {
+                        names.push(if j == 0 { r.name.clone() }
+                                   else { format!("{}.{}", r.name, j) });
+                        segs.push(g);
+                    }
+                },
+                (&Some(_), Some(ImageFormat::Elf)) =>
+                    panic!("{}: region {} is an ELF file; load it with --lib instead",
+                           path, r.name),
+                (&Some(ref f), _) => {
+                    let bytes = load_file(f);
+                    let lo    = r.offset.min(bytes.len());
+                    let size  = r.size.unwrap_or(bytes.len() - lo);
+                    let hi    = (lo + size).min(bytes.len());
+                    segs.push(Seg {
+                        addr:    r.addr.expect(&format!("{}: region {} needs an :addr",
+                                                        path, r.name)),
+                        memsz:   size,
+                        perm:    r.perm,
+                        segtype: SegType::Load,
+                        data:    bytes[lo..hi].to_vec(),
+                    });
+                    names.push(r.name.clone());
+                },
+                (&None, _) => {
+                    segs.push(Seg {
+                        addr:    r.addr.unwrap(),
+                        memsz:   r.size.unwrap(),
+                        perm:    r.perm,
+                        segtype: SegType::Load,
+                        data:    Vec::new(),
+                    });
+                    names.push(r.name.clone());
+                },
+            }
+        }
+        for (g, n) in segs.iter().zip(names.iter()) {
+            println!("[*] Region {} at {:08x} -- {:08x}, {:?}",
+                     n, g.addr, g.addr + g.memsz as u64, g.perm);
+        }
+        image_object(path, 0, segs, names, entry.or(map.entry), word_size, endian)
+}
+
+#[cfg(test)]
+mod tests {
+        use std::env;
+        use std::fs;
+        use std::process;
+        use super::*;
+
+        fn sniff (name: &str, bytes: &[u8]) -> ImageFormat {
+            let path = env::temp_dir()
+                           .join(format!("roper-sniff-{}-{}", process::id(), name))
+                           .to_string_lossy()
+                           .into_owned();
+            fs::write(&path, bytes).unwrap();
+            let f = sniff_format(&path);
+            fs::remove_file(&path).unwrap();
+            f
+        }
+
+        #[test]
+        fn sniffs_formats () {
+            assert_eq!(sniff("elf", b"\x7fELF\x01\x01\x01"), ImageFormat::Elf);
+            assert_eq!(sniff("hex", b":00000001FF\n"), ImageFormat::IHex);
+            assert_eq!(sniff("srec", b"S9031000EC\r\n"), ImageFormat::SRec);
+            assert_eq!(sniff("map", b";; flash\n(region :addr #x0 :size #x1000)\n"),
+                       ImageFormat::MemMap);
+            /* b with an offset of 0x28, then more code */
+            assert_eq!(sniff("arm", b"\x28\x00\x00\xea\x3b\x00\x00\xea"), ImageFormat::Raw);
+            assert_eq!(sniff("text", b"(not a map"), ImageFormat::Raw);
+            assert_eq!(sniff("forms", b"(frobnicate 1)"), ImageFormat::Raw);
+        }
+
+        #[test]
+        fn reads_ihex () {
+            let (chunks, entry) = read_ihex("
+                :0400000001020304F2
+                :020000040800F2
+                :0200100041426B
+                :0400000508000101ED
+                :00000001FF
+                :0200000041428B").unwrap();
+            assert_eq!(chunks, vec![(0x0, vec![1, 2, 3, 4]),
+                                    (0x08000010, vec![0x41, 0x42])]);
+            assert_eq!(entry, Some(0x08000101));
+        }
+
+        #[test]
+        fn reads_ihex_segment_records () {
+            let (chunks, entry) = read_ihex(":020000021000EC\n\
+                                             :0200100041426B\n\
+                                             :0400000310000004E5\n").unwrap();
+            assert_eq!(chunks, vec![(0x10010, vec![0x41, 0x42])]);
+            assert_eq!(entry, Some(0x10004));
+        }
+
+        #[test]
+        fn rejects_bad_ihex () {
+            assert_eq!(read_ihex(":0400000001020304F3"),
+                       Err("line 1: bad checksum".to_string()));
+            assert_eq!(read_ihex(":0200100041426B\n:0500000001020304F2"),
+                       Err("line 2: wrong record length".to_string()));
+            assert_eq!(read_ihex("0400000001020304F2"),
+                       Err("line 1: expected a record starting with :".to_string()));
+            assert_eq!(read_ihex(":04000000010203G4F2"),
+                       Err("line 1: bad hex".to_string()));
+            assert!(read_ihex(":0100000408F3").is_err());
+        }
+
+        #[test]
+        fn reads_srec () {
+            let (chunks, entry) = read_srec("S0060000686472BB\r\n\
+                                             S1061000010203E3\r\n\
+                                             S30708000000AABB8B\r\n\
+                                             S5030002FA\r\n\
+                                             S70508000101F0\r\n").unwrap();
+            assert_eq!(chunks, vec![(0x1000, vec![1, 2, 3]),
+                                    (0x08000000, vec![0xaa, 0xbb])]);
+            assert_eq!(entry, Some(0x08000101));
+            assert_eq!(read_srec("S9031000EC").unwrap(), (vec![], Some(0x1000)));
+        }
+
+        #[test]
+        fn rejects_bad_srec () {
+            assert_eq!(read_srec("S1061000010203E4"),
+                       Err("line 1: bad checksum".to_string()));
+            assert_eq!(read_srec("S1071000010203E3"),
+                       Err("line 1: wrong record length".to_string()));
+            assert_eq!(read_srec("S4030002FA"),
+                       Err("line 1: unknown record type S4".to_string()));
+            assert_eq!(read_srec(":0400000001020304F2"),
+                       Err("line 1: expected a record starting with S".to_string()));
+        }
+}
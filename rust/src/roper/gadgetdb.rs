@@ -0,0 +1,300 @@
+// A cache of harvested and profiled gadgets, so that we don't have
+// to reap and profile large binaries all over again every run. It
+// lives next to the binary, as JSON, and is keyed by a hash of the
+// binary's contents together with the reaper settings. If either
+// changes, the key won't match and the database gets rebuilt.
+extern crate rustc_serialize;
+
+use std::fs::{File,OpenOptions};
+use std::io::prelude::*;
+use std::sync::Arc;
+use std::collections::BTreeMap;
+use self::rustc_serialize::json::{Json, ToJson};
+
+use roper::phylostructs::*;
+use roper::ontostructs::*;
+use roper::profiler::*;
+use roper::arm::CONDITIONS;
+use roper::firmware::resolve_format;
+
+macro_rules! try_opt {
+        ($e:expr) => (match $e { Some(x) => x, None => return None })
+}
+
+/* Bump this whenever the reapers or the profiler change in a way
+ * that would give different gadgets for the same binary. */
+pub const GADGET_DB_VERSION : u32 = 4;
+
+pub const FNV_OFFSET : u64 = 0xcbf29ce484222325;
+const FNV_PRIME  : u64 = 0x100000001b3;
+
+pub fn fnv1a (bytes: &[u8], h: u64) -> u64 {
+        bytes.iter().fold(h, |h, &b| (h ^ b as u64).wrapping_mul(FNV_PRIME))
+}
+
+fn reaper_settings (params: &Params) -> String {
+        let modes : Vec<String> = params.mode.harvest_modes()
+                                             .iter()
+                                             .map(|m| format!("{:?}", m))
+                                             .collect();
+        format!("version={};modes={};profile_trials={}",
+                GADGET_DB_VERSION, modes.join(","), PROFILE_TRIALS)
+}
+
+fn hash_file (path: &str, h: u64) -> u64 {
+        let mut bin = Vec::new();
+        File::open(path)
+             .and_then(|mut f| f.read_to_end(&mut bin))
+             .expect(&format!("Failed to read {} to hash", path));
+        fnv1a(&bin, h)
+}
+
+/* Gadget addresses depend on how and where everything is loaded,
+ * and the ooze on which libraries are loaded with the binary. */
+pub fn gadget_db_key (params: &Params) -> String {
+        let mut h = hash_file(&params.binary_path, FNV_OFFSET);
+        h = fnv1a(format!("{:?}@{:x}",
+                          resolve_format(&params.binary_path, params.image_format),
+                          params.load_base).as_bytes(), h);
+        for &(ref path, base) in &params.libraries {
+            h = hash_file(path, h);
+            h = fnv1a(format!("@{:x}", base).as_bytes(), h);
+        }
+        let h = fnv1a(reaper_settings(params).as_bytes(), h);
+        format!("{:016x}", h)
+}
+
+pub fn gadget_db_path (params: &Params) -> String {
+        format!("{}_gadgets.json", &params.binary_path)
+}
+
+/* JSON encodings. Effects and accesses are tagged arrays, which
+ * keeps the file small enough to eyeball. */
+
+fn effect_to_json (e: &Effect) -> Json {
+        let v : Vec<Json> = match *e {
+            Effect::Load(d,k)       => vec!["load".to_json(), d.to_json(), k.to_json()],
+            Effect::Copy(d,s)       => vec!["copy".to_json(), d.to_json(), s.to_json()],
+            Effect::Const(d,c)      => vec!["const".to_json(), d.to_json(), c.to_json()],
+            Effect::AddImm(d,s,c)   => vec!["addimm".to_json(), d.to_json(),
+                                            s.to_json(), c.to_json()],
+            Effect::Arith(d,a,op,b) => vec!["arith".to_json(), d.to_json(), a.to_json(),
+                                            format!("{:?}",op).to_json(), b.to_json()],
+            Effect::Clobber(d)      => vec!["clobber".to_json(), d.to_json()],
+        };
+        Json::Array(v)
+}
+
+fn op_from_str (s: &str) -> Option<Op> {
+        match s {
+            "Add" => Some(Op::Add),
+            "Sub" => Some(Op::Sub),
+            "Xor" => Some(Op::Xor),
+            "And" => Some(Op::And),
+            "Orr" => Some(Op::Orr),
+            "Mul" => Some(Op::Mul),
+            _     => None,
+        }
+}
+
+fn effect_from_json (j: &Json) -> Option<Effect> {
+        let v = try_opt!(j.as_array());
+        let n = |i: usize| v.get(i).and_then(|x| x.as_u64());
+        match try_opt!(v.get(0).and_then(|x| x.as_string())) {
+            "load"    => Some(Effect::Load(try_opt!(n(1)) as usize, try_opt!(n(2)) as usize)),
+            "copy"    => Some(Effect::Copy(try_opt!(n(1)) as usize, try_opt!(n(2)) as usize)),
+            "const"   => Some(Effect::Const(try_opt!(n(1)) as usize, try_opt!(n(2)))),
+            "addimm"  => Some(Effect::AddImm(try_opt!(n(1)) as usize,
+                                             try_opt!(n(2)) as usize,
+                                             try_opt!(v.get(3).and_then(|x| x.as_i64())))),
+            "arith"   => Some(Effect::Arith(try_opt!(n(1)) as usize,
+                                            try_opt!(n(2)) as usize,
+                                            try_opt!(v.get(3)
+                                                      .and_then(|x| x.as_string())
+                                                      .and_then(op_from_str)),
+                                            try_opt!(n(4)) as usize)),
+            "clobber" => Some(Effect::Clobber(try_opt!(n(1)) as usize)),
+            _         => None,
+        }
+}
+
+fn access_to_json (a: &Access) -> Json {
+        Json::Array(match *a {
+            Access::Rel(r,o) => vec!["rel".to_json(), r.to_json(), o.to_json()],
+            Access::Abs(x)   => vec!["abs".to_json(), x.to_json()],
+            Access::Wild     => vec!["wild".to_json()],
+        })
+}
+
+fn access_from_json (j: &Json) -> Option<Access> {
+        let v = try_opt!(j.as_array());
+        match try_opt!(v.get(0).and_then(|x| x.as_string())) {
+            "rel"  => Some(Access::Rel(try_opt!(v.get(1).and_then(|x| x.as_u64())) as usize,
+                                       try_opt!(v.get(2).and_then(|x| x.as_i64())) as i32)),
+            "abs"  => Some(Access::Abs(try_opt!(v.get(1).and_then(|x| x.as_u64())))),
+            "wild" => Some(Access::Wild),
+            _      => None,
+        }
+}
+
+fn profile_to_json (p: &Profile) -> Json {
+        let mut b = BTreeMap::new();
+        b.insert("effects".to_string(),
+                 Json::Array(p.effects.iter().map(effect_to_json).collect()));
+        b.insert("reads".to_string(), p.reads.to_json());
+        b.insert("writes".to_string(), p.writes.to_json());
+        b.insert("mem_reads".to_string(),
+                 Json::Array(p.mem_reads.iter().map(access_to_json).collect()));
+        b.insert("mem_writes".to_string(),
+                 Json::Array(p.mem_writes.iter().map(access_to_json).collect()));
+        b.insert("sp_delta".to_string(), p.sp_delta.to_json());
+        b.insert("crash_rate".to_string(), p.crash_rate.to_json());
+        Json::Object(b)
+}
+
+fn usizes (j: Option<&Json>) -> Option<Vec<usize>> {
+        try_opt!(j.and_then(|x| x.as_array()))
+            .iter()
+            .map(|x| x.as_u64().map(|n| n as usize))
+            .collect()
+}
+
+/* The profile's mode is its clump's, so it isn't stored twice */
+fn profile_from_json (j: &Json, mode: MachineMode) -> Option<Profile> {
+        let list = |k: &str| j.find(k).and_then(|x| x.as_array());
+        Some(Profile {
+            effects    : try_opt!(try_opt!(list("effects")).iter()
+                                                          .map(effect_from_json)
+                                                          .collect()),
+            reads      : try_opt!(usizes(j.find("reads"))),
+            writes     : try_opt!(usizes(j.find("writes"))),
+            mem_reads  : try_opt!(try_opt!(list("mem_reads")).iter()
+                                                             .map(access_from_json)
+                                                             .collect()),
+            mem_writes : try_opt!(try_opt!(list("mem_writes")).iter()
+                                                              .map(access_from_json)
+                                                              .collect()),
+            sp_delta   : j.find("sp_delta").and_then(|x| x.as_i64()).map(|x| x as i32),
+            crash_rate : try_opt!(j.find("crash_rate").and_then(|x| x.as_f64())) as f32,
+            mode       : mode,
+        })
+}
+
+/* Inverts the Debug formatting that clump_to_json uses */
+fn kind_from_str (s: &str) -> Option<GadgetKind> {
+        match s {
+            "Ret"     => return Some(GadgetKind::Ret),
+            "JumpReg" => return Some(GadgetKind::JumpReg),
+            "CallReg" => return Some(GadgetKind::CallReg),
+            _         => (),
+        }
+        if !s.starts_with("CondRet(") { return None };
+        let c = s.trim_left_matches("CondRet(").trim_right_matches(')');
+        CONDITIONS.iter()
+                  .find(|x| format!("{:?}", x) == c)
+                  .map(|&x| GadgetKind::CondRet(x))
+}
+
+/* Only what the reapers and profiler fill in. Everything else is
+ * evolutionary state, and starts out at its default. */
+fn clump_to_json (c: &Clump) -> Json {
+        let mut b = BTreeMap::new();
+        b.insert("entry".to_string(), c.words[0].to_json());
+        b.insert("ret_addr".to_string(), c.ret_addr.to_json());
+        b.insert("sp_delta".to_string(), c.sp_delta.to_json());
+        b.insert("ret_offset".to_string(), c.ret_offset.to_json());
+        b.insert("exchange".to_string(), c.exchange.to_json());
+        b.insert("mode".to_string(), format!("{:?}",c.mode).to_json());
+        b.insert("kind".to_string(), format!("{:?}",c.kind).to_json());
+        b.insert("section".to_string(), c.section.to_json());
+        b.insert("module".to_string(), c.module.to_json());
+        b.insert("flags_needed".to_string(), c.flags_needed.to_json());
+        b.insert("flags_set".to_string(), c.flags_set.to_json());
+        if let Some(ref p) = c.profile {
+            b.insert("profile".to_string(), profile_to_json(p));
+        }
+        Json::Object(b)
+}
+
+fn clump_from_json (j: &Json) -> Option<Clump> {
+        let n = |k: &str| j.find(k).and_then(|x| x.as_u64());
+        let s = |k: &str| j.find(k).and_then(|x| x.as_string());
+        let mode = match try_opt!(s("mode")) {
+            "ARM"     => MachineMode::ARM,
+            "THUMB"   => MachineMode::THUMB,
+            "AARCH64" => MachineMode::AARCH64,
+            "MIPS"    => MachineMode::MIPS,
+            "MIPSEL"  => MachineMode::MIPSEL,
+            _         => return None,
+        };
+        Some(Clump {
+            words      : vec![try_opt!(n("entry"))],
+            ret_addr   : try_opt!(n("ret_addr")),
+            sp_delta   : try_opt!(j.find("sp_delta").and_then(|x| x.as_i64())) as i32,
+            ret_offset : try_opt!(n("ret_offset")) as usize,
+            exchange   : try_opt!(j.find("exchange").and_then(|x| x.as_boolean())),
+            mode       : mode,
+            kind       : try_opt!(kind_from_str(try_opt!(s("kind")))),
+            flags_needed : try_opt!(n("flags_needed")) as u8,
+            flags_set  : try_opt!(n("flags_set")) as u8,
+            section    : try_opt!(s("section")).to_string(),
+            module     : try_opt!(s("module")).to_string(),
+            profile    : match j.find("profile") {
+                Some(p) => Some(Arc::new(try_opt!(profile_from_json(p, mode)))),
+                None    => None,
+            },
+            ..Default::default()
+        })
+}
+
+pub fn save_gadget_db (params: &Params, clumps: &Vec<Clump>) {
+        let mut b = BTreeMap::new();
+        b.insert("key".to_string(), gadget_db_key(params).to_json());
+        b.insert("settings".to_string(), reaper_settings(params).to_json());
+        b.insert("binary".to_string(), params.binary_path.to_json());
+        b.insert("clumps".to_string(),
+                 Json::Array(clumps.iter().map(clump_to_json).collect()));
+        let path = gadget_db_path(params);
+        let mut file = OpenOptions::new()
+                                   .truncate(true)
+                                   .write(true)
+                                   .create(true)
+                                   .open(&path)
+                                   .expect("Failed to open gadget database for writing");
+        file.write_all(Json::Object(b).to_string().as_bytes())
+            .expect("Failed to write gadget database");
+        println!("[*] Saved {} gadgets to {}", clumps.len(), path);
+}
+
+/* None if there's no database, or if it's stale or unreadable. */
+pub fn load_gadget_db (params: &Params) -> Option<Vec<Clump>> {
+        let path = gadget_db_path(params);
+        let mut text = String::new();
+        if File::open(&path)
+                .and_then(|mut f| f.read_to_string(&mut text))
+                .is_err() {
+            return None;
+        }
+        let j = match Json::from_str(&text) {
+            Ok(j)  => j,
+            Err(e) => {
+                println!("[!] Couldn't parse gadget database {}: {:?}", path, e);
+                return None;
+            },
+        };
+        if j.find("key").and_then(|x| x.as_string()).map(|k| k.to_string())
+            != Some(gadget_db_key(params)) {
+            println!("[*] Gadget database {} is stale. Rebuilding.", path);
+            return None;
+        }
+        try_opt!(j.find("clumps").and_then(|x| x.as_array()))
+            .iter()
+            .map(clump_from_json)
+            .collect()
+}
+
+pub fn build_gadget_db (params: &Params, engine: &mut Engine) -> Vec<Clump> {
+        let clumps = harvest_gadgets(params, engine);
+        save_gadget_db(params, &clumps);
+        clumps
+}
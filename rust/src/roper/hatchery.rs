@@ -2,7 +2,6 @@
 extern crate unicorn;
 extern crate elf;
 
-use rand::{Rng,thread_rng};
 use std::time::Instant;
 use std::process::exit;
 use std::collections::HashSet;
@@ -17,36 +16,46 @@ use std::io::prelude::*;
 use elf::*;
 use unicorn::*; //{Cpu, CpuARM, uc_handle};
 use roper::util::{disas,
+                  get_word,
+                  get_word_endian,
                   get_word32le,
                   get_word16le,
                   hexvec,
+                  pack_word,
+                  pack_word_endian,
                   pack_word32le};
-use roper::phylostructs::{Chain,MachineMode};
+use roper::phylostructs::{Chain,Endian};
 use std::fmt::{Display,format,Formatter,Result};
 use roper::ontostructs::*;
+use roper::isa::Isa;
+use roper::gadgetdb::{fnv1a,FNV_OFFSET};
 
-pub fn read_registers (uc: &unicorn::Unicorn) -> Vec<u32> {
-    REGISTERS.iter().map(|&x| uc.reg_read(x.to_i32())
-                                .expect("Error reading reg")
-                                as u32)
-                    .collect()
+pub fn read_registers <A: Isa> (uc: &unicorn::Unicorn, mode: A) -> Vec<u64> {
+    mode.read_registers(uc)
 }
 
-pub fn set_registers (uc: &unicorn::Unicorn, 
-                      input: &Vec<i32>,
-                      inregs: &Vec<usize>,
-                      reset: bool) {
+/* Inputs are 32-bit. On AArch64 they go in zero-extended, as if
+ * written to the W registers. */
+/* On reset, registers that take no input go back to start, if
+ * there is one, or else to zero */
+pub fn set_registers <A: Isa> (uc: &unicorn::Unicorn, 
+                                mode: A,
+                                input: &Vec<i32>,
+                                inregs: &Vec<usize>,
+                                start: Option<&Vec<u64>>,
+                                reset: bool) {
     let mut in_ptr = 0;
     //println!("in set_registers. input: {:?}, inregs: {:?}", input, inregs);
     //exit(99);
-    for i in 0..REGISTERS.len() {
+    for i in 0..mode.num_registers() {
         if in_ptr < inregs.len() && i == inregs[in_ptr] { 
             in_ptr += 1;
             let val = input[in_ptr-1];
-            uc.reg_write_i32(REGISTERS[i].to_i32(), val).unwrap();
+            mode.write_register(uc, i, val as u32 as u64);
         } else { 
             if reset {
-                uc.reg_write_i32(REGISTERS[i].to_i32(), 0).unwrap();
+                let val = start.and_then(|s| s.get(i).cloned()).unwrap_or(0);
+                mode.write_register(uc, i, val);
             }
         };
     }
@@ -59,15 +68,17 @@ pub fn set_registers (uc: &unicorn::Unicorn,
   * (out of date, but seems to work ok, so let's not break it.)
   */
 
-pub fn add_debug_hooks (uc: &mut unicorn::CpuARM) {
+pub fn add_debug_hooks <A: Isa> (uc: &mut Emu<A>) {
     if _DEBUG {
         println!("Adding hooks...");
+        let mode = uc.mode;
+        let (hook_base, hook_end) = (uc.settings().hook_base, uc.settings().hook_end());
         let callback_c = 
         // add some hooks if in debugging mode
         uc.add_code_hook(CodeHookType::CODE,
-                         BASE_ADDR,
-                         BASE_ADDR+(MEM_SIZE as u64),
-                         debug_hook)
+                         hook_base,
+                         hook_end,
+                         move |u, addr, size| debug_hook(u, mode, addr, size))
             .expect("Error adding code hook");
     }
 }
@@ -88,7 +99,26 @@ fn mk_zerostack(n: usize) -> Vec<u8>
     z
 }
 
-pub fn hatch_chain <'u,'s> (uc: &mut unicorn::CpuARM, 
+/* Where hatch_chain writes the packed chain: payload_offset bytes
+ * into the stack if we're given one, or else smack in the middle */
+pub fn stack_entry (stack: &MemRegion, payload_offset: Option<usize>) -> u64 {
+    match payload_offset {
+        Some(off) => (stack.begin + off as u64).min(stack.end),
+        None      => stack.begin + ((stack.end - stack.begin) / 2),
+    }
+}
+
+/* The stack as it stands before each run: stack_fill over and over,
+ * or zeroes if there isn't any */
+fn fill_stack (fill: &Vec<u8>, n: usize) -> Vec<u8> {
+    if fill.is_empty() {
+        mk_zerostack(n)
+    } else {
+        fill.iter().cloned().cycle().take(n).collect()
+    }
+}
+
+pub fn hatch_chain <A: Isa> (uc: &mut Emu<A>, 
                             chain: &Chain,
                             input: &Vec<i32>,
                             inregs:  &Vec<usize>,
@@ -104,7 +134,7 @@ pub fn hatch_chain <'u,'s> (uc: &mut unicorn::CpuARM,
       */
     //println!("in hatch_chain: input: {:?}, inregs: {:?}", input, inregs);
     let mut packed = chain.pack();
-    let stack : MemRegion = find_stack(&uc);
+    let stack : MemRegion = uc.stack();
     
     /* save writeable regions so that they can be restored */
     let mut saved_regions = Vec::new();
@@ -125,47 +155,65 @@ pub fn hatch_chain <'u,'s> (uc: &mut unicorn::CpuARM,
     }
     // refactor ?
     let il = input.len();
+    let ws = uc.word_size();
     for &(off, inp) in chain.input_slots.iter() {
-        let byte_offset = off * 4;
-        let input_value = pack_word32le(input[inp % il] as u32);
-        if byte_offset + 4 <= packed.len() {
-            for i in 0..4 { 
+        let byte_offset = off * ws;
+        let input_value = pack_word_endian(input[inp % il] as u32 as u64, ws, uc.endian());
+        if byte_offset + ws <= packed.len() {
+            for i in 0..ws { 
                 packed[byte_offset+i] = input_value[i]; 
             }
         } else {
             break;
         }
     }
-    let stack_space = (stack.end - stack.begin) as usize;
-    let stack_entry = stack.begin + (stack_space / 2) as u64; /* smack in the middle */
-    if (packed.len() > stack_space) {
-        println!("[!] packed chain larger than allocated stack space. truncating.");
-        packed.truncate(stack_space-4);
+    let settings = uc.settings().clone();
+    let stack_space = (stack.end - stack.begin + 1) as usize;
+    let stack_entry = uc.payload_entry();
+    if stack_entry < stack.begin || stack_entry > stack.end {
+        println!("[!] chain entry {:08x} is off the stack. returning null HatchResult.", stack_entry);
+        return HatchResult::new();
+    }
+    let room = (stack.end + 1 - stack_entry) as usize;
+    if (packed.len() > room) {
+        println!("[!] packed chain larger than the stack space above its entry. truncating.");
+        packed.truncate(room - (room % ws));
+    }
+    if packed.is_empty() {
+        println!("[X] no room on the stack for the chain. returning null HatchResult.");
+        return HatchResult::new();
     }
 
-    set_registers(uc.emu(), &input, &inregs, reset);
+    set_registers(uc.emu(), uc.mode, &input, &inregs, uc.start_regs(), reset);
     //reset_counter(uc);
     if reset {
         /* refine: zero out all writeable memory */
-        let zerostack = vec![0; stack_space]; //mk_zerostack(STACK_SIZE);
-        uc.mem_write(stack.begin, &zerostack)
-            .expect("Error zeroing out stack");
+        match uc.stack_image() {
+            Some(image) => uc.mem_write(stack.begin, image),
+            None        => uc.mem_write(stack.begin,
+                                        &fill_stack(&settings.stack_fill, stack_space)),
+        }.expect("Error resetting stack");
     }
     uc.mem_write(stack_entry, &packed)
         .expect("Error initializing stack memory");
-    uc.reg_write(RegisterARM::SP, stack_entry+4) // pop
-        .expect("Error writing SP register");
-    let start_addr : u64 = get_word32le(&packed, 0) as u64 ; //| 1;
+    /* as though the hijacked return had popped the first word */
+    uc.mode.write_sp(uc.emu(), stack_entry + ws as u64); // pop
+    /* concatenate sets the LSB of the first word if the chain opens
+     * with a Thumb clump. Unicorn reads that bit when it writes PC, so
+     * the engine starts in the right mode whatever it was left in. */
+    let start_addr : u64 = get_word_endian(&packed, 0, ws, uc.endian());
+    let syscall_rc : Rc<RefCell<Option<Syscall>>> = Rc::new(RefCell::new(None));
+    let writes_rc : Rc<RefCell<Vec<(u64,usize)>>> = Rc::new(RefCell::new(Vec::new()));
     let mut visitor = Vec::new();
-    let visitor_rc : Rc<RefCell<Vec<u32>>> = Rc::new(RefCell::new(visitor));
+    let visitor_rc : Rc<RefCell<Vec<u64>>> = Rc::new(RefCell::new(visitor));
     let ee = {
         /* this will be a bit slower, but let us track which addrs are visited */
         // first, let's try to get the counter hook working right.
         // the way it's set up now is ludicrous
-        let vis : Rc<RefCell<Vec<u32>>> = visitor_rc.clone();
+        let vis : Rc<RefCell<Vec<u64>>> = visitor_rc.clone();
         let callback = move |_: &unicorn::Unicorn, addr: u64, _: u32| {
-            let mut v : RefMut<Vec<u32>> = vis.borrow_mut();
-            v.push(addr as u32);
+            let mut v : RefMut<Vec<u64>> = vis.borrow_mut();
+            v.push(addr);
         };
         let _callback =  |u: &unicorn::Unicorn, addr: u64, size: u32| {
             println!("{:?} -- visiting {:08x}", thread::current().id(), addr);
@@ -173,15 +221,53 @@ pub fn hatch_chain <'u,'s> (uc: &mut unicorn::CpuARM,
         // hook all the things
         let mut hooks = Vec::new();
         let h = uc.add_code_hook(unicorn::CodeHookType::CODE, 
-                                 BASE_ADDR,
-                                 BASE_ADDR+(MEM_SIZE as u64),
+                                 settings.hook_base,
+                                 settings.hook_end(),
                                  callback);
         match h {
           Ok(h) => hooks.push(h),
           Err(e) => {},
         };
+        /* A syscall is as far as a chain can go, so note what it asked
+         * for and stop there. Any other interrupt stops us too, rather
+         * than letting the engine spin on it. */
+        let sys : Rc<RefCell<Option<Syscall>>> = syscall_rc.clone();
+        let mode = uc.mode;
+        let intr_callback = move |u: &unicorn::Unicorn, intno: u32| {
+            if mode.is_syscall_intr(intno) {
+                let regs = mode.read_registers(u);
+                *sys.borrow_mut() = Some(Syscall {
+                    num:  regs[mode.syscall_num_reg()],
+                    args: mode.syscall_arg_regs()
+                              .iter()
+                              .map(|&r| regs[r])
+                              .collect(),
+                    addr: regs[mode.pc_num()],
+                });
+            }
+            u.emu_stop().expect("Error stopping emulation");
+        };
+        match uc.add_intr_hook(intr_callback) {
+          Ok(h) => hooks.push(h),
+          Err(e) => {},
+        };
+        /* note where the chain writes, so we can tell afterwards
+         * what it changed */
+        let w : Rc<RefCell<Vec<(u64,usize)>>> = writes_rc.clone();
+        let write_callback = move |_: &unicorn::Unicorn, _: MemType, 
+                                   addr: u64, size: usize, _: i64| {
+            w.borrow_mut().push((addr, size));
+            true
+        };
+        match uc.add_mem_hook(MemHookType::MEM_WRITE,
+                              settings.hook_base,
+                              settings.hook_end(),
+                              write_callback) {
+          Ok(h) => hooks.push(h),
+          Err(e) => {},
+        };
         // later handle the ret counts this way too, if it works
-        let ee = uc.emu_start(start_addr, STOP_ADDR, 0, MAX_STEPS);
+        let ee = uc.emu_start(start_addr, settings.stop_addr, 0, settings.max_steps);
         for h in hooks.iter() {
             uc.remove_hook(*h);
         };
@@ -192,8 +278,8 @@ pub fn hatch_chain <'u,'s> (uc: &mut unicorn::CpuARM,
         _      => None,
     };
     let vtmp = visitor_rc.clone();
-    let visited_addrs : Vec<u32> = (vtmp.borrow()).clone().to_vec();
-    let mut visited_addr_freq : HashMap<u32, usize> = HashMap::new();
+    let visited_addrs : Vec<u64> = (vtmp.borrow()).clone().to_vec();
+    let mut visited_addr_freq : HashMap<u64, usize> = HashMap::new();
     for addr in &visited_addrs {
         *visited_addr_freq.entry(*addr).or_insert(0) += 1;
     }
@@ -206,26 +292,34 @@ pub fn hatch_chain <'u,'s> (uc: &mut unicorn::CpuARM,
         }
     }
     //println!("[*] [hatch_chain()] leaving function.\n");
-    // cast registers to Vec<u32>
-    let registers : Vec<u32> = read_registers(&(uc.emu())).iter()
-                                                          .map(|&x| x as u32)
-                                                          .collect();
+    let registers : Vec<u64> = read_registers(uc.emu(), uc.mode);
 
     // what if we added a second register vector of derefences?
     // of type Vec<Option<u32>> ?
-    let deref_size = 512; /* for starters */
+    let deref_size = settings.deref_size;
     let reg_deref : Vec<Option<Vec<u8>>> = 
                                   registers.iter()
                                            .map(|&a| deref_vec(&(uc.emu()),
                                                             a,
                                                             deref_size))
                                            .collect();
+    let mem_writes = diff_writes(uc.emu(), &saved_regions, &writes_rc.borrow());
+    /* what a memory goal wants to see, as the chain left it */
+    let mem_after : Vec<(u64,Vec<u8>)> =
+        settings.watch
+                .iter()
+                .filter_map(|&(a,n)| uc.mem_read(a, n).ok().map(|d| (a,d)))
+                .collect();
     /* RESTORE REGIONS */
     for (addr,data) in saved_regions {
         uc.mem_write(addr, &data);
     }
+    let syscall = syscall_rc.borrow().clone();
     HatchResult { registers: registers,
                   reg_deref: reg_deref,
+                  syscall: syscall,
+                  mem_writes: mem_writes,
+                  mem_after: mem_after,
     //              memdump: memdump(&uc),
                   //rwmemory:  rwmemory,
                   error: e,
@@ -236,32 +330,88 @@ pub fn hatch_chain <'u,'s> (uc: &mut unicorn::CpuARM,
     }
 }
 
-pub fn deref (uc: &unicorn::Unicorn, addr: u32) -> Option<u32> {
-    match uc.mem_read(addr as u64, 4) {
-        Ok(bytes) => Some(get_word32le(&bytes, 0)),
+/* Whatever the chain changed outside the stack, as runs of bytes
+ * that differ from the snapshot taken before it ran, in address
+ * order. Writes that put back what was already there don't count. */
+fn diff_writes (uc: &unicorn::Unicorn,
+                saved: &Vec<(u64,Vec<u8>)>,
+                writes: &Vec<(u64,usize)>) -> Vec<(u64,Vec<u8>)> {
+    let mut dirty : Vec<u64> = writes.iter()
+                                     .flat_map(|&(a,n)| a..(a + n as u64))
+                                     .collect();
+    dirty.sort();
+    dirty.dedup();
+    let mut runs : Vec<(u64,Vec<u8>)> = Vec::new();
+    for addr in dirty {
+        let old = saved.iter()
+                       .find(|&&(b, ref d)| b <= addr && addr < b + d.len() as u64)
+                       .map(|&(b, ref d)| d[(addr - b) as usize]);
+        let old = match old {
+            Some(o) => o,
+            None    => continue, /* the stack, or unmapped */
+        };
+        let new = match uc.mem_read(addr, 1) {
+            Ok(v)  => v[0],
+            Err(_) => continue,
+        };
+        if new == old { continue };
+        match runs.last_mut() {
+            Some(&mut (b, ref mut d)) if b + d.len() as u64 == addr => {
+                d.push(new);
+                continue;
+            },
+            _ => (),
+        }
+        runs.push((addr, vec![new]));
+    }
+    runs
+}
+
+pub fn deref (uc: &unicorn::Unicorn, addr: u64, width: usize, endian: Endian) 
+              -> Option<u64> {
+    match uc.mem_read(addr, width) {
+        Ok(bytes) => Some(get_word_endian(&bytes, 0, width, endian)),
         Err(_)    => None,
     }
 }
 
-pub fn deref_vec (uc: &unicorn::Unicorn, addr: u32, size: usize) -> Option<Vec<u8>> {
-    match uc.mem_read(addr as u64, size) {
+pub fn deref_vec (uc: &unicorn::Unicorn, addr: u64, size: usize) -> Option<Vec<u8>> {
+    match uc.mem_read(addr, size) {
         Ok(bytes) => Some(bytes),
         Err(_)    => None,
     }
 }
 
 
+/* A system call the chain made, and where it made it from */
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Syscall {
+    pub num  : u64,
+    pub args : Vec<u64>,
+    pub addr : u64,
+}
+
+impl Display for Syscall {
+    fn fmt (&self, f: &mut Formatter) -> Result {
+        write!(f, "syscall {:x} ({}) at {:08x}", 
+               self.num, hexvec(&self.args), self.addr)
+    }
+}
+
 type ErrorCode = f32;
 #[derive(Default,Debug,Clone)]
 pub struct HatchResult {
-    pub registers : Vec<u32>,
+    pub registers : Vec<u64>,
     pub reg_deref : Vec<Option<Vec<u8>>>,
+    pub syscall   : Option<Syscall>,
+    pub mem_writes : Vec<(u64,Vec<u8>)>,
+    pub mem_after  : Vec<(u64,Vec<u8>)>, /* settings.watch, after the run */
     //pub memdump   : Vec<(u64,Vec<u8>)>,
     pub error     : Option<ErrorCode>,
     pub counter   : usize,
     pub null      : bool,
-    pub visited_freq   : HashMap<u32,usize>,
-    pub visited   : Vec<u32>,
+    pub visited_freq   : HashMap<u64,usize>,
+    pub visited   : Vec<u64>,
 }
 
 impl HatchResult {
@@ -269,6 +419,9 @@ impl HatchResult {
         HatchResult {
             registers : Vec::new(),
             reg_deref : Vec::new(),
+            syscall   : None,
+            mem_writes : Vec::new(),
+            mem_after  : Vec::new(),
      //       memdump   : Vec::new(),
             error     : None,
             counter   : 0,
@@ -290,6 +443,9 @@ impl Display for HatchResult {
         s.push_str(&hexvec(&self.registers));
         s.push_str(&format!("\nCNT: {}", self.counter));
         s.push_str(&format!("\nERR: {:?}", self.error));
+        if let Some(ref sys) = self.syscall {
+            s.push_str(&format!("\nSYS: {}", sys));
+        }
         write!(f, "{}\n", s)
     }
 }
@@ -318,32 +474,31 @@ pub fn read_counter_u (u: &Unicorn) -> usize {
     n as usize
 }
 
-pub fn read_counter (u: &CpuARM) -> usize {
+pub fn read_counter (u: &Unicorn) -> usize {
     let v = u.mem_read(COUNTER_ADDR, 2).unwrap();
     let n : u16 = v[0] as u16; //| ((v[1] as u16) << 8);
     n as usize
 }
 
-pub fn reset_counter (u: &CpuARM) {
+pub fn reset_counter (u: &Unicorn) {
     u.mem_write(COUNTER_ADDR, &[0,0,0,0]);
 //  println!(">>>> Reset counter: {}", read_counter(u));
 }
 
-pub fn disas_addr (uc: &unicorn::CpuARM, addr: u32) -> String { // add support for thumb later
-    let addr : u64 = addr as u64;
-    let size : usize = if addr & 1 == 1 { 2 } else { 4 }; //thumb check
-    let mode : MachineMode = if addr & 1 == 1 { MachineMode::THUMB } 
-                             else { MachineMode::ARM };
-    let instv = uc.mem_read(addr, size);
+/* Pass a Thumb address with its LSB set, the way it would be
+ * packed into a chain. */
+pub fn disas_addr <A: Isa> (uc: &Emu<A>, addr: u64) -> String {
+    let mode : A = uc.mode.mode_at(addr);
+    let size : usize = mode.inst_size(uc.emu(), addr);
+    let instv = uc.mem_read(mode.code_addr(addr), size);
     match instv {
         Ok(v)  => disas(&v, mode),
         Err(_) => "unknown".to_string(),
     }
 }
 
-pub fn debug_hook (u: &unicorn::Unicorn, addr: u64, size: u32) {
-    let sp : u64 = u.reg_read(RegisterARM::SP.to_i32())
-                    .expect("Error reading SP");
+pub fn debug_hook <A: Isa> (u: &unicorn::Unicorn, mode: A, addr: u64, size: u32) {
+    let sp : u64 = mode.read_sp(u);
     let instv : Vec<u8> = u.mem_read(addr, size as usize)
                            .expect("Error reading inst.");
 //  let mut inst_str = String::new();
@@ -351,11 +506,9 @@ pub fn debug_hook (u: &unicorn::Unicorn, addr: u64, size: u32) {
 //    inst_str.push_str(&format!("{:02x} ",i));
 //  }
     //let inst = get_word32le(&instv);
-    let mo = u.query(unicorn::Query::MODE).unwrap();
-    let mmo = if mo == 0 {MachineMode::ARM} 
-                        else {MachineMode::THUMB};
+    let mmo = mode.current_mode(u);
     let dis = disas(&instv, mmo);
-    let regs = hexvec(&read_registers(u));
+    let regs = hexvec(&read_registers(u, mode));
     // write to file instead. single name. but have wrapper
     // script rename it afterwards, as a silly kludge.
     let path = "/tmp/roper_disassembly.txt";
@@ -373,7 +526,7 @@ pub fn debug_hook (u: &unicorn::Unicorn, addr: u64, size: u32) {
     //dfile.write(&row.as_bytes()).unwrap();
 }
 
-pub fn memdump (uc: &CpuARM) -> Vec<(u64,Vec<u8>)> {
+pub fn memdump (uc: &Unicorn) -> Vec<(u64,Vec<u8>)> {
     let regions = uc.mem_regions().unwrap();
     let mut data : Vec<(u64,Vec<u8>)> = Vec::new();
     let start = Instant::now();
@@ -396,8 +549,10 @@ pub fn seek_reference (bytes: &Vec<u8>, mem: &Vec<(u64,Vec<u8>)>) -> Option<u64>
         let size  = bytes.len();
         if data.len() - size <= 0 { continue };
         /* try randomizing the starting point. This will still preserve
-         * the cyclic group property */
-        let random_offset = thread_rng().gen::<usize>() % (data.len() - size);
+         * the cyclic group property. The offset is hashed from what
+         * we're looking for, so that runs stay reproducible, and the
+         * same bytes are always found in the same place. */
+        let random_offset = fnv1a(bytes, FNV_OFFSET) as usize % (data.len() - size);
         for i in 0..(data.len() - size) {
             // random offset experiment
             let i = (i + random_offset) % (data.len() - size);
@@ -428,14 +583,14 @@ pub fn seek_word (word: u32, mem: &Vec<(u64,Vec<u8>)>) -> Option<u64> {
     seek_reference(&pack_word32le(word), mem)
 }
 
-pub fn uc_seek_word (word: u32, uc: &CpuARM) -> Option<u64> {
+pub fn uc_seek_word <A: Isa> (word: u64, uc: &Emu<A>) -> Option<u64> {
     let mem = memdump(&uc);
-    seek_word(word, &mem)
+    seek_reference(&pack_word_endian(word, uc.word_size(), uc.endian()), &mem)
 }
 
 fn printable (byte: u8) -> bool { 0x20 <= byte && byte < 0x80 }
 
-pub fn dump_strings (uc: &CpuARM, minlen: usize, nullterm: bool) 
+pub fn dump_strings (uc: &Unicorn, minlen: usize, nullterm: bool) 
                     -> Vec<(u64,String)> {
     let mut strings = Vec::new();
     let mem = memdump(&uc);
@@ -0,0 +1,328 @@
+// The architecture-dependent half of the emulator harness. Emu,
+// Engine, Machinery and hatch_chain only talk to the machine through
+// this trait, and so do the reaper, the packer and the goals. The
+// trait keeps them from caring which machine it is, but it doesn't
+// make adding one a matter of implementing it: MachineMode is the
+// only implementation, and the list of machines. A new one is a new
+// MachineMode variant, with arms here, in MachineMode's own methods
+// in phylostructs, in how gadgetdb, read_config and the loader name
+// and recognise it, and in the snapshot's register layouts
+// (prstatus_regs and reg_index).
+#![allow(bad_style)]
+
+use std::fmt::Debug;
+use unicorn::*;
+use capstone::{CsMode,CsArch};
+use roper::thumb::{th_is_wide,reap_thumb_gadgets};
+use roper::arm::reap_arm_gadgets;
+use roper::aarch64::reap_aarch64_gadgets;
+use roper::mips::reap_mips_gadgets;
+use roper::phylostructs::{Clump,Endian,MachineMode};
+
+pub trait Isa : Copy + Debug + Send + 'static {
+        fn uc_arch (&self) -> Arch;
+        fn uc_mode (&self) -> Option<Mode>; /* None if unicorn can't run it */
+        fn cs_arch (&self) -> CsArch;
+        fn cs_mode (&self) -> CsMode;
+        fn word_size (&self) -> usize;
+        fn endian (&self) -> Endian;
+        /* Unicorn register ids, in register-number order. Register
+         * indices everywhere else (inregs, patterns, HatchResult)
+         * are positions in this vector. */
+        fn reg_ids (&self) -> Vec<i32>;
+        /* register numbers of the stack pointer and program counter */
+        fn sp_num (&self) -> usize;
+        fn pc_num (&self) -> usize;
+        /* The mode an address will execute in when jumped to, and the
+         * one the engine is in now. Both double as the disassembly
+         * mode. */
+        fn mode_at (&self, addr: u64) -> Self;
+        fn current_mode (&self, uc: &Unicorn) -> Self;
+        /* Strip whatever interworking bits an address carries, or
+         * add the ones it needs to be jumped to in this mode. */
+        fn code_addr (&self, addr: u64) -> u64 { addr }
+        fn entry_addr (&self, addr: u64) -> u64 { addr }
+        /* Width of the instruction at addr, which may need a peek
+         * at memory on variable-width encodings. */
+        fn inst_size (&self, _uc: &Unicorn, _addr: u64) -> usize { 4 }
+        /* The smallest step from one instruction to the next */
+        fn inst_align (&self) -> usize { 4 }
+        /* The gadgets in a run of code starting at start_addr */
+        fn reap_gadgets (&self, code: &Vec<u8>, start_addr: u64) -> Vec<Clump>;
+        /* A register's name, the way the disassembler writes it */
+        fn reg_name (&self, r: usize) -> String;
+        /* Linux calling convention for system calls: the interrupt
+         * number a syscall instruction raises in Unicorn, the register
+         * holding the call number, and those holding its arguments. */
+        fn is_syscall_intr (&self, intno: u32) -> bool;
+        fn syscall_num_reg (&self) -> usize;
+        fn syscall_arg_regs (&self) -> Vec<usize>;
+        fn execve_num (&self) -> u64;
+
+        fn num_registers (&self) -> usize {
+            self.reg_ids().len()
+        }
+        fn sp_id (&self) -> i32 {
+            self.reg_ids()[self.sp_num()]
+        }
+        fn read_registers (&self, uc: &Unicorn) -> Vec<u64> {
+            self.reg_ids()
+                .iter()
+                .map(|&x| uc.reg_read(x).expect("Error reading reg"))
+                .collect()
+        }
+        fn write_register (&self, uc: &Unicorn, r: usize, val: u64) {
+            uc.reg_write(self.reg_ids()[r], val)
+              .expect("Error writing reg");
+        }
+        fn read_sp (&self, uc: &Unicorn) -> u64 {
+            uc.reg_read(self.sp_id()).expect("Error reading SP")
+        }
+        fn write_sp (&self, uc: &Unicorn, val: u64) {
+            uc.reg_write(self.sp_id(), val).expect("Error writing SP register")
+        }
+}
+
+pub static REGISTERS : [RegisterARM; 16] = [RegisterARM::R0,
+                                            RegisterARM::R1,
+                                            RegisterARM::R2,
+                                            RegisterARM::R3,
+                                            RegisterARM::R4,
+                                            RegisterARM::R5,
+                                            RegisterARM::R6,
+                                            RegisterARM::R7,
+                       /****************/   RegisterARM::R8,
+                       /****************/   RegisterARM::SB,
+                       /* Not used in  */   RegisterARM::SL,
+                       /* Thumb Mode   */   RegisterARM::FP,
+                       /****************/   RegisterARM::IP,
+                       /****************/   RegisterARM::SP,
+                                            RegisterARM::LR,
+                                            RegisterARM::PC];
+
+/* X0-X30, then SP and PC, so that register indices line up with
+ * the register numbers everywhere else. Unicorn only knows X16, X17,
+ * X29 and X30 by their other names. */
+pub static REGISTERS64 : [RegisterARM64; 33] = [RegisterARM64::X0,
+                                                RegisterARM64::X1,
+                                                RegisterARM64::X2,
+                                                RegisterARM64::X3,
+                                                RegisterARM64::X4,
+                                                RegisterARM64::X5,
+                                                RegisterARM64::X6,
+                                                RegisterARM64::X7,
+                                                RegisterARM64::X8,
+                                                RegisterARM64::X9,
+                                                RegisterARM64::X10,
+                                                RegisterARM64::X11,
+                                                RegisterARM64::X12,
+                                                RegisterARM64::X13,
+                                                RegisterARM64::X14,
+                                                RegisterARM64::X15,
+                                                RegisterARM64::IP0,
+                                                RegisterARM64::IP1,
+                                                RegisterARM64::X18,
+                                                RegisterARM64::X19,
+                                                RegisterARM64::X20,
+                                                RegisterARM64::X21,
+                                                RegisterARM64::X22,
+                                                RegisterARM64::X23,
+                                                RegisterARM64::X24,
+                                                RegisterARM64::X25,
+                                                RegisterARM64::X26,
+                                                RegisterARM64::X27,
+                                                RegisterARM64::X28,
+                                                RegisterARM64::FP,
+                                                RegisterARM64::LR,
+                                                RegisterARM64::SP,
+                                                RegisterARM64::PC];
+
+/* $zero through $ra in register-number order, then the pc */
+pub static REGISTERS_MIPS : [RegisterMIPS; 33] = [RegisterMIPS::ZERO,
+                                                  RegisterMIPS::AT,
+                                                  RegisterMIPS::V0,
+                                                  RegisterMIPS::V1,
+                                                  RegisterMIPS::A0,
+                                                  RegisterMIPS::A1,
+                                                  RegisterMIPS::A2,
+                                                  RegisterMIPS::A3,
+                                                  RegisterMIPS::T0,
+                                                  RegisterMIPS::T1,
+                                                  RegisterMIPS::T2,
+                                                  RegisterMIPS::T3,
+                                                  RegisterMIPS::T4,
+                                                  RegisterMIPS::T5,
+                                                  RegisterMIPS::T6,
+                                                  RegisterMIPS::T7,
+                                                  RegisterMIPS::S0,
+                                                  RegisterMIPS::S1,
+                                                  RegisterMIPS::S2,
+                                                  RegisterMIPS::S3,
+                                                  RegisterMIPS::S4,
+                                                  RegisterMIPS::S5,
+                                                  RegisterMIPS::S6,
+                                                  RegisterMIPS::S7,
+                                                  RegisterMIPS::T8,
+                                                  RegisterMIPS::T9,
+                                                  RegisterMIPS::K0,
+                                                  RegisterMIPS::K1,
+                                                  RegisterMIPS::GP,
+                                                  RegisterMIPS::SP,
+                                                  RegisterMIPS::FP,
+                                                  RegisterMIPS::RA,
+                                                  RegisterMIPS::PC];
+
+static MIPS_REG_NAMES : [&'static str; 33] =
+        ["zero", "at", "v0", "v1", "a0", "a1", "a2", "a3",
+         "t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7",
+         "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7",
+         "t8", "t9", "k0", "k1", "gp", "sp", "fp", "ra", "pc"];
+
+impl Isa for MachineMode {
+        fn uc_arch (&self) -> Arch { self.arch() }
+        fn uc_mode (&self) -> Option<Mode> { self.uc() }
+        fn cs_arch (&self) -> CsArch { MachineMode::cs_arch(self) }
+        fn cs_mode (&self) -> CsMode { self.cs() }
+        fn word_size (&self) -> usize { MachineMode::word_size(self) }
+        fn endian (&self) -> Endian { MachineMode::endian(self) }
+
+        fn reg_ids (&self) -> Vec<i32> {
+            match *self {
+                MachineMode::AARCH64 => REGISTERS64.iter().map(|r| r.to_i32()).collect(),
+                MachineMode::MIPS |
+                MachineMode::MIPSEL  => REGISTERS_MIPS.iter().map(|r| r.to_i32()).collect(),
+                _                    => REGISTERS.iter().map(|r| r.to_i32()).collect(),
+            }
+        }
+        fn sp_num (&self) -> usize {
+            match *self {
+                MachineMode::AARCH64 => 31,
+                MachineMode::MIPS |
+                MachineMode::MIPSEL  => 29,
+                _                    => 13,
+            }
+        }
+        fn pc_num (&self) -> usize {
+            match *self {
+                MachineMode::AARCH64 => 32,
+                MachineMode::MIPS |
+                MachineMode::MIPSEL  => 32,
+                _                    => 15,
+            }
+        }
+        /* Thumb addresses are the ones with their LSB set, the way
+         * they're packed into a chain. */
+        fn mode_at (&self, addr: u64) -> MachineMode {
+            match *self {
+                MachineMode::ARM |
+                MachineMode::THUMB if addr & 1 == 1 => MachineMode::THUMB,
+                MachineMode::ARM |
+                MachineMode::THUMB                  => MachineMode::ARM,
+                m                                   => m,
+            }
+        }
+        fn current_mode (&self, uc: &Unicorn) -> MachineMode {
+            match *self {
+                MachineMode::ARM |
+                MachineMode::THUMB => if uc.query(Query::MODE).unwrap() == 0 {
+                                          MachineMode::ARM
+                                      } else {
+                                          MachineMode::THUMB
+                                      },
+                m                  => m,
+            }
+        }
+        fn code_addr (&self, addr: u64) -> u64 {
+            match *self {
+                MachineMode::ARM |
+                MachineMode::THUMB => addr & !1,
+                _                  => addr,
+            }
+        }
+        fn entry_addr (&self, addr: u64) -> u64 {
+            match *self {
+                MachineMode::THUMB => addr | 1,
+                _                  => addr,
+            }
+        }
+        /* Thumb-2 instructions may be 32 bits wide, which we can
+         * only tell from the first halfword. */
+        fn inst_size (&self, uc: &Unicorn, addr: u64) -> usize {
+            match *self {
+                MachineMode::THUMB => match uc.mem_read(addr & !1, 2) {
+                    Ok(ref h) if th_is_wide((h[0] as u16) | ((h[1] as u16) << 8)) => 4,
+                    _ => 2,
+                },
+                _ => 4,
+            }
+        }
+        fn inst_align (&self) -> usize {
+            match *self {
+                MachineMode::THUMB => 2,
+                _                  => 4,
+            }
+        }
+        fn reap_gadgets (&self, code: &Vec<u8>, start_addr: u64) -> Vec<Clump> {
+            match *self {
+                MachineMode::THUMB   => reap_thumb_gadgets(code, start_addr),
+                MachineMode::ARM     => reap_arm_gadgets(code, start_addr),
+                MachineMode::AARCH64 => reap_aarch64_gadgets(code, start_addr),
+                MachineMode::MIPS |
+                MachineMode::MIPSEL  => reap_mips_gadgets(code, start_addr, *self),
+            }
+        }
+        fn reg_name (&self, r: usize) -> String {
+            match *self {
+                MachineMode::AARCH64 => match r {
+                    31 => "sp".to_string(),
+                    32 => "pc".to_string(),
+                    _  => format!("x{}", r),
+                },
+                MachineMode::MIPS |
+                MachineMode::MIPSEL  => match MIPS_REG_NAMES.get(r) {
+                    Some(n) => format!("${}", n),
+                    None    => format!("${}", r),
+                },
+                _ => match r {
+                    13 => "sp".to_string(),
+                    14 => "lr".to_string(),
+                    15 => "pc".to_string(),
+                    _  => format!("r{}", r),
+                },
+            }
+        }
+        /* svc/swi raise EXCP_SWI (2) on both ARMs, syscall raises
+         * EXCP_SYSCALL (17) on MIPS. The ARM numbers are EABI ones,
+         * taken from r7, and MIPS is o32. */
+        fn is_syscall_intr (&self, intno: u32) -> bool {
+            match *self {
+                MachineMode::MIPS |
+                MachineMode::MIPSEL => intno == 17,
+                _                   => intno == 2,
+            }
+        }
+        fn syscall_num_reg (&self) -> usize {
+            match *self {
+                MachineMode::AARCH64 => 8,
+                MachineMode::MIPS |
+                MachineMode::MIPSEL  => 2,
+                _                    => 7,
+            }
+        }
+        fn syscall_arg_regs (&self) -> Vec<usize> {
+            match *self {
+                MachineMode::AARCH64 => (0..6).collect(),
+                MachineMode::MIPS |
+                MachineMode::MIPSEL  => (4..8).collect(),
+                _                    => (0..7).collect(),
+            }
+        }
+        fn execve_num (&self) -> u64 {
+            match *self {
+                MachineMode::AARCH64 => 221,
+                MachineMode::MIPS |
+                MachineMode::MIPSEL  => 4011,
+                _                    => 11,
+            }
+        }
+}
@@ -0,0 +1,385 @@
+// Loading an ELF image the way the kernel and the dynamic linker
+// would, as far as a chain can tell the difference. PIE executables
+// and shared objects are slid up to a load base of our choosing, and
+// their dynamic relocations are applied to the segment images before
+// anything is mapped: R_ARM_RELATIVE, GLOB_DAT, JUMP_SLOT and ABS32,
+// and their AArch64 counterparts. Relocations against symbols an
+// object imports are left until every object is loaded, and then
+// resolved against the others' exports, so that a chain can reach
+// system or execve in libc through the binary's PLT. A chain evolved
+// against an image that wasn't loaded like this will find the wrong
+// pointers in the GOT, and won't transfer. Images that aren't ELF
+// are left to firmware.rs.
+extern crate elf;
+
+use std::collections::HashMap;
+use elf::types::*;
+
+use roper::util::{get_word_endian,pack_word_endian};
+use roper::ontostructs::*;
+use roper::isa::Isa;
+use roper::firmware::*;
+use roper::phylostructs::{CodeRegion,Endian,MachineMode};
+
+/* Where PIE executables and shared objects go unless we're told
+ * otherwise. It has to fall between hook_base and hook_base+hook_size,
+ * which is all that the hooks in hatch_chain can see. */
+pub const PIE_BASE : u64 = 0x00010000;
+
+/* The main image: where it is, what it is, and, for the formats
+ * that don't say, where it goes and where it starts. */
+#[derive(Debug,Clone,PartialEq)]
+pub struct ImageSpec {
+        pub path:   String,
+        pub format: ImageFormat,
+        pub base:   u64,
+        pub entry:  Option<u64>,
+}
+
+impl ImageSpec {
+        pub fn new (path: &str, format: ImageFormat, base: u64, entry: Option<u64>) -> ImageSpec {
+            ImageSpec {
+                path:   path.to_string(),
+                format: format,
+                base:   base,
+                entry:  entry,
+            }
+        }
+}
+
+/* A loaded object, by name, and how far it was slid. Addresses in
+ * it are reported as name+offset, where the offset is from the
+ * object's own idea of address 0, so that they can be rebased
+ * wherever the object turns up in the real process. */
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub struct Module {
+        pub name: String,
+        pub path: String,
+        pub bias: u64,
+        pub lo:   u64,
+        pub hi:   u64,
+}
+
+impl Module {
+        pub fn new (name: &str, path: &str, bias: u64, segs: &Vec<Seg>) -> Module {
+            let loads : Vec<&Seg> = segs.iter()
+                                        .filter(|s| s.segtype == SegType::Load)
+                                        .collect();
+            let lo = loads.iter().map(|s| s.addr).min().unwrap_or(bias);
+            let hi = loads.iter().map(|s| s.addr + s.memsz as u64).max().unwrap_or(bias);
+            Module {
+                name: name.to_string(),
+                path: path.to_string(),
+                bias: bias,
+                lo:   lo,
+                hi:   hi,
+            }
+        }
+}
+
+pub fn module_of (modules: &Vec<Module>, addr: u64) -> Option<&Module> {
+        modules.iter().find(|m| m.lo <= addr && addr < m.hi)
+}
+
+/* libc.so.6+0x3a4f1, or just the address if no module holds it */
+pub fn mod_offset (modules: &Vec<Module>, addr: u64) -> String {
+        match module_of(modules, addr) {
+            Some(m) => format!("{}+0x{:x}", m.name, addr - m.bias),
+            None    => format!("0x{:x}", addr),
+        }
+}
+
+const SHN_UNDEF : u16 = 0;
+const SHN_ABS   : u16 = 0xfff1;
+
+/* Only position-independent objects are slid; an ET_EXEC binary
+ * goes where its program headers say. */
+pub fn load_bias (file: &elf::File, base: u64) -> u64 {
+        if file.ehdr.elftype == ET_DYN { base } else { 0 }
+}
+
+pub fn elf_word_size (file: &elf::File) -> usize {
+        if file.ehdr.class == ELFCLASS64 { 8 } else { 4 }
+}
+
+pub fn elf_endian (file: &elf::File) -> Endian {
+        if file.ehdr.data == ELFDATA2MSB { Endian::BIG } else { Endian::LITTLE }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum RelocKind {
+        Relative,
+        GlobDat,
+        JumpSlot,
+        Abs,
+        Other(u32),
+}
+
+fn reloc_kind (machine: Machine, t: u32) -> RelocKind {
+        match (machine, t) {
+            (EM_ARM, 23)                         => RelocKind::Relative,
+            (EM_ARM, 21)                         => RelocKind::GlobDat,
+            (EM_ARM, 22)                         => RelocKind::JumpSlot,
+            (EM_ARM, 2)                          => RelocKind::Abs,
+            (elf::types::EM_AARCH64, 1027)       => RelocKind::Relative,
+            (elf::types::EM_AARCH64, 1025)       => RelocKind::GlobDat,
+            (elf::types::EM_AARCH64, 1026)       => RelocKind::JumpSlot,
+            (elf::types::EM_AARCH64, 257)        => RelocKind::Abs,
+            _                                    => RelocKind::Other(t),
+        }
+}
+
+/* A dynamic relocation, with its address and symbol already slid
+ * by the load bias. sym_value is None for an undefined symbol. */
+#[derive(Debug,Clone)]
+pub struct Reloc {
+        pub addr:      u64,
+        pub kind:      RelocKind,
+        pub sym_name:  String,
+        pub sym_value: Option<u64>,
+        pub addend:    Option<i64>, /* explicit, for RELA; REL keeps it in place */
+}
+
+/* Every entry in the SHF_ALLOC REL and RELA sections, which is to
+ * say the ones the dynamic linker would see. */
+pub fn read_relocs (file: &elf::File, bias: u64) -> Vec<Reloc> {
+        let ws     = elf_word_size(file);
+        let endian = elf_endian(file);
+        let mut relocs = Vec::new();
+        for sec in file.sections.iter() {
+            let rela = sec.shdr.shtype == SHT_RELA;
+            if (!rela && sec.shdr.shtype != SHT_REL)
+               || sec.shdr.flags.0 & SHF_ALLOC.0 == 0 {
+                continue;
+            };
+            let syms = if sec.shdr.link == 0 {
+                Vec::new()
+            } else {
+                file.get_symbols(&file.sections[sec.shdr.link as usize])
+                    .unwrap_or(Vec::new())
+            };
+            let entsize = if rela { 3 * ws } else { 2 * ws };
+            let mut off = 0;
+            while off + entsize <= sec.data.len() {
+                let r_offset = get_word_endian(&sec.data, off, ws, endian);
+                let r_info   = get_word_endian(&sec.data, off + ws, ws, endian);
+                let addend   = if rela {
+                    Some(get_word_endian(&sec.data, off + 2*ws, ws, endian) as i64)
+                } else {
+                    None
+                };
+                off += entsize;
+                let (sym, t) = if ws == 8 {
+                    ((r_info >> 32) as usize, (r_info & 0xffffffff) as u32)
+                } else {
+                    ((r_info >> 8) as usize, (r_info & 0xff) as u32)
+                };
+                let (name, value) = match syms.get(sym) {
+                    Some(s) if sym != 0 => {
+                        let v = match s.shndx {
+                            SHN_UNDEF => None,
+                            SHN_ABS   => Some(s.value),
+                            _         => Some(s.value.wrapping_add(bias)),
+                        };
+                        (s.name.clone(), v)
+                    },
+                    _ => (String::new(), None),
+                };
+                relocs.push(Reloc {
+                    addr:      r_offset.wrapping_add(bias),
+                    kind:      reloc_kind(file.ehdr.machine, t),
+                    sym_name:  name,
+                    sym_value: value,
+                    addend:    addend,
+                });
+            }
+        }
+        relocs
+}
+
+/* The segment whose file image holds a word at addr, and where */
+fn seg_offset (segs: &Vec<Seg>, addr: u64, ws: usize) -> Option<(usize, usize)> {
+        segs.iter()
+            .position(|s| s.segtype == SegType::Load
+                          && addr >= s.addr
+                          && addr + ws as u64 <= s.addr + s.data.len() as u64)
+            .map(|j| (j, (addr - segs[j].addr) as usize))
+}
+
+/* Patches the segment images in place, and returns the relocations
+ * that need a symbol from some other object. Lazily bound PLT slots
+ * are pointed back at the (slid) PLT, as ld.so would leave them. */
+pub fn apply_relocs (segs: &mut Vec<Seg>,
+                     relocs: &Vec<Reloc>,
+                     bias: u64,
+                     ws: usize,
+                     endian: Endian) -> Vec<Reloc> {
+        let mut unresolved = Vec::new();
+        let mut applied = 0;
+        let mut skipped = 0;
+        for r in relocs {
+            let (j, off) = match seg_offset(segs, r.addr, ws) {
+                Some(x) => x,
+                None    => { skipped += 1; continue },
+            };
+            let in_place = get_word_endian(&segs[j].data, off, ws, endian);
+            let a = r.addend.map(|a| a as u64).unwrap_or(in_place);
+            let val = match (r.kind, r.sym_value) {
+                (RelocKind::Relative, _)                 => bias.wrapping_add(a),
+                (RelocKind::GlobDat, Some(s)) |
+                (RelocKind::JumpSlot, Some(s))           => match r.addend {
+                    Some(a) => s.wrapping_add(a as u64),
+                    None    => s,
+                },
+                (RelocKind::Abs, Some(s))                => s.wrapping_add(a),
+                (RelocKind::JumpSlot, None)              => {
+                    unresolved.push(r.clone());
+                    bias.wrapping_add(in_place)
+                },
+                (RelocKind::GlobDat, None) |
+                (RelocKind::Abs, None)                   => {
+                    unresolved.push(r.clone());
+                    continue;
+                },
+                (RelocKind::Other(_), _)                 => { skipped += 1; continue },
+            };
+            let bytes = pack_word_endian(val, ws, endian);
+            segs[j].data[off..off+ws].copy_from_slice(&bytes);
+            applied += 1;
+        }
+        println!("[*] Applied {} relocations, skipped {}, {} left for imports",
+                 applied, skipped, unresolved.len());
+        unresolved
+}
+
+/* The symbols an object defines for others to use. Strong
+ * definitions win over weak ones. */
+pub fn elf_exports (file: &elf::File, bias: u64) -> HashMap<String,u64> {
+        let mut exports : HashMap<String,u64> = HashMap::new();
+        let mut weak : HashMap<String,u64> = HashMap::new();
+        for sec in file.sections.iter() {
+            if sec.shdr.shtype != SHT_DYNSYM {
+                continue;
+            };
+            for sym in file.get_symbols(sec).unwrap_or(Vec::new()) {
+                if sym.shndx == SHN_UNDEF || sym.name.is_empty() {
+                    continue;
+                };
+                let v = if sym.shndx == SHN_ABS { sym.value } else { sym.value.wrapping_add(bias) };
+                if sym.bind == STB_GLOBAL {
+                    exports.insert(sym.name, v);
+                } else if sym.bind == STB_WEAK {
+                    weak.insert(sym.name, v);
+                };
+            }
+        }
+        for (k, v) in weak {
+            exports.entry(k).or_insert(v);
+        }
+        exports
+}
+
+/* Resolves each object's imports against the others, searching
+ * them in load order, the binary first, as ld.so would. */
+pub fn link_objects (objects: &mut Vec<ElfObject>) {
+        for i in 0..objects.len() {
+            let (resolved, missing) : (Vec<Reloc>, Vec<Reloc>) = {
+                let mut resolved = Vec::new();
+                let mut missing  = Vec::new();
+                for r in &objects[i].imports {
+                    let found = objects.iter()
+                                       .enumerate()
+                                       .filter(|&(j, _)| j != i)
+                                       .filter_map(|(_, o)| o.exports.get(&r.sym_name))
+                                       .next();
+                    match found {
+                        Some(&v) => {
+                            let mut r = r.clone();
+                            r.sym_value = Some(v);
+                            resolved.push(r);
+                        },
+                        None => missing.push(r.clone()),
+                    }
+                }
+                (resolved, missing)
+            };
+            let o = &mut objects[i];
+            if !resolved.is_empty() {
+                println!("[*] Linking {}: {} imports resolved", o.module.name, resolved.len());
+                apply_relocs(&mut o.segments, &resolved, o.module.bias,
+                             o.word_size, o.endian);
+                refresh_sections(&mut o.sections, &o.segments);
+            };
+            if !missing.is_empty() {
+                let mut names : Vec<String> = missing.iter()
+                                                     .map(|r| r.sym_name.clone())
+                                                     .collect();
+                names.sort();
+                names.dedup();
+                println!("[-] {} imports of {} unresolved: {}",
+                         missing.len(), o.module.name, names.join(" "));
+            };
+            o.imports = missing;
+        }
+}
+
+/* ELF, or whatever else the file turns out to be. The word size
+ * and byte order are only needed by formats that don't give them. */
+pub fn load_image (path: &str,
+                   format: ImageFormat,
+                   base: u64,
+                   entry: Option<u64>,
+                   word_size: usize,
+                   endian: Endian) -> ElfObject {
+        match resolve_format(path, format) {
+            ImageFormat::Elf    => {
+                let mut obj = load_elf_object(path, base);
+                obj.entry = entry.or(obj.entry);
+                obj
+            },
+            ImageFormat::Raw    => load_raw_image(path, base, entry, word_size, endian),
+            ImageFormat::MemMap => load_memmap_image(path, entry, word_size, endian),
+            f                   => load_hex_image(path, f, entry, word_size, endian),
+        }
+}
+
+/* The mode to start the engine in: as given, or else as the image
+ * says, if it says at all */
+pub fn image_machine_mode (image: &ImageSpec, arch: Option<MachineMode>) -> MachineMode {
+        if let Some(m) = arch {
+            return m;
+        };
+        match resolve_format(&image.path, image.format) {
+            ImageFormat::Elf    => elf_machine_mode(&image.path),
+            ImageFormat::MemMap => memmap_mode(&image.path).unwrap_or_else(|| {
+                panic!("{} gives no (arch ...); give one there or with --arch", image.path)
+            }),
+            f => panic!("{} is a {:?} image, which doesn't say what it runs on; give --arch",
+                        image.path, f),
+        }
+}
+
+/* The main image and its libraries, loaded and linked. libs pairs
+ * each library's path with the address to load it at, which for a
+ * shared object is the slide applied to its addresses. */
+pub fn load_process_image <A: Isa> (image: &ImageSpec,
+                                    libs: &Vec<(String,u64)>,
+                                    mode: A) -> Vec<ElfObject> {
+        let (ws, endian) = (mode.word_size(), mode.endian());
+        let mut objects = vec![load_image(&image.path, image.format, image.base,
+                                          image.entry, ws, endian)];
+        for &(ref path, base) in libs {
+            println!("[*] Loading library {} at {:08x}", path, base);
+            objects.push(load_image(path, ImageFormat::Auto, base, None, ws, endian));
+        }
+        link_objects(&mut objects);
+        objects
+}
+
+pub fn image_modules (objects: &Vec<ElfObject>) -> Vec<Module> {
+        objects.iter().map(|o| o.module.clone()).collect()
+}
+
+pub fn image_code_regions (objects: &Vec<ElfObject>) -> Vec<CodeRegion> {
+        objects.iter().flat_map(|o| o.code_regions()).collect()
+}
@@ -0,0 +1,167 @@
+// Gadget reaping for 32-bit MIPS, in either byte order. There's no
+// pop here either. What we look for are function epilogues that
+// reload $ra from the stack frame and return through it, usually
+// tearing the frame down in the branch delay slot:
+//
+//     lw $ra, k($sp) ; ... ; jr $ra ; addiu $sp, $sp, n
+//     lw $ra, k($sp) ; ... ; addiu $sp, $sp, n ; jr $ra ; nop
+//
+// The instruction in the delay slot runs before the jump lands, so
+// it belongs to the gadget, and it's what we record as the exit.
+#![allow(bad_style)]
+
+use roper::util::*;
+use roper::phylostructs::*;
+
+static RA : u32 = 31;
+static SP : u32 = 29;
+
+const MAX_WALK : usize = 8;
+
+pub fn mips_is_jr_ra (w: u32) -> bool {
+        w == 0x03E00008
+}
+
+fn opcode (w: u32) -> u32 { w >> 26 }
+fn rs (w: u32) -> u32 { (w >> 21) & 0x1F }
+fn rt (w: u32) -> u32 { (w >> 16) & 0x1F }
+fn rd (w: u32) -> u32 { (w >> 11) & 0x1F }
+fn funct (w: u32) -> u32 { w & 0x3F }
+fn simm (w: u32) -> i32 { (w & 0xFFFF) as u16 as i16 as i32 }
+
+// lw $ra, k($sp)
+fn lw_ra (w: u32) -> Option<i32> {
+        if w & 0xFFFF0000 == 0x8FBF0000 {
+            Some(simm(w))
+        } else {
+            None
+        }
+}
+
+// addiu $sp, $sp, n
+fn addiu_sp (w: u32) -> Option<i32> {
+        if w & 0xFFFF0000 == 0x27BD0000 {
+            Some(simm(w))
+        } else {
+            None
+        }
+}
+
+pub fn mips_is_ctrl (w: u32) -> bool {
+        match opcode(w) {
+            0x00 => match funct(w) {
+                0x08 | 0x09     => true,        // jr, jalr
+                0x0C | 0x0D     => true,        // syscall, break
+                0x30 ..= 0x36   => true,        // traps
+                _               => false,
+            },
+            0x01            => true,            // bltz, bgez, bal, ...
+            0x02 ..= 0x07   => true,            // j, jal, beq, bne, blez, bgtz
+            0x10 ..= 0x13   => rs(w) == 8 || w & 0x3F == 0x18, // bc1f..., eret
+            0x14 ..= 0x17   => true,            // branch-likely
+            _               => false,
+        }
+}
+
+// Rough, like its ARM counterpart, but errs on the side of yes.
+pub fn mips_writes_reg (w: u32, r: u32) -> bool {
+        if r == 0 { return false };
+        match opcode(w) {
+            0x00 => match funct(w) {
+                0x08 | 0x0C | 0x0D  => false,
+                0x11 | 0x13         => false,   // mthi, mtlo
+                0x18 ..= 0x1B       => false,   // mult, div
+                0x30 ..= 0x36       => false,
+                _                   => rd(w) == r,
+            },
+            0x01            => r == RA && rt(w) & 0x10 != 0, // bltzal, bgezal
+            0x03            => r == RA,                      // jal
+            0x08 ..= 0x0F   => rt(w) == r,                   // arithmetic imm, lui
+            0x1C            => rd(w) == r,                   // mul, clz, ...
+            0x20 ..= 0x26   => rt(w) == r,                   // loads
+            0x30            => rt(w) == r,                   // ll
+            _               => false,
+        }
+}
+
+// Find the reload of $ra for the jr at ws[j], and the adjustment of
+// $sp that tears down the frame, whether it's in the delay slot or
+// ahead of the jump. Nothing between the reload and the jump may
+// touch $ra or $sp, or transfer control. As with the other reapers,
+// the clump's word is the index of the reload, and ret_addr that of
+// the delay slot.
+fn epilogue_clump (ws: &Vec<u32>, j: usize) -> Option<Clump> {
+        if j + 1 >= ws.len() || mips_is_ctrl(ws[j+1]) { return None };
+        let mut frame : Option<i32> = addiu_sp(ws[j+1]);
+        if frame.is_none() && mips_writes_reg(ws[j+1], SP) { return None };
+        let mut o = j;
+        while o > 0 && o > j.saturating_sub(MAX_WALK) {
+            o -= 1;
+            let w = ws[o];
+            if let Some(k) = lw_ra(w) {
+                let n = match frame { Some(n) => n, None => return None };
+                // slot 0 is the one just below the entry sp
+                let ra_slot = k / 4 + 1;
+                if n <= 0 || k < 0 || k % 4 != 0 || ra_slot > n / 4 {
+                    return None;
+                }
+                return Some(Clump {
+                    exchange:   false,
+                    sp_delta:   n / 4,
+                    ret_offset: ra_slot as usize,
+                    words:      vec![o as u64],
+                    ret_addr:   (j + 1) as u64,
+                    mode:       MachineMode::MIPS,
+                    kind:       GadgetKind::JumpReg,
+                    ..Default::default()
+                });
+            }
+            match addiu_sp(w) {
+                Some(n) if frame.is_none() => { frame = Some(n); continue },
+                _                          => (),
+            }
+            if mips_is_ctrl(w) || mips_writes_reg(w, SP) || mips_writes_reg(w, RA) {
+                return None;
+            }
+        }
+        None
+}
+
+fn mips_scan_for_rets (ws: &Vec<u32>) -> Vec<Clump> {
+        (0..ws.len()).filter(|&i| mips_is_jr_ra(ws[i]))
+                     .filter_map(|i| epilogue_clump(ws, i))
+                     .collect()
+}
+
+pub fn reap_mips_gadgets (code: &Vec<u8>,
+                          start_addr: u64,
+                          mode: MachineMode)
+                          -> Vec<Clump> {
+        let mut gads : Vec<Clump> = Vec::new();
+        let insts : Vec<u32>      = u8s_to_u32s(code, mode.endian());
+        for clump in mips_scan_for_rets(&insts) {
+            // walk up from the reload of $ra until we hit a control
+            // instruction, or something that moves sp out from under
+            // the epilogue. Every step is a candidate entry point.
+            let from = clump.words[0] as usize;
+            let exit = clump.ret_addr;
+            let mut o = from;
+            loop {
+                gads.push(Clump {
+                    sp_delta   : clump.sp_delta,
+                    ret_offset : clump.ret_offset,
+                    words      : vec![start_addr + (4 * o as u64)],
+                    ret_addr   : start_addr + (4 * exit),
+                    mode       : mode,
+                    exchange   : clump.exchange,
+                    kind       : clump.kind,
+                    ..Default::default()
+                });
+                if o == 0 || o <= from.saturating_sub(MAX_WALK) { break };
+                let w = insts[o-1];
+                if mips_is_ctrl(w) || mips_writes_reg(w, SP) { break };
+                o -= 1;
+            }
+        }
+        gads
+}
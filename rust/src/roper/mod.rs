@@ -6,22 +6,46 @@ pub mod thumb;
 pub mod evolve;
 pub mod phylostructs;
 pub mod arm;
+pub mod aarch64;
+pub mod mips;
 //pub mod hooks;
 pub mod ontostructs;
+pub mod isa;
 pub mod csv_reader;
 pub mod statistics;
 pub mod interactive;
+pub mod profiler;
+pub mod gadgetdb;
+pub mod export;
+pub mod serial;
+pub mod checkpoint;
+pub mod loader;
+pub mod firmware;
+pub mod snapshot;
+pub mod read_config;
 //pub mod dis;
 
 pub use self::interactive::*;
 pub use self::statistics::*;
 pub use self::ontostructs::*;
+pub use self::isa::*;
 pub use self::util::*;
 pub use self::hatchery::*;
 pub use self::thumb::*;
 pub use self::arm::*;
+pub use self::aarch64::*;
+pub use self::mips::*;
 pub use self::evolve::*;
 pub use self::phylostructs::*;
 pub use self::csv_reader::*;
+pub use self::profiler::*;
+pub use self::gadgetdb::*;
+pub use self::export::*;
+pub use self::serial::*;
+pub use self::checkpoint::*;
+pub use self::loader::*;
+pub use self::firmware::*;
+pub use self::snapshot::*;
+pub use self::read_config::*;
 //pub use self::hooks::*;
 //pub use self::dis::*;
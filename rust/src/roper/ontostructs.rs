@@ -8,28 +8,84 @@ use std::process::exit;
 use elf::types::*;
 use std::fs::File;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path,PathBuf};
+use std::collections::HashMap;
 use roper::hatchery::*;
 use roper::util::*;
 use roper::phylostructs::*;
+use roper::isa::*;
+use roper::loader::*;
+use roper::firmware::{sniff_format,ImageFormat};
+use roper::snapshot::{Snapshot,restore_snapshot};
 use unicorn::*;
 use std::thread;
 use rand::thread_rng;
 use std::rc::Rc;
 use std::cell::{RefCell,Ref,RefMut};
 use std::sync::Arc;
+use std::ops::{Deref,DerefMut};
 
 pub static _DEBUG : bool = true; //true;
 
-/* don't hard code these, read them from the elf */
+/* Defaults for the emulation settings in Params: BASE_ADDR and
+ * MEM_SIZE bound what the hooks see, as hook_base and hook_size,
+ * stretched by Machinery::new to take in whatever it loads */
 pub const BASE_ADDR  : u64   = 0x000004000;
 pub const MEM_SIZE   : usize = 0x010000000;
 pub const BASE_STACK : u64   = 0x000000000;
 pub const STACK_SIZE : usize = 0x000004000;
 pub const STACK_INIT : u64   = 0x000001000; //0x0E0000000;
 pub const MAX_STEPS  : usize = 0x800;
+pub const DEREF_SIZE : usize = 512;
 pub const STOP_ADDR  : u64   = 0x000000000;
 
+/* How an engine is laid out, and how far a chain may run in it.
+ * Params holds these, and an engine keeps its own copy. */
+#[derive(Debug,Clone,PartialEq)]
+pub struct EmuSettings {
+        pub max_steps:      usize,
+        pub stop_addr:      u64,
+        pub hook_base:      u64, /* the code and write hooks cover */
+        pub hook_size:      usize, /* hook_base up to hook_base+hook_size */
+        pub deref_size:     usize, /* bytes read behind each register */
+        pub stack_base:     Option<u64>, /* or just above the image */
+        pub stack_size:     usize,
+        pub payload_offset: Option<usize>, /* from stack_base, or a snapshot's sp */
+        pub stack_fill:     Vec<u8>, /* repeated over the stack, or zeroes */
+        pub watch:          Vec<(u64,usize)>, /* read back after each run, for memory goals */
+}
+
+impl Default for EmuSettings {
+        fn default () -> EmuSettings {
+            EmuSettings {
+                max_steps:      MAX_STEPS,
+                stop_addr:      STOP_ADDR,
+                hook_base:      BASE_ADDR,
+                hook_size:      MEM_SIZE,
+                deref_size:     DEREF_SIZE,
+                stack_base:     None,
+                stack_size:     STACK_SIZE,
+                payload_offset: None,
+                stack_fill:     Vec::new(),
+                watch:          Vec::new(),
+            }
+        }
+}
+
+impl EmuSettings {
+        /* saturating, since a hook_size reaching past the top of
+         * memory is as good as one that stops there */
+        pub fn hook_end (&self) -> u64 {
+            self.hook_base.saturating_add(self.hook_size as u64)
+        }
+        /* stretches the hooks' reach to take in lo..hi as well */
+        pub fn cover (&mut self, lo: u64, hi: u64) {
+            let end = self.hook_end().max(hi);
+            self.hook_base = self.hook_base.min(lo);
+            self.hook_size = (end - self.hook_base) as usize;
+        }
+}
+
 pub trait PageAligned {
     fn floor (&self) -> u64;
     fn ceil  (&self) -> u64;
@@ -42,16 +98,17 @@ pub struct Seg {
         pub memsz: usize,
         pub perm: unicorn::Protection,
         pub segtype: SegType,
+        pub data: Vec<u8>, // file contents, filesz bytes of it
 }
 impl PageAligned for Seg {
         fn floor (&self) -> u64 {
-            self.addr & 0xFFFFF000
+            self.addr & !0xFFF
         }
         fn ceil (&self) -> u64 {
-            (self.addr + (self.memsz as u64) + 0x1000) & 0xFFFFF000
+            (self.addr + (self.memsz as u64) + 0x1000) & !0xFFF
         }
         fn size (&self) -> usize {
-            ((self.addr as usize & 0xFFF) + self.memsz as usize + 0x1000) & 0xFFFFF000
+            ((self.addr as usize & 0xFFF) + self.memsz as usize + 0x1000) & !0xFFF
         }
 }
 #[derive(Debug,Clone)]
@@ -60,39 +117,23 @@ pub struct Sec {
         pub addr: u64,
         pub data: Vec<u8>,
         pub perm: unicorn::Protection,
+        pub alloc: bool, /* SHF_ALLOC: part of the process image */
 }
 impl PageAligned for Sec {
         fn floor (&self) -> u64 {
-            self.addr & 0xFFFFF000
+            self.addr & !0xFFF
         }
         fn ceil (&self) -> u64 {
-            (self.addr + (self.data.len() as u64) + 0x1000) & 0xFFFFF000
+            (self.addr + (self.data.len() as u64) + 0x1000) & !0xFFF
         }
         fn size (&self) -> usize {
-            ((self.addr as usize & 0xFFF) + self.data.len() + 0x1000) & 0xFFFFF000
+            ((self.addr as usize & 0xFFF) + self.data.len() + 0x1000) & !0xFFF
         }
 }
 
-pub static REGISTERS : [RegisterARM; 16] = [RegisterARM::R0,
-                                            RegisterARM::R1,
-                                            RegisterARM::R2,
-                                            RegisterARM::R3,
-                                            RegisterARM::R4,
-                                            RegisterARM::R5,
-                                            RegisterARM::R6,
-                                            RegisterARM::R7,
-                       /****************/   RegisterARM::R8,
-                       /****************/   RegisterARM::SB,
-                       /* Not used in  */   RegisterARM::SL,
-                       /* Thumb Mode   */   RegisterARM::FP,
-                       /****************/   RegisterARM::IP,
-                       /****************/   RegisterARM::SP,
-                                            RegisterARM::LR,
-                                            RegisterARM::PC];
-
-const GBA_CARTRIDGE_ROM_START : u64 = 0x08000000;
-
-fn load_file (path: &str) -> Vec<u8> {
+pub const EM_AARCH64 : u16 = 183;
+
+pub fn load_file (path: &str) -> Vec<u8> {
         let mut f = File::open(path)
                                     .expect("Failed to open path");
         let mut buf : Vec<u8> = Vec::new();
@@ -105,7 +146,8 @@ pub fn sec_is_exec (sec: &Sec, segs: &Vec<Seg>) -> bool {
         if seg.perm & PROT_EXEC == PROT_NONE {
             continue; /* this segment isn't executable. skip it. */
         };
-        if sec.addr >= seg.addr
+        if sec.alloc
+           && sec.addr >= seg.addr
            && sec.addr + sec.data.len() as u64 <= seg.addr + seg.memsz as u64 {
                return true;
         };
@@ -113,6 +155,39 @@ pub fn sec_is_exec (sec: &Sec, segs: &Vec<Seg>) -> bool {
     false
 }
 
+/* Every executable section, plus any executable PT_LOAD segment
+ * that has no executable section inside it. Stripped firmware
+ * tends to come without a section header table at all. */
+pub fn exec_code_regions (secs: &Vec<Sec>, segs: &Vec<Seg>, module: &str) 
+                          -> Vec<CodeRegion> {
+    let mut regions : Vec<CodeRegion> = 
+        secs.iter()
+            .filter(|s| !s.data.is_empty() && sec_is_exec(s, segs))
+            .map(|s| CodeRegion { name: s.name.clone(),
+                                  addr: s.addr,
+                                  data: s.data.clone(),
+                                  module: module.to_string() })
+            .collect();
+    for seg in segs {
+        if seg.segtype != SegType::Load 
+           || seg.perm & PROT_EXEC == PROT_NONE 
+           || seg.data.is_empty() {
+            continue;
+        };
+        let covered = regions.iter().any(|r| {
+            r.addr >= seg.addr 
+            && r.addr < seg.addr + seg.memsz as u64
+        });
+        if !covered {
+            regions.push(CodeRegion { name: format!("LOAD@{:08x}", seg.addr),
+                                      addr: seg.addr,
+                                      data: seg.data.clone(),
+                                      module: module.to_string() });
+        };
+    }
+    regions
+}
+
 #[derive(Copy,Clone,PartialEq,Eq,Debug)]
 pub enum SegType {
     Null,
@@ -130,7 +205,7 @@ pub enum SegType {
 }
 
 impl SegType {
-    fn new(raw: u32) -> Self {
+    pub fn new(raw: u32) -> Self {
         match raw {
             0 => SegType::Null,
             1 => SegType::Load,
@@ -174,13 +249,69 @@ pub fn elf_to_uni_perms (perm: u32) -> unicorn::Protection {
     unicorn::Protection::from_bits(unibits).expect("Failed to convert flags")
 }
 
-pub fn get_elf_addr_data (path: &str) 
+/* One object in the process image, the main binary or a library,
+ * loaded and relocated as far as it can be on its own. What it still
+ * needs from the others is in imports, and what it offers them is in
+ * exports. */
+#[derive(Debug,Clone)]
+pub struct ElfObject {
+        pub module:    Module,
+        pub sections:  Vec<Sec>,
+        pub segments:  Vec<Seg>,
+        pub imports:   Vec<Reloc>,
+        pub exports:   HashMap<String,u64>,
+        pub entry:     Option<u64>,
+        pub word_size: usize,
+        pub endian:    Endian,
+}
+
+impl ElfObject {
+        pub fn code_regions (&self) -> Vec<CodeRegion> {
+            exec_code_regions(&self.sections, &self.segments, &self.module.name)
+        }
+}
+
+/* Sections whose bytes lie in a segment's file image get those
+ * bytes, so that they see the relocations too. */
+pub fn refresh_sections (sections: &mut Vec<Sec>, segments: &Vec<Seg>) {
+        for sec in sections.iter_mut().filter(|s| s.alloc && !s.data.is_empty()) {
+            let end = sec.addr + sec.data.len() as u64;
+            if let Some(seg) = segments.iter()
+                                       .find(|g| g.segtype == SegType::Load
+                                                 && sec.addr >= g.addr
+                                                 && end <= g.addr + g.data.len() as u64) {
+                let off = (sec.addr - seg.addr) as usize;
+                let len = sec.data.len();
+                sec.data = seg.data[off..off + len].to_vec();
+            };
+        }
+}
+
+/* The sections and segments of the binary as they'd be in memory:
+ * slid to base if it's position-independent, and relocated. Each
+ * segment holds its p_filesz bytes; the rest of p_memsz is bss. */
+pub fn get_elf_addr_data (path: &str, base: u64) 
                       -> (Vec<Sec>,Vec<Seg>) {
+        let obj = load_elf_object(path, base);
+        (obj.sections, obj.segments)
+}
+
+pub fn load_elf_object (path: &str, base: u64) -> ElfObject {
+        let name = Path::new(path).file_name()
+                                  .map(|n| n.to_string_lossy().to_string())
+                                  .unwrap_or(path.to_string());
         let path = PathBuf::from(path);
         let file = match elf::File::open_path(&path) {
             Ok(f) => f,
             Err(e) => panic!("Error: {:?}",e),
         };
+        let bias = load_bias(&file, base);
+        if bias != 0 {
+            println!("[*] Position-independent; loading at {:08x}", bias);
+        };
+        /* the elf crate doesn't hand us segment contents, so we
+         * fetch them from the raw file ourselves */
+        let raw = load_file(path.to_str().unwrap());
         let mut segments : Vec<Seg> = Vec::new();
         for phdr in file.phdrs.iter() {
             println!("===> phdr.progtype = {}",phdr.progtype);
@@ -190,9 +321,14 @@ pub fn get_elf_addr_data (path: &str)
                 _ => { //SegType::Load | SegType::GnuStack => {
                     let seg = Seg {
                         segtype: segtype,
-                        addr: phdr.vaddr as u64,
+                        addr: (phdr.vaddr as u64).wrapping_add(bias),
                         memsz: phdr.memsz as usize,
                         perm: elf_to_uni_perms(phdr.flags.0),
+                        data: {
+                            let lo = (phdr.offset as usize).min(raw.len());
+                            let hi = (lo + phdr.filesz as usize).min(raw.len());
+                            raw[lo..hi].to_vec()
+                        },
                     };
                     segments.push(seg);
                 },/*
@@ -205,58 +341,238 @@ pub fn get_elf_addr_data (path: &str)
             }
         }
         
+        let ws     = elf_word_size(&file);
+        let endian = elf_endian(&file);
+        let relocs = read_relocs(&file, bias);
+        let imports = apply_relocs(&mut segments, &relocs, bias, ws, endian);
+        
         let mut sections : Vec<Sec> = Vec::new();
         
         for sec in file.sections.iter() {
             println!("===> sec.shdr: {:?}", sec.shdr);
+            /* .comment and friends sit at address 0, and aren't loaded */
+            let alloc = sec.shdr.flags.0 & SHF_ALLOC.0 != 0;
+            let addr  = if alloc { sec.shdr.addr.wrapping_add(bias) } else { sec.shdr.addr };
             let s = Sec {
                 name: sec.shdr.name.to_string(),
-                addr: sec.shdr.addr,
+                addr: addr,
                 data: sec.data.clone(),
                 perm: unicorn::Protection::from_bits((sec.shdr.flags.0 & 0xFF) as u32)
                       .unwrap_or(unicorn::Protection::from_bits(0).unwrap()),
+                alloc: alloc,
             };
             sections.push(s);
         }
-        (sections,segments)
+        /* .bss is SHT_NOBITS, and already zeroes */
+        refresh_sections(&mut sections, &segments);
+        let module = Module::new(&name, path.to_str().unwrap(), bias, &segments);
+        ElfObject {
+            module:    module,
+            sections:  sections,
+            segments:  segments,
+            imports:   imports,
+            exports:   elf_exports(&file, bias),
+            entry:     Some(file.ehdr.entry.wrapping_add(bias)),
+            word_size: ws,
+            endian:    endian,
+        }
+}
+/* The mode to start the engine in, going by the ELF header. 32-bit
+ * ARM binaries start in ARM mode, and interwork into Thumb. MIPS
+ * binaries may be of either byte order. */
+pub fn elf_machine_mode (path: &str) -> MachineMode {
+        let file = match elf::File::open_path(&PathBuf::from(path)) {
+            Ok(f) => f,
+            Err(e) => panic!("Error: {:?}",e),
+        };
+        if file.ehdr.machine.0 == EM_AARCH64 {
+            MachineMode::AARCH64
+        } else if file.ehdr.machine == EM_MIPS {
+            if file.ehdr.data == ELFDATA2MSB {
+                MachineMode::MIPS
+            } else {
+                MachineMode::MIPSEL
+            }
+        } else {
+            MachineMode::ARM
+        }
 }
+
+/* Look a symbol up in the binary's symbol tables, static or dynamic,
+ * as it would be once the binary is loaded at base */
+pub fn elf_symbol_addr (path: &str, name: &str, base: u64) -> Option<u64> {
+        /* firmware images have no symbols to look up */
+        let file = match elf::File::open_path(&PathBuf::from(path)) {
+            Ok(f) => f,
+            Err(_) if sniff_format(path) != ImageFormat::Elf => return None,
+            Err(e) => panic!("Error: {:?}",e),
+        };
+        let bias = load_bias(&file, base);
+        for sec in file.sections.iter() {
+            if sec.shdr.shtype != SHT_SYMTAB && sec.shdr.shtype != SHT_DYNSYM {
+                continue;
+            };
+            if let Ok(syms) = file.get_symbols(sec) {
+                for sym in syms {
+                    if sym.name == name && sym.value != 0 {
+                        return Some(sym.value.wrapping_add(bias));
+                    }
+                }
+            }
+        }
+        None
+}
+
 /* A struct to bundle together mutable machinery 
   * Each thread should have its own instance.
   */
-pub struct Machinery {
+pub struct Machinery <A: Isa = MachineMode> {
 //  pub rng: rand::ThreadRng,
-        pub cluster:  Vec<Engine>,
+        pub cluster:  Vec<Engine<A>>,
         //pub mangler: Mangler,
 }
 
+/* A unicorn engine that remembers which architecture it was built
+ * for, since register ids, word sizes and the like depend on it.
+ * Derefs to the underlying Unicorn. */
+pub struct Emu <A: Isa = MachineMode> {
+        uc: Box<Unicorn>,
+        pub mode: A,
+        stack: Option<MemRegion>,
+        stack_image: Option<Vec<u8>>,
+        start_regs: Option<Vec<u64>>,
+        settings: EmuSettings,
+}
+impl <A: Isa> Emu<A> {
+        pub fn new (mode: A) -> Result<Emu<A>, unicorn::Error> {
+            let uc_mode = match mode.uc_mode() {
+                Some(m) => m,
+                None    => return Err(unicorn::Error::MODE),
+            };
+            Unicorn::new(mode.uc_arch(), uc_mode)
+                .map(|uc| Emu { uc: Box::new(uc),
+                                mode: mode,
+                                stack: None,
+                                stack_image: None,
+                                start_regs: None,
+                                settings: EmuSettings::default() })
+        }
+        pub fn settings (&self) -> &EmuSettings {
+            &self.settings
+        }
+        /* The region hatch_chain lays chains out in. init_engine
+         * notes it once it's mapped, since whatever is mapped later,
+         * from a snapshot say, may well sit above it. */
+        pub fn stack (&self) -> MemRegion {
+            match self.stack {
+                Some(ref s) => s.clone(),
+                None        => find_stack(&self.uc),
+            }
+        }
+        /* A snapshot's stack replaces ours: the chain goes where its
+         * stack pointer was, and the stack goes back to what it held
+         * before each run, rather than being filled afresh. */
+        pub fn set_snapshot_stack (&mut self, region: MemRegion, image: Vec<u8>) {
+            self.stack = Some(region);
+            self.stack_image = Some(image);
+        }
+        pub fn stack_image (&self) -> Option<&Vec<u8>> {
+            self.stack_image.as_ref()
+        }
+        /* Where hatch_chain writes the packed chain. On a snapshot's
+         * stack, that's the saved stack pointer, plus payload_offset
+         * if we're given one; otherwise it's stack_entry's pick. */
+        pub fn payload_entry (&self) -> u64 {
+            match (&self.stack_image, &self.start_regs) {
+                (&Some(_), &Some(ref regs)) => {
+                    regs[self.mode.sp_num()] + self.settings.payload_offset.unwrap_or(0) as u64
+                },
+                _ => stack_entry(&self.stack(), self.settings.payload_offset),
+            }
+        }
+        /* Registers to start a chain from, rather than zero */
+        pub fn start_regs (&self) -> Option<&Vec<u64>> {
+            self.start_regs.as_ref()
+        }
+        pub fn set_start_regs (&mut self, regs: Vec<u64>) {
+            self.start_regs = Some(regs);
+        }
+        pub fn emu (&self) -> &Unicorn {
+            &self.uc
+        }
+        pub fn word_size (&self) -> usize {
+            self.mode.word_size()
+        }
+        pub fn endian (&self) -> Endian {
+            self.mode.endian()
+        }
+}
+impl <A: Isa> Deref for Emu<A> {
+        type Target = Unicorn;
+        fn deref (&self) -> &Unicorn {
+            &self.uc
+        }
+}
+impl <A: Isa> DerefMut for Emu<A> {
+        fn deref_mut (&mut self) -> &mut Unicorn {
+            &mut self.uc
+        }
+}
+
 /* Try to replace this with a safe data structure */
 /* Cf. the Rc<RefCell<_>> construction in the hatch_chain callback */
-pub struct Engine (Arc<RefCell<CpuARM>>);
+pub struct Engine <A: Isa = MachineMode> (Arc<RefCell<Emu<A>>>);
 //unsafe impl Send for Engine {}
-unsafe impl Send for Engine {}
-impl Engine {
-        pub fn new (uc: CpuARM) -> Engine {
+unsafe impl <A: Isa> Send for Engine<A> {}
+impl <A: Isa> Engine<A> {
+        pub fn new (uc: Emu<A>) -> Engine<A> {
             Engine(Arc::new(RefCell::new(uc)))
         }
-        pub fn unwrap (&self) -> Ref<CpuARM> {
+        pub fn unwrap (&self) -> Ref<Emu<A>> {
             (self.0).borrow() //&(*self.0)
         }
-        pub fn unwrap_mut (&mut self) -> RefMut<CpuARM> {
+        pub fn unwrap_mut (&mut self) -> RefMut<Emu<A>> {
             (self.0).borrow_mut()//&mut (*self.0)
         }
 }
 
-impl Machinery {
-        pub fn new (elf_path: &str, 
-                                mode: MachineMode,
+impl <A: Isa> Machinery<A> {
+        /* libs are extra objects to load and link alongside the
+         * image, each at its own address */
+        pub fn new (image: &ImageSpec, 
+                                libs: &Vec<(String,u64)>,
+                                mode: A,
+                                settings: &EmuSettings,
                                 uc_num: usize,
-                                debug: bool) -> Machinery {
-            let (elf_sections,elf_segments) = get_elf_addr_data(elf_path);
+                                debug: bool) -> Machinery<A> {
+            let objects = load_process_image(image, libs, mode);
+            /* Unless we're told where to look, the hooks reach every
+             * object we load, so that nothing a chain does in a
+             * library goes unseen */
+            let mut settings = settings.clone();
+            if settings.hook_base == BASE_ADDR && settings.hook_size == MEM_SIZE {
+                for o in &objects {
+                    settings.cover(o.module.lo, o.module.hi);
+                }
+            };
+            for o in &objects {
+                if o.module.lo < settings.hook_base || o.module.hi > settings.hook_end() {
+                    println!("[!] {} at {:08x}-{:08x} is out of the reach of the hooks at {:08x}-{:08x}",
+                             o.module.name, o.module.lo, o.module.hi,
+                             settings.hook_base, settings.hook_end());
+                };
+            }
+            let elf_sections : Vec<Sec> = objects.iter()
+                                                 .flat_map(|o| o.sections.iter().cloned())
+                                                 .collect();
+            let elf_segments : Vec<Seg> = objects.iter()
+                                                 .flat_map(|o| o.segments.iter().cloned())
+                                                 .collect();
                                                                 
             let mut cluster = Vec::new();
             for i in 0..uc_num {
                 println!("spinning up engine #{}",i);
-                let mut uc = init_engine(&elf_sections, &elf_segments, mode);
+                let mut uc = init_engine(&elf_sections, &elf_segments, mode, &settings);
                 //if debug {
                 //  add_debug_hooks(&mut uc);
                 //}
@@ -266,23 +582,35 @@ impl Machinery {
                 cluster: cluster, 
             }
         }
+
+        pub fn load_snapshot (&mut self, snap: &Snapshot) {
+            for engine in self.cluster.iter_mut() {
+                restore_snapshot(&mut engine.unwrap_mut(), snap);
+            }
+        }
 }
 
-pub fn init_engine <'a,'b> (sections: &Vec<Sec>,//<(u64, Vec<u8>)>,
-                            segments: &Vec<Seg>,
-                            mode: MachineMode)
-                            -> unicorn::CpuARM {
-    let uc = CpuARM::new(mode.uc())
-        .expect("failed to create emulator engine");
+pub fn init_engine <A: Isa> (sections: &Vec<Sec>,//<(u64, Vec<u8>)>,
+                             segments: &Vec<Seg>,
+                             mode: A,
+                             settings: &EmuSettings)
+                             -> Emu<A> {
+    let mut uc = Emu::new(mode)
+        .unwrap_or_else(|e| panic!("failed to create emulator engine for {:?}: {:?}",
+                                   mode, e));
+    uc.settings = settings.clone();
     
     let mo = uc.query(unicorn::Query::MODE).unwrap();
-    println!("[*] Initialized. Mode: {:?}, {:?}: {:?}",
-             mode, mode.uc(), mo);
+    println!("[*] Initialized. Mode: {:?}, {:?}, {:?}: {:?}",
+             mode, mode.uc_arch(), mode.uc_mode(), mo);
     // next: map text and rodata separately
     // we need a smoother interface between the elf module and unicorn
     // TODO: set stack to actual stack segment
-    /* Allocate space for comment, metadata, etc. */
-    uc.mem_map(0, 0x1000, PROT_READ);
+    /* Allocate space for comment, metadata, etc., unless the image
+     * wants page zero itself, as firmware with its vectors there does */
+    if !segments.iter().any(|s| s.segtype == SegType::Load && s.floor() < 0x1000) {
+        uc.mem_map(0, 0x1000, PROT_READ);
+    };
     /* This shouldn't be hardcoded. Figure out where in elf to get info. */
     for ref seg in segments.iter() {
         /* enforce w xor x */
@@ -302,6 +630,15 @@ pub fn init_engine <'a,'b> (sections: &Vec<Sec>,//<(u64, Vec<u8>)>,
                 uc.mem_map(seg.floor(), seg.size(), perm)
                     .expect(&format!("Failed to map segment. Size: {:x}; Addr: {:x}, Perm: {:?}",
                                      seg.memsz, seg.addr, seg.perm));
+                /* p_filesz bytes from the file, then zeroes up to p_memsz */
+                let filesz = seg.data.len().min(seg.memsz);
+                uc.mem_write(seg.addr, &seg.data[..filesz])
+                    .expect(&format!("Failed to write segment at {:x}", seg.addr));
+                if seg.memsz > filesz {
+                    uc.mem_write(seg.addr + filesz as u64,
+                                 &vec![0; seg.memsz - filesz])
+                        .expect(&format!("Failed to zero bss at {:x}", seg.addr));
+                };
             },
             _ => {
                 println!("[-] Not mapping segment {:?}",seg);
@@ -317,27 +654,32 @@ pub fn init_engine <'a,'b> (sections: &Vec<Sec>,//<(u64, Vec<u8>)>,
         }
         */
     }
-    for ref sec in sections.iter() {
-        //let &(addr, ref data) = pair
-        println!("[*] Writing section named {}, from address {:08x}, with size of {:08x} bytes",
-        sec.name, sec.addr, sec.size());
-        match uc.mem_write(sec.addr, &sec.data) {
-            Ok(x) => { println!("[+] Ok({:?}), wrote {}",x,sec.name); },
-            Err(x) => { println!("[x] Failed to write {}, error: {:?}", sec.name, x);},
-        }
+    /* The segments carry the whole image. Sections are only listed,
+     * so we can see what went where. */
+    for ref sec in sections.iter().filter(|s| s.alloc) {
+        println!("[*] Section named {}, at address {:08x}, with size of {:08x} bytes",
+        sec.name, sec.addr, sec.data.len());
     }
     //uc.mem_map(BASE_STACK, STACK_SIZE, PROT_READ|PROT_WRITE)
     //    .expect("Failed to map stack memory");
-    let regions = uc.mem_regions().unwrap();
-    let mut bottom = 0;
-    for region in &regions {
-        if region.end > bottom { bottom = region.end + 1; }; 
-    }
-    println!("[*] Mapping stack space of 0x{:x} bytes at 0x{:x}...", STACK_SIZE, bottom);
-    let res = uc.mem_map(bottom, STACK_SIZE, PROT_READ|PROT_WRITE);
+    /* where we're told, or else just above everything else */
+    let bottom = match settings.stack_base {
+        Some(b) => b,
+        None    => {
+            let regions = uc.mem_regions().unwrap();
+            let mut bottom = 0;
+            for region in &regions {
+                if region.end > bottom { bottom = region.end + 1; }; 
+            }
+            bottom
+        },
+    };
+    let stack_size = (settings.stack_size + 0xFFF) & !0xFFF;
+    println!("[*] Mapping stack space of 0x{:x} bytes at 0x{:x}...", stack_size, bottom);
+    let res = uc.mem_map(bottom, stack_size, PROT_READ|PROT_WRITE);
     match res {
         Ok(x) => { println!("[+] Ok({:?}), allocated stack",x); },
-        Err(x) => { println!("[x] Error allocating stack: {:?}", x); }
+        Err(x) => { panic!("[x] Error allocating stack at {:x}: {:?}", bottom, x); }
     }
     let regions = uc.mem_regions().unwrap();
     println!("REGIONS:\n{:?}",regions);
@@ -345,8 +687,11 @@ pub fn init_engine <'a,'b> (sections: &Vec<Sec>,//<(u64, Vec<u8>)>,
         println!("{:08x} -- {:08x} ({:?})", region.begin, region.end, region.perms);
     }
     println!("ok, engine initialized");
-    let stack = find_stack(&uc);
-    println!("Stack found: {:08x} -- {:08x} ({:?})", stack.begin, stack.end, stack.perms);
+    let stack = MemRegion { begin: bottom,
+                            end:   bottom + stack_size as u64 - 1,
+                            perms: PROT_READ|PROT_WRITE };
+    println!("Stack at: {:08x} -- {:08x} ({:?})", stack.begin, stack.end, stack.perms);
+    uc.stack = Some(stack);
     /* the false param says don't restrict the dump to null terminated strings.
      * true for restrict to null terminated. */
     let stringdump = dump_strings(&uc, 4, false);
@@ -360,7 +705,7 @@ pub fn init_engine <'a,'b> (sections: &Vec<Sec>,//<(u64, Vec<u8>)>,
     uc
 }
 
-pub fn find_stack (uc: &CpuARM) -> MemRegion {
+pub fn find_stack (uc: &Unicorn) -> MemRegion {
     let mut bottom : Option<u64> = None;
     let mut stack : Option<MemRegion> = None;
     let regions = &uc.mem_regions().unwrap();
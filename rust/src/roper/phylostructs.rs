@@ -5,7 +5,6 @@ extern crate rand;
 extern crate unicorn;
 extern crate time;
 extern crate chrono;
-extern crate rustc_serialize;
 extern crate regex;
 extern crate bio;
 extern crate indextree;
@@ -16,17 +15,14 @@ use std::cmp::Ordering::*;
 use self::regex::*;
 use self::chrono::prelude::*;
 use self::chrono::offset::LocalResult;
-use std::collections::{BTreeMap};
 use std::hash::*;
-use std::iter::repeat;
-use self::rustc_serialize::json::{self, Json, ToJson};
 use rand::*;
 use unicorn::*;
-use capstone::CsMode;
+use capstone::{CsMode,CsArch};
 use std::fmt::{Display,format,Formatter,Result};
 use std::collections::{HashSet,HashMap};
 use std::cmp::*;
-use std::sync::RwLock;
+use std::sync::{RwLock,Arc};
 use std::ops::{Index,IndexMut};
 use std::fs::{DirBuilder,File,OpenOptions};
 use std::io::prelude::*;
@@ -42,8 +38,14 @@ use roper::util::*;
 use roper::evolve::*;
 use roper::hatchery::*;
 use roper::ontostructs::*;
+use roper::isa::Isa;
 use roper::interactive::*;
 use roper::statistics::*;
+use roper::profiler::*;
+use roper::gadgetdb::*;
+use roper::loader::{PIE_BASE,Module,ImageSpec,mod_offset};
+use roper::firmware::ImageFormat;
+use roper::arm::{Condition,flags_str};
 pub const MAX_VISC : i32 = 100;
 pub const MIN_VISC : i32 = 0;
 pub const VISC_DROP_THRESH : i32 = 10;
@@ -52,58 +54,98 @@ pub const MAX_FIT : f32 = 1.0;
 const DEFAULT_MODE : MachineMode = MachineMode::ARM;
 
 
-#[derive(PartialEq,Debug,Clone)]
+/* An executable stretch of the binary to harvest gadgets from.
+ * Usually a section, but stripped binaries may only give us
+ * the segment. */
+#[derive(PartialEq,Debug,Clone,Serialize,Deserialize)]
+pub struct CodeRegion {
+        pub name : String,
+        pub addr : u64,
+        pub data : Vec<u8>,
+        pub module : String, /* the object it belongs to */
+}
+
+#[derive(PartialEq,Debug,Clone,Serialize,Deserialize)]
 pub struct Params {
+        pub bad_bytes        : Vec<u8>,
         pub binary_path      : String,
         pub brood_size       : usize,
         pub class_masks      : Vec<(u32,usize)>,
-        pub code             : Vec<u8>,
-        pub code_addr        : u32,
+        pub code             : Vec<CodeRegion>,
         pub comment          : String,
-        pub constants        : Vec<u32>,
+        pub constants        : Vec<u64>,
+        #[serde(with = "::roper::serial::float")]
         pub crash_penalty    : f32,
+        #[serde(with = "::roper::serial::float")]
         pub crossover_rate   : f32,
         pub csv_path         : String,
+        #[serde(with = "::roper::serial::float")]
         pub cuckoo_rate        : f32,
         pub data             : Vec<Vec<u8>>,
-        pub data_addrs       : Vec<u32>,
+        pub data_addrs       : Vec<u64>,
         pub date_dir         : String,
+        pub deref_size       : usize, /* bytes of memory read behind each register */
+        #[serde(with = "::roper::serial::float")]
         pub edi_toggle_rate  : f32,
+        pub entry            : Option<u64>, /* for images that don't give one */
         pub fatal_crash      : bool,
+        #[serde(with = "::roper::serial::float")]
         pub fit_goal         : f32,
         pub fitness_sharing  : bool,
         pub homologous_crossover: bool,
+        pub hook_base        : u64, /* the hooks see hook_base..hook_base+hook_size */
+        pub hook_size        : usize,
         pub host_port        : String,
+        pub image_format     : ImageFormat,
+        #[serde(with = "::roper::serial::float")]
         pub initial_edi_rate : f32,
         pub inregs           : Vec<usize>,
         pub io_targets       : IoTargets,
         pub label            : String,
+        pub libraries        : Vec<(String,u64)>, /* extra objects, and where to load them */
+        pub load_base        : u64, /* for PIE binaries, shared objects and raw images */
         pub log_dir          : String,
         pub max_iterations  : usize,
         pub max_len          : usize,
+        pub max_packed_len   : usize, /* in bytes, 0 for no limit */
         pub max_start_len    : usize,
+        pub max_steps        : usize, /* instructions per run of a chain */
+        #[serde(with = "::roper::serial::float")]
         pub migration        : f32,
         pub min_start_len    : usize,
+        pub mode             : MachineMode,
+        pub modules          : Vec<Module>, /* what got loaded where */
         pub num_demes        : usize,
         pub outregs          : Vec<usize>,
+        pub payload_offset   : Option<usize>, /* where the chain goes, in bytes from stack_base or a snapshot's sp */
         pub population_size  : usize,
         pub random_override  : bool,
         pub reward_visitation_diversity : bool,
+        #[serde(with = "::roper::serial::float")]
         pub sample_ratio     : f32,
         pub save_period      : usize, 
         pub season_divisor    : usize,
+        pub seed             : u64, /* master seed for every RNG in the run */
         pub selection_method : SelectionMethod,
+        pub snapshot         : Option<String>, /* a core file or maps dump to run chains in */
+        pub stack_base       : Option<u64>, /* None to put the stack above the image */
+        pub stack_fill       : Vec<u8>, /* repeated over the stack before each run */
+        #[serde(with = "::roper::serial::float")]
         pub stack_input_sampling : f32,
+        pub stack_size       : usize,
+        pub stop_addr        : u64,
         pub t_size           : usize,
         pub test_targets     : IoTargets,
         pub threads          : usize,
         pub timestamp        : String,
+        #[serde(with = "::roper::serial::pairs")]
         pub training_ht      : HashMap<Vec<i32>,usize>,
         pub ttl              : usize,
         pub use_edis         : bool,
         pub use_viscosity    : bool,
         pub use_dynamic_crash_penalty : bool,
         pub verbose          : bool,
+        #[serde(with = "::roper::serial::float")]
         pub visitation_diversity_weight : f32,
 /*  pub ro_data_data     : Vec<u8>, */
 }
@@ -116,30 +158,67 @@ impl Display for Params {
 
             s.push_str(&format!("{} COMMENT: {}\n", rem, self.label));
         
+            s.push_str(&format!("{} bad_bytes: {}\n", rem, hexvec_(&self.bad_bytes.iter().map(|&b| b as u64).collect())));
             s.push_str(&format!("{} binary_path: {}\n", rem, self.binary_path));
             s.push_str(&format!("{} brood_size: {}\n", rem, self.brood_size));
             s.push_str(&format!("{} class_masks: {}\n", rem, class_masks_to_string(&self.class_masks)));
             s.push_str(&format!("{} crash_penalty: {}\n", rem, self.crash_penalty));
             s.push_str(&format!("{} crossover_rate: {}\n", rem, self.crossover_rate));
             s.push_str(&format!("{} cuckoo_rate: {}\n", rem, self.cuckoo_rate));
+            s.push_str(&format!("{} deref_size: {}\n", rem, self.deref_size));
             s.push_str(&format!("{} edi_toggle_rate: {}\n", rem, self.edi_toggle_rate));
+            if let Some(e) = self.entry {
+                s.push_str(&format!("{} entry: {:x}\n", rem, e));
+            };
             s.push_str(&format!("{} fatal_crash: {}\n", rem, self.fatal_crash));
             s.push_str(&format!("{} fit_goal: {}\n", rem, self.fit_goal));
             s.push_str(&format!("{} fitness_sharing: {}\n", rem, self.fitness_sharing));
             s.push_str(&format!("{} homologous_crossover: {:?}\n", rem, self.homologous_crossover));
+            s.push_str(&format!("{} hook_base: {:x}\n", rem, self.hook_base));
+            s.push_str(&format!("{} hook_size: {:x}\n", rem, self.hook_size));
+            s.push_str(&format!("{} image_format: {:?}\n", rem, self.image_format));
             s.push_str(&format!("{} initial_edi_rate: {}\n", rem, self.initial_edi_rate));
             s.push_str(&format!("{} inregs: {:?}\n", rem, self.inregs));
             s.push_str(&format!("{} label: {}\n", rem, self.label));
+            s.push_str(&format!("{} libraries: {}\n", rem,
+                                self.libraries
+                                    .iter()
+                                    .map(|&(ref p, b)| format!("{}@{:x}", p, b))
+                                    .collect::<Vec<String>>()
+                                    .join(" ")));
+            s.push_str(&format!("{} load_base: {:x}\n", rem, self.load_base));
             s.push_str(&format!("{} max_iterations: {}\n", rem, self.max_iterations));
             s.push_str(&format!("{} max_len: {}\n", rem, self.max_len));
+            s.push_str(&format!("{} max_packed_len: {}\n", rem, self.max_packed_len));
             s.push_str(&format!("{} max_start_len: {}\n", rem, self.max_start_len));
+            s.push_str(&format!("{} max_steps: {}\n", rem, self.max_steps));
             s.push_str(&format!("{} migration: {}\n", rem, self.migration));
             s.push_str(&format!("{} min_start_len: {}\n", rem, self.min_start_len));
+            s.push_str(&format!("{} mode: {:?}\n", rem, self.mode));
             s.push_str(&format!("{} num_demes: {}\n", rem, self.num_demes));
             s.push_str(&format!("{} outregs: {:?}\n", rem, self.outregs));
+            if let Some(o) = self.payload_offset {
+                s.push_str(&format!("{} payload_offset: {}\n", rem, o));
+            };
             s.push_str(&format!("{} population_size: {}\n", rem, self.population_size));
             s.push_str(&format!("{} random_override: {}\n", rem, self.random_override));
+            s.push_str(&format!("{} seed: {}\n", rem, self.seed));
             s.push_str(&format!("{} selection_method: {:?}\n", rem, self.selection_method));
+            if let Some(ref p) = self.snapshot {
+                s.push_str(&format!("{} snapshot: {}\n", rem, p));
+            };
+            if let Some(b) = self.stack_base {
+                s.push_str(&format!("{} stack_base: {:x}\n", rem, b));
+            };
+            if !self.stack_fill.is_empty() {
+                s.push_str(&format!("{} stack_fill: {}\n", rem,
+                                    self.stack_fill
+                                        .iter()
+                                        .map(|b| format!("{:02x}", b))
+                                        .collect::<String>()));
+            };
+            s.push_str(&format!("{} stack_size: {:x}\n", rem, self.stack_size));
+            s.push_str(&format!("{} stop_addr: {:x}\n", rem, self.stop_addr));
             s.push_str(&format!("{} t_size: {}\n", rem, self.t_size));
             s.push_str(&format!("{} ttl: {}\n", rem, self.ttl));
             s.push_str(&format!("{} threads: {}\n", rem, self.threads));
@@ -157,11 +236,11 @@ impl Params {
             Params {
                 // don't hardcode size and numbers of in/out regs.
                 // make this dependent on the data
+                bad_bytes:        Vec::new(),
                 binary_path:      "".to_string(),
                 brood_size:       2,
                 class_masks:      Vec::new(),
                 code:             Vec::new(),
-                code_addr:        0,
                 comment:          String::new(),
                 constants:        Vec::new(),
                 crash_penalty:    0.2,
@@ -171,32 +250,50 @@ impl Params {
                 data:             Vec::new(),
                 data_addrs:       Vec::new(),
                 date_dir:         datepath.clone(),
+                deref_size:       DEREF_SIZE,
                 edi_toggle_rate:  0.05,
+                entry:            None,
                 fatal_crash:      false,
                 fit_goal:         0.1,  
                 fitness_sharing:  true,
                 homologous_crossover: true,
+                hook_base:        BASE_ADDR,
+                hook_size:        MEM_SIZE,
                 host_port:        "127.0.0.1:8888".to_string(),
+                image_format:     ImageFormat::Auto,
                 initial_edi_rate: 0.1,
                 inregs:           vec![1,2,3,4],
                 io_targets:       IoTargets::new(TargetKind::PatternMatch),
                 label:            label.to_string(),
+                libraries:        Vec::new(),
+                load_base:        PIE_BASE,
                 log_dir:          "UNSET".to_string(),
                 max_iterations:   800000,
                 max_len:          256,
+                max_packed_len:   0,
                 max_start_len:    32,
+                max_steps:        MAX_STEPS,
                 migration:        0.05,
                 min_start_len:    2,
+                mode:             DEFAULT_MODE,
+                modules:          Vec::new(),
                 num_demes:        4,
                 outregs:          vec![5,6,7],
+                payload_offset:   None,
                 population_size:  2048,
                 random_override:  false,
                 reward_visitation_diversity: true,
                 sample_ratio:     1.0,
                 save_period:      10000,
                 season_divisor:    4,
+                seed:             thread_rng().gen::<u64>(),
                 selection_method: SelectionMethod::Tournament,
+                snapshot:         None,
+                stack_base:       None,
+                stack_fill:       Vec::new(),
                 stack_input_sampling: 0.0,
+                stack_size:       STACK_SIZE,
+                stop_addr:        STOP_ADDR,
                 t_size:           4,
                 test_targets:     IoTargets::new(TargetKind::PatternMatch),
                 threads:          5,
@@ -230,6 +327,25 @@ impl Params {
             }
         }
 
+        pub fn image (&self) -> ImageSpec {
+            ImageSpec::new(&self.binary_path, self.image_format, self.load_base, self.entry)
+        }
+
+        pub fn emu_settings (&self) -> EmuSettings {
+            EmuSettings {
+                max_steps:      self.max_steps,
+                stop_addr:      self.stop_addr,
+                hook_base:      self.hook_base,
+                hook_size:      self.hook_size,
+                deref_size:     self.deref_size,
+                stack_base:     self.stack_base,
+                stack_size:     self.stack_size,
+                payload_offset: self.payload_offset,
+                stack_fill:     self.stack_fill.clone(),
+                watch:          self.io_targets.watched(),
+            }
+        }
+
         pub fn set_log_dir (&mut self, dir: &str) {
             let ddir = format!("{}/{}/{}",
                                dir, 
@@ -252,9 +368,8 @@ fn class_masks_to_string (class_masks: &Vec<(u32,usize)>) -> String {
     s
 }
 
-pub fn name (syllables: usize) -> String {
+pub fn name (syllables: usize, rng: &mut RoperRng) -> String {
 
-    let mut rng = rand::thread_rng();
     let consonants = vec!['b','c','d','f','g',
                           'h','j','k','l','m',
                           'n','p','q','r','s',
@@ -324,46 +439,44 @@ impl Index<usize> for Fingerprint {
         }
 }
 
-#[derive(Clone,Debug)]
+#[derive(Clone,Debug,Serialize,Deserialize)]
 pub struct Clump {
         pub sp_delta:    i32, // how much does the sp change by?
         pub ret_offset:  usize, // how far down is the next address?
         pub exchange:    bool, // BX instruction? can we change mode?
         pub mode:        MachineMode,
         pub ttl:         usize,
-        pub ret_addr:    u32,
-        pub words:       Vec<u32>,
+        pub ret_addr:    u64,
+        pub words:       Vec<u64>,
         pub viscosity:   i32,
         pub input_slots: Vec<(usize,usize)>, // (offset, input#)
         pub link_age:    i32,
+        #[serde(with = "::roper::serial::float_opt")]
         pub link_fit:    Option<f32>, // 
         pub enabled:     bool, // disabling a clump makes it into an explicit intron
+        pub section:     String, // the code region it was reaped from
+        pub module:      String, // and the object that region belongs to
+        pub kind:        GadgetKind,
+        pub profile:     Option<Arc<Profile>>, // what it seems to do
+        pub flags_needed: u8, // NZCV read by conditional insts (ARM only)
+        pub flags_set:   u8, // NZCV the gadget may set (ARM only)
+}
+
+/* How a gadget hands control to the next one. A Ret pops the next
+ * address straight into PC, and a CondRet does the same if its
+ * condition holds, and falls through otherwise. The others load a
+ * register from the stack and jump through it (bx rN, mov pc, rN)
+ * or call through it (blx rN), so the slot holding the next address
+ * may sit in the middle of the words the gadget pops, rather than at
+ * the end. */
+#[derive(Eq,PartialEq,Debug,Clone,Copy,Serialize,Deserialize)]
+pub enum GadgetKind {
+        Ret,
+        CondRet(Condition), // only returns if the condition holds
+        JumpReg,
+        CallReg,
 }
 
-// UPDATE THIS to_json with new fields TODO
-impl ToJson for Clump {
-        fn to_json(&self) -> Json {
-            let mut b = BTreeMap::new();
-            b.insert("sp_delta".to_string(), self.sp_delta.to_json());
-            b.insert("ret_offset".to_string(), self.ret_offset.to_json());
-            b.insert("exchange".to_string(), self.exchange.to_json()); 
-            b.insert("mode".to_string(),format!("{:?}",self.mode).to_json());
-            b.insert("ret_addr".to_string(),self.ret_addr.to_json());
-            b.insert("words".to_string(), self.words.to_json());
-            b.insert("viscosity".to_string(), self.viscosity.to_json());
-            b.insert("link_fit".to_string(),
-                format!("{:?}",self.link_fit).to_json());
-            b.insert("enabled".to_string(), self.enabled.to_json());
-            Json::Object(b)
-        }
-}
-/* TODO
-impl FromJson for Clump {
-        fn from_json (json: Json) -> Self {
-            
-        }
-}
-*/
 impl Display for Clump {
         fn fmt (&self, f: &mut Formatter) -> Result {
             let mut s = String::new();
@@ -371,6 +484,15 @@ impl Display for Clump {
             s.push_str("CLUMP:\n");
             s.push_str(&format!("enabled:    {:?}\n", self.enabled));
             s.push_str(&format!("mode:       {:?}\n", self.mode));
+            s.push_str(&format!("section:    {}\n", self.section));
+            s.push_str(&format!("module:     {}\n", self.module));
+            s.push_str(&format!("kind:       {:?}\n", self.kind));
+            s.push_str(&format!("flags:      needs {} sets {}\n", 
+                                flags_str(self.flags_needed),
+                                flags_str(self.flags_set)));
+            if let Some(ref p) = self.profile {
+                s.push_str(&format!("profile:    {}\n", p));
+            }
             s.push_str(&format!("sp_delta:   0x{:x}\n", self.sp_delta));
             s.push_str(&format!("ret_offset: 0x{:x}\n", self.ret_offset));
             s.push_str(&format!("viscosity:  %{}\n", vp * 100.0));
@@ -401,6 +523,12 @@ impl Default for Clump {
                 link_age:   0,
                 link_fit:   None, // (MAX_FIT/2),
                 enabled:    true,
+                section:    String::new(),
+                module:     String::new(),
+                kind:       GadgetKind::Ret,
+                profile:    None,
+                flags_needed: 0,
+                flags_set:  0,
             }
         }
 }
@@ -411,34 +539,61 @@ impl Clump {
         pub fn size (&self) -> usize {
             self.words.len()
         }
+        /* Where word k of the clump lands on the stack, relative
+         * to the slot just below the gadget's entry SP. Word 0, the
+         * address, lives wherever the previous gadget reads it from.
+         * The remaining words fill the sp_delta slots the gadget
+         * pops, skipping the one holding the next gadget's address. */
+        pub fn slot_of (&self, k: usize) -> usize {
+            if k < self.ret_offset { k } else { k + 1 }
+        }
+        /* The inverse of slot_of. None for the next gadget's slot. */
+        pub fn word_at_slot (&self, k: usize) -> Option<usize> {
+            if k < self.ret_offset { 
+                Some(k) 
+            } else if k == self.ret_offset { 
+                None 
+            } else { 
+                Some(k - 1) 
+            }
+        }
         pub fn gadlen (&self) -> usize {
             (self.ret_addr - self.words[0]) as usize
         }
         pub fn visc (&self) -> i32 {
             self.viscosity
         }
-        pub fn addr (&self) -> u32 {
+        pub fn addr (&self) -> u64 {
             self.words[0]
         }
-        pub fn entry (&self) -> u32 { 
+        pub fn entry (&self) -> u64 { 
             self.addr()
         }
-        pub fn exit (&self) -> u32 {
+        pub fn exit (&self) -> u64 {
             self.ret_addr
         }
         pub fn sicken (&mut self) {
             self.link_fit = Some(MAX_FIT);
         }
+        /* Whether every word we'd pack for this clump is free of bad
+         * bytes. Thumb entry points get packed with their LSB set. */
+        pub fn is_clean (&self, bad: &Vec<u8>) -> bool {
+            let width = self.mode.word_size();
+            self.words.iter().enumerate().all(|(i,&w)| {
+                let w = if i == 0 { self.mode.entry_addr(w) } else { w };
+                word_is_clean(w, width, bad)
+            })
+        }
 }
 pub trait Stack <T> {
         fn push (&mut self, t: T);
         fn pop (&mut self) -> Option<T>;
 }
-impl Stack <u32> for Clump {
-        fn push (&mut self, t: u32) {
+impl Stack <u64> for Clump {
+        fn push (&mut self, t: u64) {
             self.words.push(t);
         }
-        fn pop (&mut self) -> Option<u32> {
+        fn pop (&mut self) -> Option<u64> {
             self.words.pop()
         }
 }
@@ -453,13 +608,13 @@ fn index_opt (&self, t: u32) -> Option<usize> {
 }
 */
 impl Index <usize> for Clump {
-        type Output = u32;
-        fn index (&self, index: usize) -> &u32 {
+        type Output = u64;
+        fn index (&self, index: usize) -> &u64 {
             &(self.words[index])
         }
 }
 impl IndexMut <usize> for Clump {
-        fn index_mut (&mut self, index: usize) -> &mut u32 {
+        fn index_mut (&mut self, index: usize) -> &mut u64 {
             &mut (self.words[index])
         }
 }
@@ -468,16 +623,18 @@ pub fn saturated (gad: &Clump) -> bool {
         gad.words.len() as i32 == gad.sp_delta
 }
 
-/* why isn't this a trait? */
-fn concatenate (clumps: &Vec<Clump>) -> Vec<u32> {
-        let s : usize = clumps.iter()
-                              .map(|ref x| x.words.len())
-                              .sum();
-        let mut c = vec![0; s];
-        let mut rto = 0 as usize;
+/* Where each word of each clump lands on the stack, as
+ * (clump index, word index, slot, value), in packing order. The
+ * value is the word as it will be packed, with the Thumb bit set
+ * wherever a gadget address needs it. concatenate() and the
+ * exporters both work from this, so they can't disagree. */
+pub fn layout (clumps: &Vec<Clump>) -> Vec<(usize, usize, usize, u64)> {
+        let mut l = Vec::new();
+        let mut rto = 0 as usize; // where this gadget's address goes
+        let mut base = 0 as usize; // the slot below its entry SP
         let mut exchange = false;
         let mut i = 0;
-        for ref gad in clumps {
+        for (j, gad) in clumps.iter().enumerate() {
             /* for debugging */
             /*****************/
             if !saturated(gad) {
@@ -488,37 +645,65 @@ fn concatenate (clumps: &Vec<Clump>) -> Vec<u32> {
             if !gad.enabled && i > 0 { /* at least one clump should be enabled */
                 continue;
             }
-            let t : usize = rto + gad.sp_delta as usize;
-            &c[rto..t].clone_from_slice(&(gad.words));
-            if exchange && (gad.mode == MachineMode::THUMB) {
-                /* If we BX, the LSB of the addr decides machine mode */
-                c[rto] |= 1;
+            /* If we BX, or if this is the entry point, the LSB of the
+             * addr decides machine mode. hatch_chain relies on this to
+             * start the chain in the right mode. */
+            let mut addr = gad.words[0];
+            if exchange || i == 0 {
+                addr = gad.mode.entry_addr(addr);
+            }
+            /* For a Ret, rto == base, and this reduces to laying the
+             * words out end to end. */
+            l.push((j, 0, rto, addr));
+            for k in 1..gad.words.len() {
+                l.push((j, k, base + gad.slot_of(k), gad.words[k]));
             }
-            rto += gad.ret_offset as usize;
+            rto = base + gad.ret_offset as usize;
+            base += gad.sp_delta as usize;
             exchange = gad.exchange;
             i += 1;
         }
-        c[..rto].to_vec()
+        l
+}
+
+/* why isn't this a trait? */
+fn concatenate (clumps: &Vec<Clump>) -> Vec<u64> {
+        let l = layout(clumps);
+        let end = l.iter().map(|&(_,_,t,_)| t + 1).max().unwrap_or(0);
+        let mut c = vec![0; end];
+        for &(_, _, t, w) in &l {
+            c[t] = w;
+        }
+        c
 } /* program seems to hang here, sometimes, with futex. not sure why. FIXME. */
 
-#[derive(Clone,Debug)]
+#[derive(Clone,Debug,Serialize,Deserialize)]
 pub struct Chain {
         pub index: usize, // handy to store a reference to this here
         pub clumps: Vec<Clump>, //Arr1K<Clump>, //[Clump; MAX_CHAIN_LENGTH], 
         //pub packed: Vec<u8>,
+        #[serde(with = "::roper::serial::float_opt")]
         pub fitness: Option<f32>,
+        #[serde(with = "::roper::serial::float_opt")]
         pub ab_fitness: Option<f32>, // unshared
+        #[serde(with = "::roper::serial::floats")]
         pub p_fitness:  Vec<f32>,
         pub generation: u32,
         pub input_slots: Vec<(usize,usize)>,
         pub verbose_tag: bool,
         pub crashes: Vec<usize>,
+        #[serde(with = "::roper::serial::float")]
         pub ratio_run: f32,
         pub season: usize,
+        #[serde(with = "::roper::serial::genealogy")]
         pub genealogy: Arena<(String, f32, f32, bool)>,
+        #[serde(with = "::roper::serial::float")]
         pub visitation_diversity: f32,
-        pub visited_map: HashMap<Problem, Vec<u32>>,
-        pub register_map: HashMap<Problem, (Vec<u32>,Vec<Option<Vec<u8>>>)>,
+        #[serde(with = "::roper::serial::pairs")]
+        pub visited_map: HashMap<Problem, Vec<u64>>,
+        #[serde(with = "::roper::serial::pairs")]
+        pub register_map: HashMap<Problem, (Vec<u64>,Vec<Option<Vec<u8>>>)>,
+        #[serde(with = "::roper::serial::float_opt")]
         pub runtime: Option<f32>,
         pub name: String,
         i: usize,
@@ -583,6 +768,9 @@ impl Display for Chain {
                     };
                     i += 1;
                 }
+                if !clump.module.is_empty() {
+                    s.push_str(&format!(" [{}]", clump.module));
+                }
                 s.push_str("\n");
             }
             s.push_str("Packed:\n");
@@ -618,7 +806,7 @@ impl Default for Chain {
                 visitation_diversity: 0.0,
                 visited_map: HashMap::new(),
                 register_map: HashMap::new(),
-                name: name(4),
+                name: String::new(),
                 i: 0,
             }
         } 
@@ -662,18 +850,58 @@ impl IndexMut <usize> for Chain {
 
 impl Chain {
         /* NB: a Chain::new(c) takes ownership of its clump vec */
-        pub fn new (clumps: Vec<Clump>) -> Chain {
+        pub fn new (clumps: Vec<Clump>, rng: &mut RoperRng) -> Chain {
             let mut chain = Chain {
                 clumps: clumps,
                 ..Default::default()
             };
-            chain.name = name(4);
+            chain.name = name(4, rng);
             chain.collate_input_slots();
             chain
         }
 
         pub fn pack (&self) -> Vec<u8> {
-            pack_word32le_vec(&concatenate(&self.clumps))
+            pack_words(&self.stack_words(), self.word_size(), self.endian())
+        }
+
+        /* The payload as stack words, before packing into bytes. */
+        pub fn stack_words (&self) -> Vec<u64> {
+            concatenate(&self.clumps)
+        }
+
+        /* 0.0 for a payload we could ship as it is, rising towards 1.0
+         * the more bad bytes it carries, or the further it runs over
+         * the length limit. Either kind of violation costs at least
+         * half, so a dirty chain never looks like a solution. */
+        pub fn constraint_violation (&self, params: &Params) -> f32 {
+            let packed = self.pack();
+            if packed.is_empty() { return 0.0 };
+            let len = packed.len() as f32;
+            let bad = count_bad_bytes(&packed, &params.bad_bytes);
+            let bad_err = if bad > 0 { 0.5 + 0.5 * (bad as f32 / len) } else { 0.0 };
+            let max = params.max_packed_len;
+            let len_err = if max > 0 && packed.len() > max {
+                0.5 + 0.5 * ((packed.len() - max) as f32 / len)
+            } else {
+                0.0
+            };
+            f32::min(1.0, bad_err + len_err)
+        }
+
+        /* All the clumps in a chain come from the same engine, so
+         * the first one tells us how wide the stack slots are. */
+        pub fn word_size (&self) -> usize {
+            match self.clumps.first() {
+                Some(c) => c.mode.word_size(),
+                None    => 4,
+            }
+        }
+
+        pub fn endian (&self) -> Endian {
+            match self.clumps.first() {
+                Some(c) => c.mode.endian(),
+                None    => Endian::LITTLE,
+            }
         }
 
         pub fn collate_input_slots (&mut self) {
@@ -681,9 +909,9 @@ impl Chain {
             let mut offset = 0;
             for clump in self.clumps.iter_mut().filter(|ref x| x.enabled) {
                 for &(off, inp) in clump.input_slots.iter() {
-                    self.input_slots.push((off + offset, inp));
+                    self.input_slots.push((clump.slot_of(off) + offset, inp));
                 }
-                offset += clump.ret_offset;
+                offset += clump.sp_delta as usize;
             }
         }
 
@@ -722,7 +950,7 @@ impl Chain {
             (sum as f32) / c
         }
 
-        pub fn interval_tree (&self) -> IntervalTree<u32,usize> {
+        pub fn interval_tree (&self) -> IntervalTree<u64,usize> {
             let mut tree = IntervalTree::new();
             let mut idx = 0;
             for clump in &self.clumps {
@@ -737,25 +965,25 @@ impl Chain {
             self.crashes.len() == 0 && self.stray_addr_rate() > 0.0
         }
 
-        pub fn get_intervals (&self) -> Vec<(u32,u32,usize)> {
+        pub fn get_intervals (&self) -> Vec<(u64,u64,usize)> {
             /* third term is original index */
             let mut intervals = self.clumps
                                     .iter()
                                     .enumerate()
                                     .map(|(i,c)| (c.entry(), c.exit(), i))
-                                    .collect::<Vec<(u32,u32,usize)>>();
+                                    .collect::<Vec<(u64,u64,usize)>>();
             intervals.sort();
             intervals
         }
 
-        pub fn is_stray(&self, addr: u32) -> bool {
+        pub fn is_stray(&self, addr: u64) -> bool {
             let intervals = self.get_intervals();
             self.search_intervals(&intervals, addr) == None
         }
 
         pub fn search_intervals (&self, 
-                                 intervals: &Vec<(u32,u32,usize)>, 
-                                 addr: u32) 
+                                 intervals: &Vec<(u64,u64,usize)>, 
+                                 addr: u64) 
                                 -> Option<usize>{
             let res = intervals.binary_search_by(
                 (|c| if c.0 <= addr && addr <= c.1 {
@@ -771,8 +999,8 @@ impl Chain {
             }
         }
 
-        pub fn dedup_visits (&self) -> Vec<Vec<u32>> {
-            let mut visits : Vec<Vec<u32>> = self.visited_map
+        pub fn dedup_visits (&self) -> Vec<Vec<u64>> {
+            let mut visits : Vec<Vec<u64>> = self.visited_map
                                                  .values()
                                                  .map(|x| x.clone())
                                                  .collect();
@@ -841,7 +1069,7 @@ impl Chain {
         }
         pub fn dump_visited_map (&self,
                                  path: &str,
-                                 uc: &unicorn::CpuARM,
+                                 uc: &Emu,
                                  params: &Params) {
 
             let s = self.dump_visited_map_to_string(uc, params);
@@ -858,7 +1086,7 @@ impl Chain {
         }
 
         pub fn dump_visited_map_to_string (&self, 
-                                           uc: &unicorn::CpuARM,
+                                           uc: &Emu,
                                            params: &Params) -> String {
 
             let mut s = String::new();
@@ -878,14 +1106,19 @@ impl Chain {
                                     pname));
                 s.push_str(&format!("IN:  {}\n", hexvec_(&p.input
                                                          .iter()
-                                                         .map(|&x| x as u32)
-                                                         .collect::<Vec<u32>>())));
+                                                         .map(|&x| x as u32 as u64)
+                                                         .collect::<Vec<u64>>())));
                 let intervals = self.get_intervals();
                 for addr in self.visited_map.get(p).unwrap() {
-                    let is_stray = self.search_intervals(&intervals, *addr) == None;
-                    let dis = disas_addr(&uc, *addr);
-                    s.push_str(&format!("{:08x}{} | {}\n", 
+                    let owner = self.search_intervals(&intervals, *addr);
+                    let is_stray = owner == None;
+                    let dis = match owner {
+                        Some(i) => disas_addr(uc, self.clumps[i].mode.entry_addr(*addr)),
+                        None    => disas_addr(uc, *addr),
+                    };
+                    s.push_str(&format!("{:08x} {:<28}{} | {}\n", 
                                         addr,
+                                        mod_offset(&params.modules, *addr),
                                         if is_stray { " stray"} else {"      "},
                                         dis)
                               );
@@ -904,7 +1137,7 @@ impl Chain {
                     s.push_str(&format!("{:x}",r));
                     match d {
                         &Some(ref a) => {
-                            let w = get_word32le(a,0);
+                            let w = get_word_endian(a, 0, self.word_size(), self.endian());
                             s.push_str(&format!("->{:x} ",w));
                         },
                         &None => {
@@ -916,7 +1149,7 @@ impl Chain {
                 let r0 = &self.register_map.get(p).unwrap().0[0];
                 s.push_str(&format!("R0 (bin): {:032b}\n", r0));
                 if params.class_masks.len() != 0 {
-                    s.push_str(&format!("CLASS: {}\n", class_mask_classify(*r0, &params.class_masks)));
+                    s.push_str(&format!("CLASS: {}\n", class_mask_classify(*r0 as u32, &params.class_masks)));
                 }
                 s.push_str(&format!("--- END VISIT MAP FOR PROBLEM {} ---\n",
                                     pname));
@@ -940,7 +1173,7 @@ impl Ord for Chain {
 
 const POPSIZE : usize = 400;
 
-#[derive(Clone)]
+#[derive(Clone,Serialize,Deserialize)]
 pub struct Population  {
         pub deme: Vec<Chain>,
         pub best: Option<Chain>,
@@ -952,8 +1185,8 @@ pub struct Population  {
 
 unsafe impl Send for Population {}
 
-pub fn make_gadget_heatmap(clumps: &Vec<Clump>, width: u32) -> HashMap<u32,usize> {
-    let mut hm : HashMap<u32,usize> = HashMap::new();
+pub fn make_gadget_heatmap(clumps: &Vec<Clump>, width: u64) -> HashMap<u64,usize> {
+    let mut hm : HashMap<u64,usize> = HashMap::new();
     for clump in clumps {
         println!("GADGET ENTRY: {:08x}, EXIT: {:08x}, SIZE: {}", 
                  clump.words[0], clump.ret_addr, clump.ret_addr - clump.words[0]);
@@ -964,57 +1197,79 @@ pub fn make_gadget_heatmap(clumps: &Vec<Clump>, width: u32) -> HashMap<u32,usize
     hm
 }
 
+/* Reap every code region in each mode the engine can reach, then
+ * profile what we got.
+ * This is the slow part of start-up, so Population::new will use
+ * the gadget database instead, when there's a fresh one. */
+pub fn harvest_gadgets (params: &Params, engine: &mut Engine) -> Vec<Clump> {
+        let mut clumps : Vec<Clump> = Vec::new();
+        for region in &params.code {
+            for mode in params.mode.harvest_modes() {
+                let mut found = reap_gadgets(&region.data,
+                                             region.addr,
+                                             mode);
+                println!("[*] Harvested {} {:?} gadgets from {} in {}",
+                         found.len(), mode, region.name, 
+                         region.module);
+                for clump in &mut found { 
+                    clump.section = region.name.clone(); 
+                    clump.module  = region.module.clone();
+                }
+                clumps.extend_from_slice(&found);
+            }
+        }
+        /* profiles end up in the gadget database, so they depend on
+         * the seed, but not on anything else that happens in the run */
+        let mut rng = seeded_rng(params.seed);
+        for clump in clumps.iter_mut() {
+            let profile = profile_clump(&mut engine.unwrap_mut(), 
+                                        &clump, 
+                                        &mut rng);
+            clump.profile = Some(Arc::new(profile));
+        }
+        clumps
+}
+
 impl Population {
-        pub fn new (params: &Params, engine: &mut Engine) -> Population {
-            let mut clumps = reap_gadgets(&params.code, 
-                                          params.code_addr, 
-                                          MachineMode::ARM);
-            println!("[*] Harvested {} ARM gadgets from {}",
-                              clumps.len(), params.binary_path);
+        pub fn new (params: &Params, engine: &mut Engine, rng: &mut RoperRng) -> Population {
+            let mut clumps = match load_gadget_db(params) {
+                Some(c) => {
+                    println!("[*] Loaded {} gadgets from {}", 
+                             c.len(), gadget_db_path(params));
+                    c
+                },
+                None    => build_gadget_db(params, engine),
+            };
             /* set initial ttls */
             for clump in &mut clumps { clump.ttl = params.ttl }
-            //let thumb_clumps = &reap_gadgets(&params.code,
-            //                                 params.code_addr,
-            //                                 MachineMode::THUMB);
-            //println!("[*] Harvested {} THUMB gadgets from {}",
-            //                  thumb_clumps.len(), params.binary_path);
-            //clumps.extend_from_slice(&thumb_clumps);
             
             /* it would be good to dump a "heatmap" of the gadgets found here */
             let gadget_heatmap = make_gadget_heatmap(&clumps, 2);
             let hmpath = format!("{}_heatmap.sexp", &params.binary_path);
             dump_heatmap(&gadget_heatmap, &params.binary_path, &hmpath);
-            let mut rng = rand::thread_rng();
-
-            let mut clump_buckets : Vec<Vec<Clump>> = 
-                vec![Vec::new(), Vec::new(), Vec::new(), Vec::new()];
-            for clump in clumps.iter() {
-                clump_buckets[test_clump(&mut engine.unwrap_mut(), &clump)]
-                    .push(clump.clone())
-            }
-            println!("[*] Size of buckets:\n[+] NOCHANGE_CRASH_BUCKET: {}\n[+] NOCHANGE_NOCRASH_BUCKET: {}\n[+] CHANGE_CRASH_BUCKET: {}\n[+] CHANGE_NOCRASH_BUCKET: {}\n",
-                              clump_buckets[NOCHANGE_CRASH_BUCKET].len(),
-                              clump_buckets[NOCHANGE_NOCRASH_BUCKET].len(),
-                              clump_buckets[CHANGE_CRASH_BUCKET].len(),
-                              clump_buckets[CHANGE_NOCRASH_BUCKET].len());
+            print!("{}", capability_summary(&clumps));
+            /* seed the population with gadgets that seem to do something */
+            let live : Vec<Clump> = clumps.iter()
+                                          .filter(|c| match c.profile {
+                                              Some(ref p) => !p.is_dud(),
+                                              None        => true,
+                                          })
+                                          .cloned()
+                                          .collect();
 
-            let mut data_pool  = Mangler::new(&params.constants);
+            let mut data_pool  = Mangler::avoiding(&params.constants,
+                                                   params.mode.word_size(),
+                                                   &params.bad_bytes,
+                                                   child_rng(rng));
             let mut deme : Vec<Chain> = Vec::new();
-            for _ in 0..params.population_size{
-/*
-                deme.push(random_chain_from_buckets(
-                                                              &clump_buckets,
-                                                              params.min_start_len,
-                                                              params.max_start_len,
-                                                              &mut data_pool,
-                                                              &mut rand::thread_rng()));
-                */
-                deme.push(random_chain(&clumps,
-                                       &params,
-                                       &mut data_pool,
-                                       &mut rand::thread_rng()));
-                                                              
-
+            {
+                let seeds = if live.is_empty() { &clumps } else { &live };
+                for _ in 0..params.population_size{
+                    deme.push(random_chain(seeds,
+                                           &params,
+                                           &mut data_pool,
+                                           rng));
+                }
             }
             Population {
                 deme: deme,
@@ -1026,7 +1281,7 @@ impl Population {
             }
         }
 
-        pub fn dump_all (&self, uc: &unicorn::CpuARM) -> String {
+        pub fn dump_all (&self, uc: &Emu) -> String {
             let dir = format!("{}/{}_season_{}_dump/",
                               &self.params.log_dir,
                               &self.params.label,
@@ -1053,12 +1308,15 @@ impl Population {
             dir
         }
 
-        pub fn random_spawn (&self) -> Chain {
-            let mut mangler = Mangler::new(&self.params.constants);
+        pub fn random_spawn (&self, rng: &mut RoperRng) -> Chain {
+            let mut mangler = Mangler::avoiding(&self.params.constants,
+                                                self.params.mode.word_size(),
+                                                &self.params.bad_bytes,
+                                                child_rng(rng));
             random_chain(&self.primordial_ooze,
                          &self.params,
                          &mut mangler,
-                         &mut thread_rng())
+                         rng)
         }
 
         pub fn avg_gen (&self) -> f32 {
@@ -1194,7 +1452,7 @@ impl Population {
                  cand as f32
         }
 
-        pub fn ret_addrs (&self) -> Vec<u32> {
+        pub fn ret_addrs (&self) -> Vec<u64> {
             let mut addrs = Vec::new();
             for chain in &self.deme {
                 for clump in &chain.clumps {
@@ -1204,7 +1462,7 @@ impl Population {
             addrs
         }
 
-        pub fn entry_addrs (&self) -> Vec<u32> {
+        pub fn entry_addrs (&self) -> Vec<u64> {
             let mut addrs = Vec::new();
             for chain in &self.deme {
                 for clump in &chain.clumps {
@@ -1377,34 +1635,90 @@ impl Population {
   * Constants and parameters
   */
 
-#[derive(PartialEq,Debug,Clone,Copy)]
+#[derive(PartialEq,Debug,Clone,Copy,Serialize,Deserialize)]
 pub enum SelectionMethod {
         Tournament,
         Roulette,
 }
 
-#[derive(PartialEq, Debug, Clone, Copy)]
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum Endian {
         LITTLE,
         BIG,
 }
 
-#[derive(Eq,PartialEq, Debug, Clone, Copy)]
+#[derive(Eq,PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum MachineMode {
         THUMB,
         ARM,
+        AARCH64,
+        MIPS,   // 32-bit, big-endian
+        MIPSEL, // 32-bit, little-endian
 }
 impl MachineMode {
-        pub fn uc(&self) -> Mode {
+        /* Unicorn wants MIPS32|BIG_ENDIAN for big-endian MIPS, but
+         * the bindings' Mode enum has no variant for the pair, and
+         * nothing else of theirs takes the bits, so there's no engine
+         * to be had for it. main turns it away with the arguments. */
+        pub fn uc(&self) -> Option<Mode> {
+            match self {
+              &MachineMode::THUMB   => Some(Mode::THUMB),
+              &MachineMode::ARM     => Some(Mode::LITTLE_ENDIAN),
+              &MachineMode::AARCH64 => Some(Mode::LITTLE_ENDIAN),
+              &MachineMode::MIPSEL  => Some(Mode::MODE_32),
+              &MachineMode::MIPS    => None,
+            }
+        }
+        pub fn arch(&self) -> Arch {
             match self {
-              &MachineMode::THUMB => Mode::THUMB,
-              &MachineMode::ARM   => Mode::LITTLE_ENDIAN,
+              &MachineMode::AARCH64 => Arch::ARM64,
+              &MachineMode::MIPS |
+              &MachineMode::MIPSEL  => Arch::MIPS,
+              _                     => Arch::ARM,
             }
         }
         pub fn cs(&self) -> CsMode {
             match self {
-                &MachineMode::THUMB => CsMode::MODE_THUMB,
-                &MachineMode::ARM   => CsMode::MODE_LITTLE_ENDIAN,
+                &MachineMode::THUMB   => CsMode::MODE_THUMB,
+                &MachineMode::ARM     => CsMode::MODE_LITTLE_ENDIAN,
+                &MachineMode::AARCH64 => CsMode::MODE_LITTLE_ENDIAN,
+                &MachineMode::MIPS    => CsMode::MODE_BIG_ENDIAN,
+                &MachineMode::MIPSEL  => CsMode::MODE_LITTLE_ENDIAN,
+            }
+        }
+        pub fn cs_arch(&self) -> CsArch {
+            match self {
+                &MachineMode::AARCH64 => CsArch::ARCH_ARM64,
+                &MachineMode::MIPS |
+                &MachineMode::MIPSEL  => CsArch::ARCH_MIPS,
+                _                     => CsArch::ARCH_ARM,
+            }
+        }
+        /* bytes per stack slot, and per register */
+        pub fn word_size(&self) -> usize {
+            match self {
+                &MachineMode::AARCH64 => 8,
+                _                     => 4,
+            }
+        }
+        /* byte order of code, stack slots and memory */
+        pub fn endian(&self) -> Endian {
+            match self {
+                &MachineMode::MIPS => Endian::BIG,
+                _                  => Endian::LITTLE,
+            }
+        }
+        pub fn is_mips(&self) -> bool {
+            *self == MachineMode::MIPS || *self == MachineMode::MIPSEL
+        }
+        /* The modes an engine running in this mode can reach, and
+         * so the ones worth reaping gadgets for. */
+        pub fn harvest_modes(&self) -> Vec<MachineMode> {
+            match self {
+                &MachineMode::AARCH64 => vec![MachineMode::AARCH64],
+                &MachineMode::MIPS    => vec![MachineMode::MIPS],
+                &MachineMode::MIPSEL  => vec![MachineMode::MIPSEL],
+                _                     => vec![MachineMode::ARM, MachineMode::THUMB],
             }
         }
 }
@@ -1412,15 +1726,17 @@ impl Default for MachineMode {
         fn default() -> MachineMode { MachineMode::THUMB }
 }
 
-#[derive(Copy,Debug,Clone,Eq,PartialEq)]
+#[derive(Copy,Debug,Clone,Eq,PartialEq,Serialize,Deserialize)]
 pub enum TargetKind {
         PatternMatch,
         Classification,
         Game,
         Kafka,
+        Syscall,
+        Memory,
 }
 
-#[derive(Debug,Clone,Eq,PartialEq)]
+#[derive(Debug,Clone,Eq,PartialEq,Serialize,Deserialize)]
 pub struct IoTargets {
         v: Vec<Problem>,
         k: TargetKind, 
@@ -1430,15 +1746,28 @@ pub struct IoTargets {
 pub fn mk_class(c: usize, num_classes: usize, class_masks: &Vec<(u32,usize)>) -> Target {
         Target::Vote(Classification::new(c, num_classes, class_masks))
 }
-pub fn mk_pattern(s: &str) -> Target {
-        Target::Exact(RPattern::new(&s))
+pub fn mk_pattern(s: &str, mode: MachineMode) -> Target {
+        if RPattern2::is_equation(s) {
+            Target::Equations(RPattern2::new(&s, mode))
+        } else {
+            Target::Exact(RPattern::new(&s))
+        }
+}
+pub fn mk_syscall(s: &str, mode: MachineMode) -> Target {
+        Target::Syscall(SyscallGoal::new(&s, mode))
+}
+pub fn mk_memgoal(s: &str, elf_path: &str, load_base: u64, mode: MachineMode) -> Target {
+        Target::Memory(MemGoal::new(&s, elf_path, load_base, mode))
 }
 
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,Serialize,Deserialize)]
 pub struct Problem {
         pub input: Vec<i32>,
+        #[serde(with = "::roper::serial::float")]
         difficulty: f32,
+        #[serde(with = "::roper::serial::floats")]
         predifficulty: Vec<f32>,
+        #[serde(with = "::roper::serial::float")]
         pfactor: f32,
         pub target: Target,
 }
@@ -1492,9 +1821,10 @@ impl Problem {
         }
 
         pub fn get_input<'a> (&'a self, 
-                              output: &Vec<u32>, 
+                              output: &Vec<u64>, 
                               random_override: bool,
-                              verbose: bool) 
+                              verbose: bool,
+                              rng: &mut RoperRng) 
                               -> (Option<i32>, Vec<i32>) {
             match &self.target {
                 
@@ -1509,7 +1839,7 @@ impl Problem {
                         p.extend_from_slice(&x.params);
                         /*** RANDOMIZATION OVERRIDE ***/
                         if random_override {
-                            p[1] = thread_rng().gen::<i32>();
+                            p[1] = rng.gen::<i32>();
                         }
                         /******************************/
                         (None, init_game(&p, &x.addr))
@@ -1528,11 +1858,25 @@ impl Problem {
           */
         pub fn assess_output (&self,
                               outregs: &Vec<usize>,
-                              registers: &Vec<u32>,
-                              reg_deref: &Vec<Option<Vec<u8>>>,
-                              uc: &CpuARM) 
+                              result: &HatchResult,
+                              uc: &Emu,
+                              rng: &mut RoperRng) 
                               -> (f32, f32) {
+            let registers = &result.registers;
+            let reg_deref = &result.reg_deref;
             match &self.target {
+                &Target::Syscall(ref goal) => {
+                    let r = goal.distance(&result.syscall, registers, reg_deref, uc.mode);
+                    (r, r)
+                },
+                &Target::Memory(ref goal) => {
+                    let r = goal.distance(&result.mem_after, &result.mem_writes);
+                    (r, r)
+                },
+                &Target::Equations(ref rp) => {
+                    let r = rp.distance(registers, reg_deref, uc.mode);
+                    (r, r)
+                },
                 &Target::Exact(ref rp) => {
                     // here we can try some sort of fitness sharing thing
                     // refactor later so that this returns a fingerprint
@@ -1543,7 +1887,7 @@ impl Problem {
                 },
                 &Target::Vote(ref cls) => {
                     /** Let's try this with bitmasks on R0, instead. */
-                    let class_guess = cls.classify(registers[0]);
+                    let class_guess = cls.classify(registers[0] as u32);
                     //println!("CLASSIFIED: R0 = {:032b}, so class_guess = {} ({})", registers[0], class_guess, if class_guess == cls.class { "PASS" } else if class_guess == cls.num_classes { "AUTOFAIL" } else {"FAIL"});
 
                     /*
@@ -1578,7 +1922,7 @@ impl Problem {
                     }
                 } 
                 &Target::Game(_) => {
-                    let mut output : Vec<u32> = Vec::new();
+                    let mut output : Vec<u64> = Vec::new();
                     for idx in outregs {
                         output.push(registers[*idx]);
                     }
@@ -1589,7 +1933,7 @@ impl Problem {
                 },
                 /* It is a very painful thing... */
                 &Target::Kafka => {
-                    let r = f32::min(1.0, 0.1 + thread_rng().gen::<f32>());
+                    let r = f32::min(1.0, 0.1 + rng.gen::<f32>());
                     (r,r)
                 },
             }
@@ -1601,9 +1945,12 @@ impl Problem {
         pub fn kind (&self) -> TargetKind {
             match self.target {
                 Target::Exact(_) => TargetKind::PatternMatch,
+                Target::Equations(_) => TargetKind::PatternMatch,
                 Target::Vote(_)  => TargetKind::Classification,
                 Target::Game(_)  => TargetKind::Game,
                 Target::Kafka    => TargetKind::Kafka,
+                Target::Syscall(_) => TargetKind::Syscall,
+                Target::Memory(_)  => TargetKind::Memory,
             }
         }
         pub fn rotate_difficulty(&mut self) {
@@ -1641,14 +1988,13 @@ impl PartialEq for Problem {
 }
 impl Eq for Problem {}
 
-pub fn class_masks_randomized (num_classes: usize) -> Vec<(u32, usize)> {
+pub fn class_masks_randomized (num_classes: usize, rng: &mut RoperRng) -> Vec<(u32, usize)> {
     /** must return num_classes masks, each of which has an equal number
      * of ones, mod (32 mod num_classes), none of which have bits in the
      * same indices.
      */
     let mut inner_loop_count = 0;
     let mut class_idx = 0;
-    let mut rng = thread_rng();
     let mut masks : Vec<(u32,usize)> = Vec::new();
     let mut bits_used : HashSet<u32> = HashSet::new();
     bits_used.insert(0); /* to initialize */
@@ -1719,12 +2065,14 @@ fn class_mask_classify (reg: u32, class_masks: &Vec<(u32,usize)>) -> usize {
     }
 }
 
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,Serialize,Deserialize)]
 pub struct Classification {
         pub class: usize,
         class_masks: Vec<(u32,usize)>,
         num_classes: usize,
+        #[serde(with = "::roper::serial::float")]
         difficulty: f32,
+        #[serde(with = "::roper::serial::float")]
         predifficulty: f32,
 }
 impl Hash for Classification {
@@ -1765,10 +2113,10 @@ impl Classification {
 
 pub static DEFAULT_DIFFICULTY : f32 = 0.0; // don't hardcode
 
-pub fn suggest_constants (iot: &IoTargets) -> Vec<i32> {
+pub fn suggest_constants (iot: &IoTargets, rng: &mut RoperRng) -> Vec<i32> {
         let mut cons : Vec<i32> = Vec::new();
         for ref p in iot.v.iter() {
-            cons.extend_from_slice(&p.target.suggest_constants(&p.input));
+            cons.extend_from_slice(&p.target.suggest_constants(&p.input, rng));
         }
         cons
 }
@@ -1788,9 +2136,21 @@ impl FromIterator<(Problem, Target)> for IoTargets {
 */
 
 impl IoTargets {
-        pub fn shuffle (&self) -> IoTargets {
+        /* The memory the memory goals among these look at */
+        pub fn watched (&self) -> Vec<(u64,usize)> {
+            let mut w = Vec::new();
+            for p in &self.v {
+                if let Target::Memory(ref g) = p.target {
+                    w.extend(g.writes.iter().map(|&(a, ref d)| (a, d.len())));
+                }
+            }
+            w.sort();
+            w.dedup();
+            w
+        }
+        pub fn shuffle (&self, rng: &mut RoperRng) -> IoTargets {
             let mut c = self.v.clone();
-            thread_rng().shuffle(&mut c);
+            rng.shuffle(&mut c);
             IoTargets{v:c, k: self.k, num_classes: self.num_classes}
         }
         pub fn difficulty_profile (&self) -> Vec<f32> {
@@ -1859,7 +2219,7 @@ impl IoTargets {
         // Improve on this later, so that it preserves ratios. See example in
         // GENLIN. 
         // TODO: Fix this. it doesn't work. 
-        pub fn balanced_split_at (&self, i: usize) -> (IoTargets, IoTargets) {
+        pub fn balanced_split_at (&self, i: usize, rng: &mut RoperRng) -> (IoTargets, IoTargets) {
             if self.k != TargetKind::Classification {
                 (self.clone(),self.clone())
             } else {
@@ -1867,7 +2227,7 @@ impl IoTargets {
                                                                           .map(|x| x.target.clone())
                                                                           .collect::<Vec<Target>>();
                 unique_targets.dedup();
-                let shuffled = self.shuffle();                         
+                let shuffled = self.shuffle(rng);                         
                 let num_classes : usize = unique_targets.len();
                 let mut buckets : Vec<Vec<Problem>> = Vec::new();
                 for j in 0..num_classes {
@@ -1897,7 +2257,7 @@ impl IoTargets {
                         part_2.push(item);
                     }
                 }
-                let (mut at, mut bt) = (part_1.shuffle(), part_2.shuffle());
+                let (mut at, mut bt) = (part_1.shuffle(rng), part_2.shuffle(rng));
                 at.num_classes = self.num_classes;
                 bt.num_classes = self.num_classes;
                 println!(">> i == {}; at.len() == {}; bt.len() == {}",i, at.len(), bt.len());
@@ -1932,28 +2292,34 @@ impl IoTargets {
 
 pub type Score = u32;
 
-#[derive(Hash,Eq,PartialEq,Debug,Clone)]
+#[derive(Hash,Eq,PartialEq,Debug,Clone,Serialize,Deserialize)]
 pub struct GameData {
         pub addr: String,
         pub params: Vec<i32>,
 }
 
 
-#[derive(Eq,PartialEq,Debug,Clone)]
+#[derive(Eq,PartialEq,Debug,Clone,Serialize,Deserialize)]
 pub enum Target {
     Exact(RPattern),
+    Equations(RPattern2),
     Vote(Classification),
     Game(GameData),
     Kafka,
+    Syscall(SyscallGoal),
+    Memory(MemGoal),
 }
 
 impl Hash for Target {
         fn hash <H: Hasher> (&self, state: &mut H) {
             match self {
                 &Target::Exact(ref r) => r.hash(state),
+                &Target::Equations(ref r) => r.hash(state),
                 &Target::Vote(ref c) => c.hash(state),
                 &Target::Game(ref s) => s.hash(state),
                 &Target::Kafka => ().hash(state),
+                &Target::Syscall(ref g) => g.hash(state),
+                &Target::Memory(ref g) => g.hash(state),
             }
         }
 }
@@ -1961,27 +2327,36 @@ impl Display for Target {
         fn fmt (&self, f: &mut Formatter) -> Result {
             match self {
                 &Target::Exact(ref rp) => rp.fmt(f),
+                &Target::Equations(ref rp) => rp.fmt(f),
                 &Target::Vote(ref i)   => i.class.fmt(f),
                 &Target::Game(_)       => "[game]".fmt(f),
                 &Target::Kafka         => "X".fmt(f),
+                &Target::Syscall(ref g) => g.fmt(f),
+                &Target::Memory(ref g)  => g.fmt(f),
             }
         }
 }
 
 impl Target {
-        pub fn suggest_constants (&self, input: &Vec<i32>) -> Vec<i32> {
+        pub fn suggest_constants (&self, input: &Vec<i32>, rng: &mut RoperRng) -> Vec<i32> {
             match self {
                 &Target::Vote(_) => {
                     let mut cons : Vec<i32> = Vec::new();
-                    let mut rng = rand::thread_rng();
                     for ut in input {
                         cons.push(rng.gen::<i32>() % (2 * (ut.abs()+1) as i32));
                     }
                     cons
                 },
                 &Target::Exact(ref r) => r.constants(),
+                &Target::Equations(ref r) => r.constants(),
+                &Target::Syscall(ref g) => {
+                    let mut cons = g.args.constants();
+                    cons.push(g.num as i32);
+                    cons
+                },
+                &Target::Memory(ref g) => g.constants(),
                 &Target::Game(_) => vec![2], // PLACEHOLDER TODO
-                &Target::Kafka => (0..1024).map(|_| thread_rng().gen::<i32>())
+                &Target::Kafka => (0..1024).map(|_| rng.gen::<i32>())
                                            .collect::<Vec<i32>>(),
             }
         }
@@ -1999,40 +2374,289 @@ impl Target {
         }
 }
 
-#[derive(Debug,Clone)]
-struct RPatEq {
-        pub reg_res: usize,
-        pub reg_exp: Option<usize>,
-        pub immed: u32,
-        pub diff:  f32,
-        pub prediff: f32,
+/* The right-hand side of a register equation */
+#[derive(Hash,Debug,Clone,PartialEq,Eq,Serialize,Deserialize)]
+pub enum RExpr {
+    Immed(u64),          // r7=0xb
+    Reg(usize, i64),     // r2=r1, r0=sp+4
+    Deref(usize),        // r1=*r2
+    Points(Vec<u8>),     // r0=&"/bin/sh", r0=&0x41414141
+    Range(u64, u64),     // r3 in [0x1000,0x2000), half-open
+}
+
+/* A single constraint: the masked value of reg must stand in the
+ * given relation to expr. The mask is all ones unless one was given,
+ * as in r3&0xff=0x41. */
+#[derive(Hash,Debug,Clone,PartialEq,Eq,Serialize,Deserialize)]
+pub struct RPatEq {
+        pub reg: usize,
+        pub mask: u64,
+        pub expr: RExpr,
 }
-/* Formula: match req.reg_exp {
-  *  None => dist(regs[req.reg_res], req.immed),
-  *  Some(r) => dist(regs[req.reg_res], regs[req.reg_exp.unwrap()]
-  *                                     + immed),
-  * }
-  */
 
-#[derive(Debug,Clone)]
-pub struct RPattern2 (Vec<RPatEq>);
+#[derive(Hash,Debug,Clone,PartialEq,Eq,Serialize,Deserialize)]
+pub struct RPattern2 (pub Vec<RPatEq>);
 
-/* TODO:
-  * reimplement Register Patterns in such a way that
-  * the pattern can contain other register references.
-  * e.g: r0 = sp+4
-  * And then write a parser for a simple set of equations, 
-  * instead of an int / wildcard pattern.
-  */
-#[derive(Hash,Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord)]
+/* Numbers are hex with a 0x prefix, decimal without. */
+fn parse_num (s: &str) -> Option<u64> {
+        let s = s.trim();
+        if s.starts_with("0x") {
+            u64::from_str_radix(&s[2..], 16).ok()
+        } else if s.starts_with('-') {
+            s.parse::<i64>().ok().map(|n| n as u64)
+        } else {
+            s.parse::<u64>().ok()
+        }
+}
+
+/* rN works everywhere, otherwise we go by the names reg_name gives,
+ * with or without MIPS's sigil. */
+fn parse_reg (s: &str, mode: MachineMode) -> Option<usize> {
+        let s = s.trim();
+        let n = mode.num_registers();
+        if s.len() > 1 && (s.starts_with('r') || s.starts_with('x')) {
+            if let Ok(r) = s[1..].parse::<usize>() {
+                if r < n { return Some(r) };
+            }
+        }
+        (0..n).find(|&r| {
+            let name = reg_name(r, mode);
+            name == s || name.trim_left_matches('$') == s.trim_left_matches('$')
+        })
+}
+
+/* Split on commas and semicolons, but not inside brackets or quotes */
+fn split_constraints (s: &str) -> Vec<String> {
+        let mut parts = Vec::new();
+        let mut cur = String::new();
+        let mut depth = 0;
+        let mut quoted = false;
+        for c in s.chars() {
+            match c {
+                '"'                             => quoted = !quoted,
+                '[' | '(' if !quoted            => depth += 1,
+                ']' | ')' if !quoted            => depth -= 1,
+                ',' | ';' if !quoted && depth == 0 => {
+                    parts.push(cur.trim().to_string());
+                    cur = String::new();
+                    continue;
+                },
+                _                               => (),
+            }
+            cur.push(c);
+        }
+        parts.push(cur.trim().to_string());
+        parts.into_iter().filter(|p| !p.is_empty()).collect()
+}
+
+fn eq_fail (s: &str) -> ! {
+        panic!("Failed to parse {:?} as a register equation", s)
+}
+
+fn word_bits (mode: MachineMode) -> u32 {
+        (mode.word_size() * 8) as u32
+}
+
+/* Half Hamming distance, half how many orders of magnitude apart
+ * the two values are, as a fraction of the word. */
+fn word_dist (a: u64, b: u64, mask: u64, bits: u32) -> f32 {
+        let (a, b) = (a & mask, b & mask);
+        if a == b { return 0.0 };
+        let ham = (a ^ b).count_ones() as f32 / bits as f32;
+        let gap = min(a.wrapping_sub(b), b.wrapping_sub(a)) & word_mask((bits / 8) as usize);
+        let mag = (64 - gap.leading_zeros()) as f32 / bits as f32;
+        f32::min(1.0, (ham + mag) / 2.0)
+}
+
+impl RPatEq {
+        pub fn new (s: &str, mode: MachineMode) -> Self {
+            /* an " in " after the = is part of the value, as in
+             * r0=&"log in " */
+            let eq = s.find('=');
+            let within = s.find(" in ").filter(|&i| eq.map_or(true, |e| i < e));
+            let (lhs, expr) = if let Some(i) = within {
+                let rhs = s[(i+4)..].trim();
+                if rhs.len() < 2 { eq_fail(s) };
+                let inner = &rhs[1..(rhs.len()-1)];
+                let mut bounds = inner.splitn(2, ',');
+                let lo = parse_num(bounds.next().unwrap_or(""))
+                             .unwrap_or_else(|| eq_fail(s));
+                let hi = parse_num(bounds.next().unwrap_or(""))
+                             .unwrap_or_else(|| eq_fail(s));
+                let lo = if rhs.starts_with('(') { lo.saturating_add(1) } else { lo };
+                let hi = if rhs.ends_with(']') { hi.saturating_add(1) } else { hi };
+                (&s[..i], RExpr::Range(lo, hi))
+            } else if let Some(i) = s.find('=') {
+                let rhs = s[(i+1)..].trim_left_matches('=').trim();
+                let expr = if rhs.starts_with("&\"") {
+                    let mut bytes = rhs[1..].trim_matches('"').as_bytes().to_vec();
+                    bytes.push(0);
+                    RExpr::Points(bytes)
+                } else if rhs.starts_with('&') {
+                    let n = parse_num(&rhs[1..]).unwrap_or_else(|| eq_fail(s));
+                    RExpr::Points(pack_word_endian(n, mode.word_size(), mode.endian()))
+                } else if rhs.starts_with('*') {
+                    RExpr::Deref(parse_reg(&rhs[1..], mode).unwrap_or_else(|| eq_fail(s)))
+                } else if let Some(n) = parse_num(rhs) {
+                    RExpr::Immed(n)
+                } else {
+                    let k = rhs.find(|c| c == '+' || c == '-').unwrap_or(rhs.len());
+                    let r = parse_reg(&rhs[..k], mode).unwrap_or_else(|| eq_fail(s));
+                    let off = if k < rhs.len() {
+                        let n = parse_num(&rhs[(k+1)..]).unwrap_or_else(|| eq_fail(s)) as i64;
+                        if &rhs[k..(k+1)] == "-" { -n } else { n }
+                    } else { 0 };
+                    RExpr::Reg(r, off)
+                };
+                (&s[..i], expr)
+            } else {
+                eq_fail(s)
+            };
+            let mut lparts = lhs.splitn(2, '&');
+            let reg = parse_reg(lparts.next().unwrap(), mode).unwrap_or_else(|| eq_fail(s));
+            let mask = match lparts.next() {
+                Some(m) => parse_num(m).unwrap_or_else(|| eq_fail(s)),
+                None    => !0,
+            };
+            RPatEq { reg: reg, mask: mask, expr: expr }
+        }
+
+        pub fn distance (&self,
+                         regs: &Vec<u64>,
+                         regs_deref: &Vec<Option<Vec<u8>>>,
+                         mode: MachineMode) -> f32 {
+            let bits = word_bits(mode);
+            let ws = mode.word_size();
+            let v = regs[self.reg] & self.mask;
+            match self.expr {
+                RExpr::Immed(x) => word_dist(v, x, self.mask, bits),
+                RExpr::Reg(r, off) => {
+                    let x = (regs[r] as i64).wrapping_add(off) as u64;
+                    word_dist(v, x, self.mask, bits)
+                },
+                RExpr::Deref(r) => match regs_deref[r] {
+                    Some(ref d) if d.len() >= ws => {
+                        let x = get_word_endian(d, 0, ws, mode.endian());
+                        word_dist(v, x, self.mask, bits)
+                    },
+                    _ => 1.0,
+                },
+                /* Found at an offset from where the register points is
+                 * better than not found at all, and nearer is better. */
+                RExpr::Points(ref want) => match regs_deref[self.reg] {
+                    Some(ref d) if d.len() >= want.len() && want.len() > 0 => {
+                        match d.windows(want.len()).position(|w| w == &want[..]) {
+                            Some(i) => 0.5 * (i as f32 / d.len() as f32),
+                            None    => {
+                                let wrong : u32 = want.iter()
+                                                      .zip(d.iter())
+                                                      .map(|(a,b)| (a ^ b).count_ones())
+                                                      .sum();
+                                0.5 + 0.5 * (wrong as f32 / (8 * want.len()) as f32)
+                            },
+                        }
+                    },
+                    _ => 1.0,
+                },
+                RExpr::Range(lo, hi) => {
+                    if lo <= v && v < hi {
+                        0.0
+                    } else if v < lo {
+                        word_dist(v, lo, !0, bits)
+                    } else {
+                        word_dist(v, hi.saturating_sub(1), !0, bits)
+                    }
+                },
+            }
+        }
+
+        pub fn constants (&self) -> Vec<u64> {
+            match self.expr {
+                RExpr::Immed(x)       => vec![x],
+                RExpr::Reg(_, off)    => vec![off as u64],
+                RExpr::Range(lo, hi)  => vec![lo, hi.saturating_sub(1)],
+                _                     => Vec::new(),
+            }
+        }
+}
+
+impl Display for RPatEq {
+        fn fmt (&self, f: &mut Formatter) -> Result {
+            let lhs = if self.mask == !0 { 
+                format!("r{}", self.reg) 
+            } else { 
+                format!("r{}&0x{:x}", self.reg, self.mask) 
+            };
+            match self.expr {
+                RExpr::Immed(x)      => write!(f, "{}=0x{:x}", lhs, x),
+                RExpr::Reg(r, 0)     => write!(f, "{}=r{}", lhs, r),
+                RExpr::Reg(r, off)   => write!(f, "{}=r{}{:+}", lhs, r, off),
+                RExpr::Deref(r)      => write!(f, "{}=*r{}", lhs, r),
+                RExpr::Points(ref b) => write!(f, "{}=&{:?}", lhs, 
+                                               String::from_utf8_lossy(b)),
+                RExpr::Range(lo, hi) => write!(f, "{} in [0x{:x},0x{:x})", lhs, lo, hi),
+            }
+        }
+}
+
+impl RPattern2 {
+        /* Comma- or semicolon-separated register equations, e.g.
+         * r0=&"/bin/sh", r1=0, r7=0xb, r2=r1, r3 in [0x1000,0x2000) */
+        pub fn new (s: &str, mode: MachineMode) -> Self {
+            RPattern2(split_constraints(s).iter()
+                                          .map(|c| RPatEq::new(c, mode))
+                                          .collect())
+        }
+
+        /* Anything with a relation in it is an equation; the old
+         * hex-and-underscore patterns never have one. */
+        pub fn is_equation (s: &str) -> bool {
+            s.contains('=') || s.contains(" in ")
+        }
+
+        pub fn distance (&self,
+                         regs: &Vec<u64>,
+                         regs_deref: &Vec<Option<Vec<u8>>>,
+                         mode: MachineMode) -> f32 {
+            if self.0.is_empty() { return 0.0 };
+            mean(&self.0.iter()
+                        .map(|e| e.distance(regs, regs_deref, mode))
+                        .collect::<Vec<f32>>())
+        }
+
+        pub fn satisfy (&self,
+                        regs: &Vec<u64>,
+                        regs_deref: &Vec<Option<Vec<u8>>>,
+                        mode: MachineMode) -> bool {
+            self.0.iter().all(|e| e.distance(regs, regs_deref, mode) == 0.0)
+        }
+
+        pub fn constants (&self) -> Vec<i32> {
+            self.0.iter()
+                  .flat_map(|e| e.constants().into_iter())
+                  .map(|x| x as i32)
+                  .collect()
+        }
+}
+
+impl Display for RPattern2 {
+        fn fmt (&self, f: &mut Formatter) -> Result {
+            let parts : Vec<String> = self.0.iter().map(|e| format!("{}", e)).collect();
+            write!(f, "{}", parts.join(", "))
+        }
+}
+
+#[derive(Hash,Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord,Serialize,Deserialize)]
 pub enum RVal {
-    Immed(u32),
-    Deref(u32),
+    Immed(u64),
+    Deref(u64),
 }
 
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,Serialize,Deserialize)]
 pub struct RPattern { 
+        #[serde(with = "::roper::serial::float_triples")]
         regvals_diff: Vec<(usize,RVal,f32)>,
+        #[serde(with = "::roper::serial::float_triples")]
         regvals_prediff: Vec<(usize,RVal,f32)>,
 }
 impl Hash for RPattern {
@@ -2051,6 +2675,13 @@ impl PartialEq for RPattern {
 }
 impl Eq for RPattern {}
 
+/* Patterns don't know what machine they'll be matched on, so a
+ * dereferenced value is read as a 32-bit word unless it only fits
+ * in a 64-bit one. */
+fn deref_width (x: u64) -> usize {
+        if x > 0xFFFFFFFF { 8 } else { 4 }
+}
+
 
 impl RPattern {
         pub fn clean (&self) -> Vec<(usize,RVal)> {
@@ -2072,12 +2703,12 @@ impl RPattern {
                     /* check if its immediate or a pointer. (&) */
                     if part.starts_with("&") {
                         let p : String = part.chars().skip(1).collect();
-                        let int = u32::from_str_radix(&p, 16)
+                        let int = u64::from_str_radix(&p, 16)
                                       .expect(&format!("Failed to parse {:?} in RPattern",
                                                        part));
                         rp.push((i,RVal::Deref(int)));
                     } else {
-                        let int = u32::from_str_radix(part,16)
+                        let int = u64::from_str_radix(part,16)
                                       .expect(&format!("Failed to parse {:?} in RPattern",
                                                        part));
                         rp.push((i,RVal::Immed(int)));
@@ -2088,10 +2719,9 @@ impl RPattern {
             rp
         }
 
-        pub fn shuffle_vec (&self) 
+        pub fn shuffle_vec (&self, rng: &mut RoperRng) 
                             -> Vec<(usize, RVal, f32)> {
             let mut c = self.regvals_diff.clone();
-            let mut rng = thread_rng(); // switch to seedable
             rng.shuffle(&mut c);
             c
         }
@@ -2111,7 +2741,7 @@ impl RPattern {
                 .collect()
         }
 
-        pub fn satisfy (&self, regs: &Vec<u32>, regs_deref: &Vec<Option<Vec<u8>>>) -> bool {
+        pub fn satisfy (&self, regs: &Vec<u64>, regs_deref: &Vec<Option<Vec<u8>>>) -> bool {
             for &(idx,val,_) in &self.regvals_diff {
                 match val {
                     RVal::Immed(x) => {
@@ -2123,7 +2753,7 @@ impl RPattern {
                     RVal::Deref(x) => {
                         match &regs_deref[idx] {
                             &Some(ref y) => {
-                                if x != get_word32le(&y,0) {
+                                if x != get_word(&y, 0, deref_width(x)) {
                                   return false 
                                 };
                             }, 
@@ -2136,7 +2766,7 @@ impl RPattern {
         }
         /* NB: scorecard records false for match, true for mismatch */
         pub fn matches (&self, 
-                        regs: &Vec<u32>, 
+                        regs: &Vec<u64>, 
                         regs_deref: &Vec<Option<Vec<u8>>>) -> Fingerprint {
             let mut scorecard : Fingerprint = Fingerprint::new();
             for &(idx,val,diff) in &self.regvals_diff {
@@ -2153,11 +2783,11 @@ impl RPattern {
 
 
         pub fn distance (&self, 
-                         regs: &Vec<u32>, 
+                         regs: &Vec<u64>, 
                          regs_deref: &Vec<Option<Vec<u8>>>) -> f32 {
-            fn arith_err_dist(a: u32, b: u32) -> f32 {
+            fn arith_err_dist(a: u64, b: u64) -> f32 {
                 /* let's just try hamming distance */
-                let ham = (a ^ b).count_ones() as f32 / 32.0;
+                let ham = f32::min(1.0, (a ^ b).count_ones() as f32 / 32.0);
                 // peephole distance
                 let dif = a.wrapping_sub(b);
                 let peep = 2048;
@@ -2167,17 +2797,15 @@ impl RPattern {
                 (ham + peepdist) / 2.0
             }
 
-            fn mem_err_dist(a: u32, b: &Vec<u8>) -> f32 {
+            fn mem_err_dist(a: u64, b: &Vec<u8>) -> f32 {
                 /* a is target, b is result */
                 let mlen = b.len();
-                assert!(b.len() >= 4);
-                let a_bytes : Vec<u8> = pack_word32le(a);
+                let width = deref_width(a);
+                assert!(b.len() >= width);
+                let a_bytes : Vec<u8> = pack_word(a, width);
                 let mut int_dist : Option<usize> = None;
-                for i in 0..(mlen-4) {
-                    if a_bytes[0] == b[i]
-                        && a_bytes[1] == b[i+1]
-                        && a_bytes[2] == b[i+2]
-                        && a_bytes[3] == b[i+3] {
+                for i in 0..(mlen-width) {
+                    if a_bytes[..] == b[i..(i+width)] {
                             /* we found a match */
                             int_dist = Some(i);
             //                println!("---> found {:08x} at offset {}/{} = {}",
@@ -2251,7 +2879,7 @@ impl RPattern {
                         let mut nearest = 1.0;
                         let v = &regs_deref[idx];
                         if let &Some(ref vd) = v {
-                                let y = get_word32le(vd,0);
+                                let y = get_word(vd, 0, deref_width(x));
                                 //println!(">>> Comparing {:?}|{:x}->{:x} to {:x}", regs_deref[idx].as_ref().unwrap(),regs[idx], y, x);
                                 if y == x {
                                     exact_deref_matches += 1.0;
@@ -2325,6 +2953,189 @@ impl RPattern {
 } /* TODO add some unit tests. i think there's an arithmetic error up here, 
      which is causing a perfect champion to receive a fitness of 0.003... */
 pub const MAXPATLEN : usize = 12;
+
+/* A system call we'd like the chain to make. args is a pattern over
+ * the call's arguments, rather than the registers that carry them,
+ * and ptr_args are arguments that must be NULL or point somewhere
+ * readable (argv, envp). */
+#[derive(Hash,Debug,Clone,PartialEq,Eq,Serialize,Deserialize)]
+pub struct SyscallGoal {
+        pub num: u64,
+        pub args: RPattern,
+        pub ptr_args: Vec<usize>,
+}
+
+const SYSCALL_REACHED_WEIGHT : f32 = 0.4;
+const SYSCALL_NUM_WEIGHT     : f32 = 0.2;
+const SYSCALL_ARGS_WEIGHT    : f32 = 0.4;
+
+impl SyscallGoal {
+        /* Either a named goal ("execve"), or a hex syscall number and
+         * an argument pattern, as in "b:&68732f6e69622f,*,*", where
+         * * marks a NULL-or-valid pointer argument. */
+        pub fn new (s: &str, mode: MachineMode) -> Self {
+            if s == "execve" { return SyscallGoal::execve(mode) };
+            let mut halves = s.splitn(2, ':');
+            let num = u64::from_str_radix(halves.next().unwrap(), 16)
+                          .expect(&format!("Failed to parse syscall number in {:?}", s));
+            let argstr = halves.next().unwrap_or("");
+            let mut ptr_args = Vec::new();
+            let parts : Vec<&str> = argstr.split(',')
+                                          .enumerate()
+                                          .map(|(i,p)| if p == "*" { 
+                                              ptr_args.push(i); "_" 
+                                          } else { p })
+                                          .collect();
+            let args = if argstr.is_empty() {
+                RPattern { regvals_diff: Vec::new(), regvals_prediff: Vec::new() }
+            } else {
+                RPattern::new(&parts.join(","))
+            };
+            SyscallGoal { num: num, args: args, ptr_args: ptr_args }
+        }
+
+        /* execve("/bin/sh", argv, envp), with argv and envp either
+         * NULL or pointing somewhere we could read. */
+        pub fn execve (mode: MachineMode) -> Self {
+            let num = mode.execve_num();
+            let mut args = RPattern { regvals_diff: Vec::new(), 
+                                      regvals_prediff: Vec::new() };
+            /* "/bin/sh\0", as deref_width reads it */
+            args.push((0, RVal::Deref(0x0068732f6e69622f)));
+            SyscallGoal { num: num, args: args, ptr_args: vec![1,2] }
+        }
+
+        /* Graded by whether a syscall was made at all, whether it was
+         * the right one, and how close the arguments came. If the
+         * chain never got there, the registers it ended with stand in
+         * for the arguments, so there's still a gradient to climb. */
+        pub fn distance (&self,
+                         call: &Option<Syscall>,
+                         regs: &Vec<u64>,
+                         regs_deref: &Vec<Option<Vec<u8>>>,
+                         mode: MachineMode) -> f32 {
+            let arg_regs = mode.syscall_arg_regs();
+            let args : Vec<u64> = arg_regs.iter().map(|&r| regs[r]).collect();
+            let args_deref : Vec<Option<Vec<u8>>> = 
+                arg_regs.iter().map(|&r| regs_deref[r].clone()).collect();
+            let num = match call {
+                &Some(ref c) => c.num,
+                &None        => regs[mode.syscall_num_reg()],
+            };
+            let reached_err = if call.is_some() { 0.0 } else { 1.0 };
+            let num_err = f32::min(1.0, (num ^ self.num).count_ones() as f32 / 8.0);
+            let mut arg_errs = Vec::new();
+            if self.args.regvals_diff.len() > 0 {
+                arg_errs.push(self.args.distance(&args, &args_deref));
+            }
+            for &i in &self.ptr_args {
+                if i >= args.len() { continue };
+                let ok = args[i] == 0 || args_deref[i].is_some();
+                arg_errs.push(if ok { 0.0 } else { 1.0 });
+            }
+            let args_err = if arg_errs.is_empty() { 0.0 } else { mean(&arg_errs) };
+            f32::min(1.0, SYSCALL_REACHED_WEIGHT * reached_err
+                          + SYSCALL_NUM_WEIGHT * num_err
+                          + SYSCALL_ARGS_WEIGHT * args_err)
+        }
+}
+
+/* Memory contents we'd like the chain to leave behind: runs of
+ * bytes at fixed addresses, i.e. a write-what-where. */
+#[derive(Hash,Debug,Clone,PartialEq,Eq,Serialize,Deserialize)]
+pub struct MemGoal {
+        pub writes: Vec<(u64,Vec<u8>)>,
+}
+
+impl MemGoal {
+        /* Semicolon-separated hex address=value pairs, where the
+         * value is a quoted string, @symbol for the address of a
+         * symbol in the binary, or a hex word. Words are laid out the
+         * way the machine would store them.
+         * e.g. 2a000="flag";2a010=@system */
+        pub fn new (s: &str, elf_path: &str, load_base: u64, mode: MachineMode) -> Self {
+            let mut writes = Vec::new();
+            for part in s.split(';').filter(|p| !p.is_empty()) {
+                let mut halves = part.splitn(2, '=');
+                let a = halves.next().unwrap().trim_left_matches("0x");
+                let addr = u64::from_str_radix(a, 16)
+                               .expect(&format!("Failed to parse address in {:?}", part));
+                let val = halves.next()
+                                .expect(&format!("No value in {:?}", part));
+                let bytes = if val.starts_with('"') {
+                    val.trim_matches('"').as_bytes().to_vec()
+                } else if val.starts_with('@') {
+                    let sym = elf_symbol_addr(elf_path, &val[1..], load_base)
+                                  .expect(&format!("No symbol named {:?} in {}", 
+                                                   &val[1..], elf_path));
+                    pack_word_endian(sym, mode.word_size(), mode.endian())
+                } else {
+                    let n = u64::from_str_radix(val.trim_left_matches("0x"), 16)
+                                .expect(&format!("Failed to parse value in {:?}", part));
+                    pack_word_endian(n, mode.word_size(), mode.endian())
+                };
+                writes.push((addr, bytes));
+            }
+            MemGoal { writes: writes }
+        }
+
+        pub fn constants (&self) -> Vec<i32> {
+            self.writes.iter().map(|&(a,_)| a as i32).collect()
+        }
+
+        /* Mean over the target bytes. Each counts by how many bits
+         * of it were wrong once the chain had run, whether the chain
+         * wrote it or it held the right value all along. A byte we
+         * couldn't read back counts as wholly wrong, unless the run
+         * it belongs to turned up intact somewhere else, which is
+         * worth half. mem_after is the memory hatch_chain read back
+         * for us, and mem_writes what the chain changed. */
+        pub fn distance (&self,
+                         mem_after: &Vec<(u64,Vec<u8>)>,
+                         mem_writes: &Vec<(u64,Vec<u8>)>) -> f32 {
+            fn byte_at (runs: &Vec<(u64,Vec<u8>)>, addr: u64) -> Option<u8> {
+                for &(b, ref d) in runs {
+                    if b <= addr && addr < b + d.len() as u64 {
+                        return Some(d[(addr - b) as usize]);
+                    }
+                }
+                None
+            }
+            let mut errs = Vec::new();
+            for &(addr, ref want) in &self.writes {
+                let misplaced = !want.is_empty() && mem_writes.iter().any(|&(_, ref d)| {
+                    d.windows(want.len()).any(|w| w == &want[..])
+                });
+                let floor = if misplaced { 0.5 } else { 1.0 };
+                for (i, &byte) in want.iter().enumerate() {
+                    let a = addr + i as u64;
+                    let e = match byte_at(mem_after, a).or(byte_at(mem_writes, a)) {
+                        Some(b) => (b ^ byte).count_ones() as f32 / 8.0,
+                        None    => 1.0,
+                    };
+                    errs.push(f32::min(e, floor));
+                }
+            }
+            if errs.is_empty() { 0.0 } else { mean(&errs) }
+        }
+}
+
+impl Display for MemGoal {
+        fn fmt (&self, f: &mut Formatter) -> Result {
+            let parts : Vec<String> = 
+                self.writes.iter()
+                           .map(|&(a, ref d)| format!("{:08x} <- {:?}", a, d))
+                           .collect();
+            write!(f, "{}", parts.join("; "))
+        }
+}
+
+impl Display for SyscallGoal {
+        fn fmt (&self, f: &mut Formatter) -> Result {
+            write!(f, "syscall {:x} ({}) ptr args: {:?}", 
+                   self.num, self.args, self.ptr_args)
+        }
+}
 impl Display for RPattern {
         fn fmt (&self, f: &mut Formatter) -> Result {
             let blank = "________ ";
@@ -2368,49 +3179,22 @@ impl RunningAvg {
 }
 
 
-pub fn test_clump (uc: &mut unicorn::CpuARM,
-                                          clump: &Clump) -> usize {
-        let input = vec![2,2,2,2,
-                         2,2,2,2,
-                         2,2,2,2,
-                         2,2,2,2];
-        let inregs = vec![ 0, 1, 2, 3,
-                           4, 5, 6, 7,
-                           8, 9,10,11,
-                           12,13,14,15];
-        let mut twos = repeat(2);
-        let mut cl = clump.clone();
-        saturate_clump(&mut cl, &mut twos);
-        let vanilla = Chain::new(vec![cl]);
-        let res = hatch_chain(uc, &vanilla, &input, &inregs, true);
-        //println!("\n{}",res);
-        let mut differ = 0;
-        for r in res.registers[..12].to_vec() {
-            if r != 2 {
-                differ = 1;
-                break;
-            }
-        }
-        let smooth = if res.error == None {2} else {0};
-        differ | smooth
-}
-
-const NOCHANGE_CRASH_BUCKET    : usize = 0;
-const CHANGE_CRASH_BUCKET      : usize = 1;
-const NOCHANGE_NOCRASH_BUCKET  : usize = 2;
-const CHANGE_NOCRASH_BUCKET    : usize = 3;
-
 pub fn random_chain (clumps:  &Vec<Clump>,
                      params:  &Params,
                      pool:    &mut Mangler,
-                     rng:     &mut ThreadRng) -> Chain {
+                     rng:     &mut RoperRng) -> Chain {
         let max_len = params.max_start_len;
         let min_len = params.min_start_len;
         let rlen  = rng.gen::<usize>() % (max_len - min_len) + min_len;
         let mut genes : Vec<Clump> = Vec::new();
         for _ in 0..rlen {
+            /* prefer gadgets whose addresses we can actually send */
             let mut clump = clumps[rng.gen::<usize>() % clumps.len()].clone();
-            saturate_clump(&mut clump, pool);
+            for _ in 0..CLEAN_DRAWS {
+                if clump.is_clean(&params.bad_bytes) { break };
+                clump = clumps[rng.gen::<usize>() % clumps.len()].clone();
+            }
+            saturate_clump(&mut clump, pool, &params.bad_bytes);
             for i in 1..clump.words.len() {
                 let roll = rng.gen::<f32>();
                 if roll < params.stack_input_sampling {
@@ -2424,40 +3208,18 @@ pub fn random_chain (clumps:  &Vec<Clump>,
             }
             genes.push(clump);
         }
-        Chain::new(genes)
-}
-
-pub fn random_chain_from_buckets (clump_buckets:  &Vec<Vec<Clump>>,
-                                                                        min_len: usize,
-                                                                        max_len: usize,
-                                                                        pool:    &mut Mangler,
-                                                                        rng:     &mut ThreadRng) -> Chain {
-        let rlen  = rng.gen::<usize>() % (max_len - min_len) + min_len;
-        let mut genes : Vec<Clump> = Vec::new();
-        for _ in 0..rlen {
-            let clumps : &Vec<Clump>;
-            
-            let roll = rng.gen::<usize>() % 128;
-            if roll == 0 { 
-                clumps = &clump_buckets[NOCHANGE_CRASH_BUCKET];
-            } else if 1 <= roll && roll < 4 {
-                clumps = &clump_buckets[NOCHANGE_NOCRASH_BUCKET];
-            } else if 4 <= roll && roll < 7 {
-                clumps = &clump_buckets[CHANGE_CRASH_BUCKET];
-            } else {
-                clumps = &clump_buckets[CHANGE_NOCRASH_BUCKET];
-            };
-
-            let mut c = clumps[rng.gen::<usize>() % clumps.len()].clone();
-            saturate_clump(&mut c, pool);
-            genes.push(c);
+        /* trim to fit the payload limit, if there is one */
+        while params.max_packed_len > 0
+              && genes.len() > 1
+              && concatenate(&genes).len() * params.mode.word_size() 
+                 > params.max_packed_len {
+            genes.pop();
         }
-        Chain::new(genes)
+        Chain::new(genes, rng)
 }
 
-
-pub fn mark_heatmap (heatmap: &mut HashMap<u32,usize>,
-                     visits: &Vec<Vec<u32>>) {
+pub fn mark_heatmap (heatmap: &mut HashMap<u64,usize>,
+                     visits: &Vec<Vec<u64>>) {
     for visits_row in visits {
         for addr in visits_row {
             let count = heatmap.entry(*addr)
@@ -2467,7 +3229,7 @@ pub fn mark_heatmap (heatmap: &mut HashMap<u32,usize>,
     }
 }
 
-pub fn dump_heatmap (heatmap: &HashMap<u32,usize>, 
+pub fn dump_heatmap (heatmap: &HashMap<u64,usize>, 
                      elfpath: &str,
                      path: &str) {
     let mut file = OpenOptions::new()
@@ -2481,7 +3243,7 @@ pub fn dump_heatmap (heatmap: &HashMap<u32,usize>,
     file.write(b";; --- BEGIN HEATMAP ---\n");
     file.write(format!("(\n  (:elfpath . \"{}\") ;; don't parse as int!\n",
                        elfpath).as_bytes());
-    let mut addrs : Vec<u32> = heatmap.keys()
+    let mut addrs : Vec<u64> = heatmap.keys()
                                       .map(|a| *a)
                                       .collect();
     addrs.sort();
@@ -2494,3 +3256,158 @@ pub fn dump_heatmap (heatmap: &HashMap<u32,usize>,
     file.write(b")\n;; --- END HEATMAP ---\n");
     
 }
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+
+        fn eq (s: &str) -> RPatEq {
+            RPatEq::new(s, MachineMode::ARM)
+        }
+
+        #[test]
+        fn parses_each_kind_of_equation () {
+            assert_eq!(eq("r7=0xb").expr, RExpr::Immed(0xb));
+            assert_eq!(eq("r7 == 11").expr, RExpr::Immed(11));
+            assert_eq!(eq("r2=r1").expr, RExpr::Reg(1, 0));
+            assert_eq!(eq("r0=sp+4").expr, RExpr::Reg(13, 4));
+            assert_eq!(eq("r0=lr-0x10").expr, RExpr::Reg(14, -16));
+            assert_eq!(eq("r1=*r2").expr, RExpr::Deref(2));
+            assert_eq!(eq("r0=&0x41424344").expr,
+                       RExpr::Points(vec![0x44, 0x43, 0x42, 0x41]));
+            assert_eq!(eq("r0=&\"/bin/sh\"").expr,
+                       RExpr::Points(b"/bin/sh\0".to_vec()));
+            let e = eq("r3&0xff=0x41");
+            assert_eq!((e.reg, e.mask, e.expr), (3, 0xff, RExpr::Immed(0x41)));
+            assert_eq!(eq("r4=5").mask, !0);
+        }
+
+        #[test]
+        fn parses_ranges () {
+            assert_eq!(eq("r3 in [0x1000,0x2000)").expr, RExpr::Range(0x1000, 0x2000));
+            assert_eq!(eq("r3 in (0x1000,0x2000]").expr, RExpr::Range(0x1001, 0x2001));
+            assert_eq!(eq("sp in [0, 16]").reg, 13);
+        }
+
+        #[test]
+        fn in_inside_a_value_is_not_a_range () {
+            assert_eq!(eq("r0=&\"log in \"").expr,
+                       RExpr::Points(b"log in \0".to_vec()));
+            let p = RPattern2::new("r0=&\"sign in, then out\", r3 in [1,2)",
+                                   MachineMode::ARM);
+            assert_eq!(p.0.len(), 2);
+            assert_eq!(p.0[0].expr, RExpr::Points(b"sign in, then out\0".to_vec()));
+            assert_eq!(p.0[1].expr, RExpr::Range(1, 2));
+        }
+
+        #[test]
+        fn splits_patterns () {
+            let p = RPattern2::new("r0=1; r1=2, r2 in [3,4)", MachineMode::ARM);
+            assert_eq!(p.0.iter().map(|e| e.reg).collect::<Vec<usize>>(),
+                       vec![0, 1, 2]);
+            assert!(RPattern2::is_equation("r0=1"));
+            assert!(RPattern2::is_equation("r0 in [1,2)"));
+            assert!(!RPattern2::is_equation("0b,_,&68732f6e"));
+        }
+
+        #[test]
+        fn display_reads_back () {
+            let p = RPattern2::new("r1=0, r7=0xb, r2=r1, r0=r13-8, r3&0xff=*r4, r5 in [0x10,0x20)",
+                                   MachineMode::ARM);
+            assert_eq!(RPattern2::new(&format!("{}", p), MachineMode::ARM), p);
+        }
+
+        fn dist (s: &str, regs: &Vec<u64>, deref: &Vec<Option<Vec<u8>>>) -> f32 {
+            eq(s).distance(regs, deref, MachineMode::ARM)
+        }
+
+        #[test]
+        fn exact_distances () {
+            let mut regs = vec![0; 16];
+            regs[0] = 0xb;
+            regs[1] = 0x1000;
+            regs[2] = 0x1008;
+            let none = vec![None; 16];
+            assert_eq!(dist("r0=0xb", &regs, &none), 0.0);
+            assert_eq!(dist("r2=r1+8", &regs, &none), 0.0);
+            assert_eq!(dist("r1=r2-8", &regs, &none), 0.0);
+            /* one bit off is nearer than a different value altogether */
+            let near = dist("r0=0xa", &regs, &none);
+            let far  = dist("r0=0xdeadbeef", &regs, &none);
+            assert!(0.0 < near && near < far && far <= 1.0);
+            let off = dist("r2=r1", &regs, &none);
+            assert!(0.0 < off && off <= 1.0);
+        }
+
+        #[test]
+        fn range_distances () {
+            let mut regs = vec![0; 16];
+            let none = vec![None; 16];
+            let r = "r3 in [0x1000,0x2000)";
+            for &v in &[0x1000, 0x1800, 0x1fff] {
+                regs[3] = v;
+                assert_eq!(dist(r, &regs, &none), 0.0, "{:x} is in range", v);
+            }
+            /* outside, measured from the nearer end */
+            regs[3] = 0x2000;
+            assert_eq!(dist(r, &regs, &none), dist("r3=0x1fff", &regs, &none));
+            regs[3] = 0xfff;
+            assert_eq!(dist(r, &regs, &none), dist("r3=0x1000", &regs, &none));
+            assert!(dist(r, &regs, &none) > 0.0);
+        }
+
+        #[test]
+        fn masked_distances () {
+            let mut regs = vec![0; 16];
+            regs[3] = 0x12345641;
+            let none = vec![None; 16];
+            assert_eq!(dist("r3&0xff=0x41", &regs, &none), 0.0);
+            assert!(dist("r3&0xff=0x42", &regs, &none) > 0.0);
+            assert!(dist("r3=0x41", &regs, &none) > 0.0);
+        }
+
+        #[test]
+        fn deref_distances () {
+            let mut regs = vec![0; 16];
+            regs[1] = 0x41424344;
+            let mut deref = vec![None; 16];
+            deref[4] = Some(vec![0x44, 0x43, 0x42, 0x41]);
+            assert_eq!(dist("r1=*r4", &regs, &deref), 0.0);
+            assert!(dist("r1=*r5", &regs, &deref) == 1.0, "r5 points nowhere");
+            deref[4] = Some(vec![0x45, 0x43, 0x42, 0x41]);
+            let d = dist("r1=*r4", &regs, &deref);
+            assert!(0.0 < d && d < 1.0);
+            /* too short to hold a word */
+            deref[4] = Some(vec![0x44, 0x43]);
+            assert_eq!(dist("r1=*r4", &regs, &deref), 1.0);
+        }
+
+        #[test]
+        fn memory_already_right_counts () {
+            let goal = MemGoal { writes: vec![(0x2a000, b"flag".to_vec()),
+                                              (0x2a010, vec![0x78, 0x56, 0, 0])] };
+            /* nothing written, but the memory already says what we want */
+            let after = vec![(0x2a000, b"flag".to_vec()),
+                             (0x2a010, vec![0x78, 0x56, 0, 0])];
+            assert_eq!(goal.distance(&after, &vec![]), 0.0);
+            /* only the low half of the word written, the zeroes were there */
+            let writes = vec![(0x2a010, vec![0x78, 0x56])];
+            assert_eq!(goal.distance(&after, &writes), 0.0);
+        }
+
+        #[test]
+        fn memory_distance_is_graded () {
+            let goal = MemGoal { writes: vec![(0x100, vec![0xff, 0xff])] };
+            let half = vec![(0x100, vec![0xff, 0x0f])];
+            assert_eq!(goal.distance(&half, &half), 0.25);
+            /* unreadable, but written intact somewhere else */
+            assert_eq!(goal.distance(&vec![], &vec![(0x200, vec![0xff, 0xff])]), 0.5);
+            assert_eq!(goal.distance(&vec![], &vec![]), 1.0);
+        }
+
+        #[test]
+        #[should_panic(expected = "as a register equation")]
+        fn rejects_unknown_registers () {
+            eq("r99=1");
+        }
+}
@@ -0,0 +1,457 @@
+// Semantic profiling of harvested gadgets. Each clump is run in
+// isolation on a handful of random register and stack states, and
+// we look at what comes out the other end to guess what it does:
+// "r0 <- stack[1]", "r3 <- r3 + r1", and so on. It's black-box
+// guesswork, and will miss anything that only shows up for
+// particular inputs, but it's a lot better than nothing.
+extern crate unicorn;
+extern crate rand;
+
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Display,Formatter,Result};
+use rand::Rng;
+use unicorn::*;
+
+use roper::util::*;
+use roper::evolve::saturate_clump;
+use roper::hatchery::*;
+use roper::phylostructs::*;
+use roper::ontostructs::*;
+use roper::isa::Isa;
+
+pub const PROFILE_TRIALS : usize = 6;
+
+/* Every general-purpose register except sp and pc, which hatch_chain
+ * sets up itself, and MIPS's $zero, which nothing can change. */
+pub fn profiled_regs (mode: MachineMode) -> Vec<usize> {
+        (0..mode.num_registers()).filter(|&r| r != mode.sp_num() 
+                                             && r != mode.pc_num()
+                                             && !(mode.is_mips() && r == 0))
+                                .collect()
+}
+
+pub fn reg_name (r: usize, mode: MachineMode) -> String {
+        mode.reg_name(r)
+}
+
+#[derive(Eq,PartialEq,Debug,Clone,Copy,Serialize,Deserialize)]
+pub enum Op {
+        Add,
+        Sub,
+        Xor,
+        And,
+        Orr,
+        Mul,
+}
+
+static OPS : [Op; 6] = [Op::Add, Op::Sub, Op::Xor, Op::And, Op::Orr, Op::Mul];
+
+impl Op {
+        fn apply (&self, a: u64, b: u64, width: usize) -> u64 {
+            word_mask(width) & match *self {
+                Op::Add => a.wrapping_add(b),
+                Op::Sub => a.wrapping_sub(b),
+                Op::Xor => a ^ b,
+                Op::And => a & b,
+                Op::Orr => a | b,
+                Op::Mul => a.wrapping_mul(b),
+            }
+        }
+        fn sym (&self) -> &'static str {
+            match *self {
+                Op::Add => "+",
+                Op::Sub => "-",
+                Op::Xor => "^",
+                Op::And => "&",
+                Op::Orr => "|",
+                Op::Mul => "*",
+            }
+        }
+}
+
+/* What a gadget does to a single register. Stack slots are counted
+ * in words from the slot just below the entry SP, the same way
+ * Clump::slot_of counts them. */
+#[derive(PartialEq,Debug,Clone,Serialize,Deserialize)]
+pub enum Effect {
+        Load (usize, usize),            // reg <- stack[slot]
+        Copy (usize, usize),            // reg <- reg
+        Const (usize, u64),             // reg <- imm
+        AddImm (usize, usize, i64),     // reg <- reg + imm
+        Arith (usize, usize, Op, usize),// reg <- reg op reg
+        Clobber (usize),                // reg <- ?
+}
+
+impl Effect {
+        pub fn dst (&self) -> usize {
+            match *self {
+                Effect::Load(d,_)       => d,
+                Effect::Copy(d,_)       => d,
+                Effect::Const(d,_)      => d,
+                Effect::AddImm(d,_,_)   => d,
+                Effect::Arith(d,_,_,_)  => d,
+                Effect::Clobber(d)      => d,
+            }
+        }
+        pub fn srcs (&self) -> Vec<usize> {
+            match *self {
+                Effect::Copy(_,s)       => vec![s],
+                Effect::AddImm(_,s,_)   => vec![s],
+                Effect::Arith(_,a,_,b)  => vec![a,b],
+                _                       => Vec::new(),
+            }
+        }
+        /* Register names depend on the machine, so this stands in
+         * for Display. */
+        pub fn show (&self, mode: MachineMode) -> String {
+            let n = |r| reg_name(r, mode);
+            match *self {
+                Effect::Load(d,k)      => format!("{} <- stack[{}]", n(d), k),
+                Effect::Copy(d,s)      => format!("{} <- {}", n(d), n(s)),
+                Effect::Const(d,c)     => format!("{} <- 0x{:x}", n(d), c),
+                Effect::AddImm(d,s,c) if c < 0 
+                                       => format!("{} <- {} - 0x{:x}", n(d), n(s),
+                                                  c.wrapping_neg()),
+                Effect::AddImm(d,s,c)  => format!("{} <- {} + 0x{:x}", n(d), n(s), c),
+                Effect::Arith(d,a,op,b) => format!("{} <- {} {} {}", n(d),
+                                                   n(a), op.sym(), n(b)),
+                Effect::Clobber(d)     => format!("{} <- ?", n(d)),
+            }
+        }
+}
+
+/* Where a memory access lands, relative to the gadget's inputs */
+#[derive(PartialEq,Debug,Clone,Copy,Serialize,Deserialize)]
+pub enum Access {
+        Rel (usize, i32),
+        Abs (u64),
+        Wild,
+}
+
+impl Access {
+        pub fn show (&self, mode: MachineMode) -> String {
+            match *self {
+                Access::Rel(r,0)          => format!("mem[{}]", reg_name(r, mode)),
+                Access::Rel(r,o) if o < 0 => format!("mem[{}-0x{:x}]", reg_name(r, mode), -o),
+                Access::Rel(r,o)          => format!("mem[{}+0x{:x}]", reg_name(r, mode), o),
+                Access::Abs(a)            => format!("mem[0x{:08x}]", a),
+                Access::Wild              => "mem[?]".to_string(),
+            }
+        }
+}
+
+#[derive(PartialEq,Debug,Clone,Serialize,Deserialize)]
+pub struct Profile {
+        pub effects    : Vec<Effect>,
+        pub reads      : Vec<usize>,
+        pub writes     : Vec<usize>,
+        pub mem_reads  : Vec<Access>,
+        pub mem_writes : Vec<Access>,
+        pub sp_delta   : Option<i32>, // as observed, in words
+        #[serde(with = "::roper::serial::float")]
+        pub crash_rate : f32,
+        pub mode       : MachineMode, // of the clump profiled
+}
+
+impl Profile {
+        /// (register, stack slot) for every register the gadget
+        /// lets us set from the stack.
+        pub fn loads (&self) -> Vec<(usize,usize)> {
+            self.effects.iter().filter_map(|e| match *e {
+                Effect::Load(d,k) => Some((d,k)),
+                _                 => None,
+            }).collect()
+        }
+        /* Always crashes, or never does anything we can see. */
+        pub fn is_dud (&self) -> bool {
+            self.crash_rate >= 1.0
+            || (self.effects.is_empty() && self.mem_writes.is_empty())
+        }
+}
+
+impl Display for Profile {
+        fn fmt (&self, f: &mut Formatter) -> Result {
+            let mut parts : Vec<String> =
+                self.effects.iter().map(|e| e.show(self.mode)).collect();
+            for a in &self.mem_writes {
+                parts.push(format!("{} <- ?", a.show(self.mode)));
+            }
+            for a in &self.mem_reads {
+                parts.push(format!("? <- {}", a.show(self.mode)));
+            }
+            match self.sp_delta {
+                Some(d) => parts.push(format!("sp += {}", 
+                                              d * self.mode.word_size() as i32)),
+                None    => parts.push("sp += ?".to_string()),
+            }
+            if self.crash_rate > 0.0 {
+                parts.push(format!("crashes {}%", (self.crash_rate * 100.0) as usize));
+            }
+            write!(f, "{}", parts.join("; "))
+        }
+}
+
+struct Trial {
+        regs_in  : Vec<u64>,
+        stack    : Vec<u64>,
+        regs_out : Vec<u64>,
+        reads    : Vec<u64>,
+        writes   : Vec<u64>,
+        sp_delta : i32,
+}
+
+/* Random values, but half the time ones that point into writeable
+ * memory, so that gadgets that dereference a register get a chance
+ * to run to the end. They're 32-bit either way, since that's what
+ * hatch_chain takes as input. */
+fn random_state (uc: &Emu, rng: &mut RoperRng, pointy: bool) -> Vec<u64> {
+        let stack = uc.stack();
+        let scratch = uc.mem_regions().unwrap()
+                        .into_iter()
+                        .find(|r| r.begin != stack.begin
+                                  && r.perms.intersects(PROT_WRITE));
+        (0..uc.mode.num_registers()).map(|_| match scratch {
+            Some(ref r) if pointy => {
+                let size = r.end - r.begin;
+                (r.begin + size / 4 + rng.gen::<u64>() % (size / 2)) & !7
+            },
+            _ => rng.gen::<u32>() as u64,
+        }).collect()
+}
+
+fn run_trial (uc: &mut Emu, clump: &Clump, rng: &mut RoperRng, pointy: bool)
+              -> Option<Trial> {
+        let regs_in = random_state(uc, rng, pointy);
+        let ws = uc.word_size();
+        let profiled = profiled_regs(uc.mode);
+        let mut cl = clump.clone();
+        cl.words.truncate(1);
+        cl.enabled = true;
+        cl.input_slots = Vec::new();
+        let fill : Vec<u64> = (1..cl.sp_delta).map(|_| rng.gen::<u64>() & word_mask(ws))
+                                              .collect();
+        saturate_clump(&mut cl, &mut fill.into_iter(), &Vec::new());
+        let chain = Chain::new(vec![cl], rng);
+        let packed = chain.pack();
+        let stack_words : Vec<u64> = (0..(packed.len() / ws)).map(|i| get_word_endian(&packed, i*ws, ws, uc.endian()))
+                                                             .collect();
+        let input : Vec<i32> = profiled.iter().map(|&r| regs_in[r] as i32).collect();
+        let inregs : Vec<usize> = profiled.clone();
+
+        let stack = uc.stack();
+        let (hook_base, hook_end) = (uc.settings().hook_base, uc.settings().hook_end());
+        let reads_rc : Rc<RefCell<Vec<u64>>> = Rc::new(RefCell::new(Vec::new()));
+        let writes_rc : Rc<RefCell<Vec<u64>>> = Rc::new(RefCell::new(Vec::new()));
+        let mut hooks = Vec::new();
+        {
+            let r = reads_rc.clone();
+            let cb = move |_: &Unicorn, _: MemType, addr: u64, _: usize, _: i64| {
+                r.borrow_mut().push(addr);
+                true
+            };
+            match uc.add_mem_hook(MemHookType::MEM_READ,
+                                  hook_base, hook_end, cb) {
+                Ok(h)  => hooks.push(h),
+                Err(_) => (),
+            };
+            let w = writes_rc.clone();
+            let cb = move |_: &Unicorn, _: MemType, addr: u64, _: usize, _: i64| {
+                w.borrow_mut().push(addr);
+                true
+            };
+            match uc.add_mem_hook(MemHookType::MEM_WRITE,
+                                  hook_base, hook_end, cb) {
+                Ok(h)  => hooks.push(h),
+                Err(_) => (),
+            };
+        }
+        let res = hatch_chain(uc, &chain, &input, &inregs, true);
+        for h in hooks.iter() {
+            let _ = uc.remove_hook(*h);
+        }
+        if res.error.is_some() || res.null {
+            return None;
+        }
+        /* the gadget's own pops don't count as memory accesses */
+        let off_stack = |a: &u64| *a < stack.begin || *a >= stack.end;
+        let reads  = reads_rc.borrow().iter().cloned().filter(&off_stack).collect();
+        let writes = writes_rc.borrow().iter().cloned().filter(&off_stack).collect();
+        let sp_in = uc.payload_entry() + ws as u64;
+        let sp_out = res.registers[uc.mode.sp_num()];
+        Some(Trial {
+            regs_in  : regs_in,
+            stack    : stack_words,
+            sp_delta : (sp_out.wrapping_sub(sp_in) as i64 / ws as i64) as i32,
+            regs_out : res.registers,
+            reads    : reads,
+            writes   : writes,
+        })
+}
+
+fn all<F> (trials: &Vec<Trial>, f: F) -> bool
+        where F: Fn(&Trial) -> bool {
+        trials.iter().all(f)
+}
+
+fn infer_effect (trials: &Vec<Trial>, d: usize, profiled: &Vec<usize>, ws: usize) 
+                 -> Option<Effect> {
+        let out = |t: &Trial| t.regs_out[d];
+        if all(trials, |t| out(t) == t.regs_in[d]) {
+            return None;
+        }
+        let depth = trials.iter().map(|t| t.stack.len()).min().unwrap_or(0);
+        for k in 1..depth {
+            if all(trials, |t| out(t) == t.stack[k]) {
+                return Some(Effect::Load(d,k));
+            }
+        }
+        for &s in profiled.iter() {
+            if all(trials, |t| out(t) == t.regs_in[s]) {
+                return Some(Effect::Copy(d,s));
+            }
+        }
+        let c = out(&trials[0]);
+        if all(trials, |t| out(t) == c) {
+            return Some(Effect::Const(d,c));
+        }
+        let diff = |t: &Trial, s: usize| out(t).wrapping_sub(t.regs_in[s]) & word_mask(ws);
+        for &s in profiled.iter() {
+            let imm = diff(&trials[0], s);
+            if all(trials, |t| diff(t, s) == imm) {
+                return Some(Effect::AddImm(d,s,sign_extend(imm, ws)));
+            }
+        }
+        for &a in profiled.iter() {
+            for &b in profiled.iter() {
+                for op in OPS.iter() {
+                    if all(trials, |t| out(t) == op.apply(t.regs_in[a], t.regs_in[b], ws)) {
+                        return Some(Effect::Arith(d,a,*op,b));
+                    }
+                }
+            }
+        }
+        Some(Effect::Clobber(d))
+}
+
+fn infer_accesses (trials: &Vec<Trial>, 
+                   get: &dyn Fn(&Trial) -> &Vec<u64>,
+                   profiled: &Vec<usize>)
+                   -> Vec<Access> {
+        let n = get(&trials[0]).len();
+        if !all(trials, |t| get(t).len() == n) {
+            return vec![Access::Wild];
+        }
+        let mut accesses : Vec<Access> = Vec::new();
+        for i in 0..n {
+            let a0 = get(&trials[0])[i];
+            let mut acc = if all(trials, |t| get(t)[i] == a0) {
+                Access::Abs(a0)
+            } else {
+                Access::Wild
+            };
+            /* prefer a register base, and the nearest one at that */
+            let mut best : Option<i32> = None;
+            for &s in profiled.iter() {
+                let off = a0.wrapping_sub(trials[0].regs_in[s]) as i32;
+                if all(trials, |t| get(t)[i].wrapping_sub(t.regs_in[s]) as i32 == off)
+                   && best.map_or(true, |b| off.abs() < b.abs()) {
+                    best = Some(off);
+                    acc = Access::Rel(s, off);
+                }
+            }
+            if accesses.last() != Some(&acc) {
+                accesses.push(acc);
+            }
+        }
+        accesses
+}
+
+pub fn profile_clump (uc: &mut Emu, clump: &Clump, rng: &mut RoperRng)
+                      -> Profile {
+        let profiled = profiled_regs(uc.mode);
+        let ws = uc.word_size();
+        let mut trials : Vec<Trial> = Vec::new();
+        for i in 0..PROFILE_TRIALS {
+            match run_trial(uc, clump, rng, i % 2 == 0) {
+                Some(t) => trials.push(t),
+                None    => (),
+            }
+        }
+        let crash_rate = 1.0 - (trials.len() as f32 / PROFILE_TRIALS as f32);
+        if trials.is_empty() {
+            return Profile {
+                effects    : Vec::new(),
+                reads      : Vec::new(),
+                writes     : Vec::new(),
+                mem_reads  : Vec::new(),
+                mem_writes : Vec::new(),
+                sp_delta   : None,
+                crash_rate : crash_rate,
+                mode       : clump.mode,
+            };
+        }
+        let effects : Vec<Effect> = profiled.iter()
+                                            .filter_map(|&d| infer_effect(&trials, d, 
+                                                                          &profiled, ws))
+                                            .collect();
+        let mem_reads  = infer_accesses(&trials, &|t| &t.reads, &profiled);
+        let mem_writes = infer_accesses(&trials, &|t| &t.writes, &profiled);
+        let mut reads : Vec<usize> = effects.iter().flat_map(|e| e.srcs()).collect();
+        for a in mem_reads.iter().chain(mem_writes.iter()) {
+            if let Access::Rel(r,_) = *a { reads.push(r) };
+        }
+        reads.sort();
+        reads.dedup();
+        let writes : Vec<usize> = effects.iter().map(|e| e.dst()).collect();
+        let d0 = trials[0].sp_delta;
+        Profile {
+            effects    : effects,
+            reads      : reads,
+            writes     : writes,
+            mem_reads  : mem_reads,
+            mem_writes : mem_writes,
+            sp_delta   : if all(&trials, |t| t.sp_delta == d0) {Some(d0)} else {None},
+            crash_rate : crash_rate,
+            mode       : clump.mode,
+        }
+}
+
+/* What the ooze, taken as a whole, can do. */
+pub fn capability_summary (clumps: &Vec<Clump>) -> String {
+        let mut loads   : HashMap<usize,usize> = HashMap::new();
+        let mut moves   : HashMap<usize,usize> = HashMap::new();
+        let mut duds    = 0;
+        let mut stores  = 0;
+        let mut unprofiled = 0;
+        for clump in clumps {
+            let p = match clump.profile {
+                Some(ref p) => p,
+                None        => { unprofiled += 1; continue },
+            };
+            if p.is_dud() { duds += 1; continue };
+            if !p.mem_writes.is_empty() { stores += 1 };
+            for e in &p.effects {
+                match *e {
+                    Effect::Load(d,_)  => *loads.entry(d).or_insert(0) += 1,
+                    Effect::Clobber(_) => (),
+                    _                  => *moves.entry(e.dst()).or_insert(0) += 1,
+                }
+            }
+        }
+        let mode = match clumps.first() {
+            Some(c) => c.mode,
+            None    => MachineMode::ARM,
+        };
+        let mut s = String::new();
+        s.push_str(&format!("[*] Profiled {} gadgets: {} duds, {} unprofiled, {} write memory\n",
+                            clumps.len(), duds, unprofiled, stores));
+        for r in profiled_regs(mode) {
+            s.push_str(&format!("[+] {:>3}: loaded from stack by {:>5}, computed by {:>5}\n",
+                                reg_name(r, mode),
+                                loads.get(&r).unwrap_or(&0),
+                                moves.get(&r).unwrap_or(&0)));
+        }
+        s
+}
@@ -1 +1,518 @@
-use roper::phylostructs;
+// Experiment presets, as s-expressions, in the spirit of the files
+// in lisp/roper/configs. A config is a list of (name value) forms,
+// one per parameter, where the names are those of the Params fields,
+// with hyphens or underscores as you please:
+//
+//   ;; iris, with a bigger brood
+//   (binary-path "/home/vagrant/ROPER/data/openssl")
+//   (data "/home/vagrant/ROPER/data/iris.data")
+//   (population-size 2048)
+//   (brood-size 4)
+//   (selection-method :tournament)
+//   (bad-bytes (#x00 #x0a))
+//   (fitness-sharing t)
+//
+// (defparameter <name> value "docstring") is read the same way.
+// Numbers are decimal, or hex with #x or 0x. Booleans are t and nil.
+// A few names that aren't Params fields pick the task, as the
+// corresponding command line options do: pattern, syscall, memory,
+// data, kafka, num-attrs and num-classes; log-dir stands in for -o.
+// Whatever is given on the command line wins over the file.
+use std::fs::{File,OpenOptions};
+use std::io::prelude::*;
+use std::fmt::{self,Display,Formatter};
+use std::result;
+
+use roper::phylostructs::*;
+use roper::firmware::ImageFormat;
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Sexp {
+        Atom(String),
+        Str(String),
+        List(Vec<Sexp>),
+}
+
+impl Display for Sexp {
+        fn fmt (&self, f: &mut Formatter) -> fmt::Result {
+            match self {
+                &Sexp::Atom(ref a) => write!(f, "{}", a),
+                &Sexp::Str(ref s)  => write!(f, "{:?}", s),
+                &Sexp::List(ref v) => {
+                    let parts : Vec<String> = v.iter()
+                                               .map(|x| format!("{}", x))
+                                               .collect();
+                    write!(f, "({})", parts.join(" "))
+                },
+            }
+        }
+}
+
+/* Reads every top-level form in s */
+pub fn read_sexps (s: &str) -> result::Result<Vec<Sexp>, String> {
+        let chars : Vec<char> = s.chars().collect();
+        let mut i = 0;
+        let mut stack : Vec<Vec<Sexp>> = vec![Vec::new()];
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                ';' => while i < chars.len() && chars[i] != '\n' { i += 1 },
+                '(' => { stack.push(Vec::new()); i += 1; },
+                ')' => {
+                    if stack.len() < 2 {
+                        return Err(format!("unbalanced ) at offset {}", i));
+                    }
+                    let l = stack.pop().unwrap();
+                    stack.last_mut().unwrap().push(Sexp::List(l));
+                    i += 1;
+                },
+                '"' => {
+                    let mut t = String::new();
+                    i += 1;
+                    while i < chars.len() && chars[i] != '"' {
+                        if chars[i] == '\\' && i + 1 < chars.len() { i += 1 };
+                        t.push(chars[i]);
+                        i += 1;
+                    }
+                    if i == chars.len() {
+                        return Err("unterminated string".to_string());
+                    }
+                    stack.last_mut().unwrap().push(Sexp::Str(t));
+                    i += 1;
+                },
+                '\'' => i += 1, // quoted lists read as plain lists
+                _ if c.is_whitespace() => i += 1,
+                _ => {
+                    let start = i;
+                    while i < chars.len()
+                          && !chars[i].is_whitespace()
+                          && !"();\"".contains(chars[i]) {
+                        i += 1;
+                    }
+                    let a : String = chars[start..i].iter().cloned().collect();
+                    stack.last_mut().unwrap().push(Sexp::Atom(a));
+                },
+            }
+        }
+        if stack.len() != 1 {
+            return Err("unbalanced (".to_string());
+        }
+        Ok(stack.pop().unwrap())
+}
+
+/* population-size, <population-size> and population_size are all
+ * the same parameter */
+fn normalize_key (k: &str) -> String {
+        k.trim_matches(|c| c == '<' || c == '>' || c == '*')
+         .to_lowercase()
+         .replace("-", "_")
+}
+
+pub trait FromSexp : Sized {
+        fn from_sexp (s: &Sexp) -> Option<Self>;
+}
+
+pub trait ToSexp {
+        fn to_sexp (&self) -> String;
+}
+
+fn parse_uint (a: &str) -> Option<u64> {
+        if a.starts_with("#x") || a.starts_with("0x") {
+            u64::from_str_radix(&a[2..], 16).ok()
+        } else if a.starts_with("#b") {
+            u64::from_str_radix(&a[2..], 2).ok()
+        } else {
+            a.parse::<u64>().ok()
+        }
+}
+
+macro_rules! uint_sexp {
+        ($($t:ty),*) => {$(
+            impl FromSexp for $t {
+                fn from_sexp (s: &Sexp) -> Option<$t> {
+                    match s {
+                        /* out of range is as bad as unparseable */
+                        &Sexp::Atom(ref a) => parse_uint(a)
+                            .and_then(|x| if x <= <$t>::max_value() as u64 {
+                                Some(x as $t)
+                            } else {
+                                None
+                            }),
+                        _ => None,
+                    }
+                }
+            }
+            impl ToSexp for $t {
+                fn to_sexp (&self) -> String { format!("{}", self) }
+            }
+        )*}
+}
+uint_sexp!(u8, u32, u64, usize);
+
+impl FromSexp for f32 {
+        fn from_sexp (s: &Sexp) -> Option<f32> {
+            match s {
+                &Sexp::Atom(ref a) => a.parse::<f32>().ok(),
+                _ => None,
+            }
+        }
+}
+impl ToSexp for f32 {
+        fn to_sexp (&self) -> String { format!("{:?}", self) }
+}
+
+impl FromSexp for bool {
+        fn from_sexp (s: &Sexp) -> Option<bool> {
+            match s {
+                &Sexp::Atom(ref a) => match a.to_lowercase().as_str() {
+                    "t" | "true" | "#t"    => Some(true),
+                    "nil" | "false" | "#f" => Some(false),
+                    _ => None,
+                },
+                &Sexp::List(ref v) if v.is_empty() => Some(false),
+                _ => None,
+            }
+        }
+}
+impl ToSexp for bool {
+        fn to_sexp (&self) -> String {
+            if *self { "t".to_string() } else { "nil".to_string() }
+        }
+}
+
+impl FromSexp for String {
+        fn from_sexp (s: &Sexp) -> Option<String> {
+            match s {
+                &Sexp::Str(ref t)  => Some(t.clone()),
+                &Sexp::Atom(ref a) => Some(a.clone()),
+                _ => None,
+            }
+        }
+}
+impl ToSexp for String {
+        fn to_sexp (&self) -> String { format!("{}", Sexp::Str(self.clone())) }
+}
+
+impl <T: FromSexp> FromSexp for Vec<T> {
+        fn from_sexp (s: &Sexp) -> Option<Vec<T>> {
+            match s {
+                &Sexp::List(ref v) => v.iter().map(T::from_sexp).collect(),
+                &Sexp::Atom(ref a) if a == "nil" => Some(Vec::new()),
+                _ => None,
+            }
+        }
+}
+impl <T: ToSexp> ToSexp for Vec<T> {
+        fn to_sexp (&self) -> String {
+            let parts : Vec<String> = self.iter().map(|x| x.to_sexp()).collect();
+            format!("({})", parts.join(" "))
+        }
+}
+
+/* nil for nothing */
+impl <T: FromSexp> FromSexp for Option<T> {
+        fn from_sexp (s: &Sexp) -> Option<Option<T>> {
+            match s {
+                &Sexp::Atom(ref a) if a == "nil" => Some(None),
+                x => T::from_sexp(x).map(Some),
+            }
+        }
+}
+impl <T: ToSexp> ToSexp for Option<T> {
+        fn to_sexp (&self) -> String {
+            match self {
+                &Some(ref x) => x.to_sexp(),
+                &None        => "nil".to_string(),
+            }
+        }
+}
+
+impl <A: FromSexp, B: FromSexp> FromSexp for (A, B) {
+        fn from_sexp (s: &Sexp) -> Option<(A, B)> {
+            match s {
+                &Sexp::List(ref v) if v.len() == 2 =>
+                    match (A::from_sexp(&v[0]), B::from_sexp(&v[1])) {
+                        (Some(a), Some(b)) => Some((a, b)),
+                        _ => None,
+                    },
+                _ => None,
+            }
+        }
+}
+impl <A: ToSexp, B: ToSexp> ToSexp for (A, B) {
+        fn to_sexp (&self) -> String {
+            format!("({} {})", self.0.to_sexp(), self.1.to_sexp())
+        }
+}
+
+fn keyword (s: &Sexp) -> Option<String> {
+        match s {
+            &Sexp::Atom(ref a) => Some(a.trim_left_matches(':').to_uppercase()),
+            _ => None,
+        }
+}
+
+impl FromSexp for MachineMode {
+        fn from_sexp (s: &Sexp) -> Option<MachineMode> {
+            match keyword(s) {
+                Some(ref k) if k == "THUMB"   => Some(MachineMode::THUMB),
+                Some(ref k) if k == "ARM"     => Some(MachineMode::ARM),
+                Some(ref k) if k == "AARCH64" => Some(MachineMode::AARCH64),
+                Some(ref k) if k == "MIPS"    => Some(MachineMode::MIPS),
+                Some(ref k) if k == "MIPSEL"  => Some(MachineMode::MIPSEL),
+                _ => None,
+            }
+        }
+}
+impl ToSexp for MachineMode {
+        fn to_sexp (&self) -> String { format!(":{:?}", self).to_lowercase() }
+}
+
+impl FromSexp for ImageFormat {
+        fn from_sexp (s: &Sexp) -> Option<ImageFormat> {
+            match keyword(s) {
+                Some(ref k) if k == "AUTO"   => Some(ImageFormat::Auto),
+                Some(ref k) if k == "ELF"    => Some(ImageFormat::Elf),
+                Some(ref k) if k == "RAW"    => Some(ImageFormat::Raw),
+                Some(ref k) if k == "IHEX"   => Some(ImageFormat::IHex),
+                Some(ref k) if k == "SREC"   => Some(ImageFormat::SRec),
+                Some(ref k) if k == "MEMMAP" => Some(ImageFormat::MemMap),
+                _ => None,
+            }
+        }
+}
+impl ToSexp for ImageFormat {
+        fn to_sexp (&self) -> String { format!(":{:?}", self).to_lowercase() }
+}
+
+impl FromSexp for SelectionMethod {
+        fn from_sexp (s: &Sexp) -> Option<SelectionMethod> {
+            match keyword(s) {
+                Some(ref k) if k == "TOURNAMENT" => Some(SelectionMethod::Tournament),
+                Some(ref k) if k == "ROULETTE"   => Some(SelectionMethod::Roulette),
+                _ => None,
+            }
+        }
+}
+impl ToSexp for SelectionMethod {
+        fn to_sexp (&self) -> String { format!(":{:?}", self).to_lowercase() }
+}
+
+/* Names that main reads for itself: the task, and the log directory,
+ * which Params::set_log_dir dates and labels before it's stored. */
+pub static RUN_KEYS : [&'static str; 8] =
+        ["pattern", "syscall", "memory", "data", "kafka", "num_attrs",
+         "num_classes", "log_dir"];
+
+/* Every Params field that a config can set, and that we write back
+ * out. The rest are worked out at run time: code from the binary,
+ * io_targets from the task, the paths and timestamps from the date,
+ * and so on. */
+macro_rules! config_fields {
+        ($($f:ident),*) => {
+            fn set_field (params: &mut Params, key: &str, val: &Sexp) -> bool {
+                match key {
+                    $(stringify!($f) => {
+                        params.$f = FromSexp::from_sexp(val)
+                            .unwrap_or_else(|| panic!("Bad value in config for {}: {}", key, val));
+                        true
+                    },)*
+                    _ => false,
+                }
+            }
+
+            pub fn params_to_config (params: &Params) -> String {
+                let mut s = String::new();
+                $(s.push_str(&format!("({} {})\n",
+                                      stringify!($f).replace("_", "-"),
+                                      params.$f.to_sexp()));)*
+                s
+            }
+        }
+}
+
+config_fields!(bad_bytes, binary_path, brood_size, class_masks, comment,
+               constants, crash_penalty, crossover_rate, cuckoo_rate,
+               data_addrs, deref_size, edi_toggle_rate, entry, fatal_crash,
+               fit_goal, fitness_sharing, homologous_crossover, hook_base,
+               hook_size, host_port, image_format, initial_edi_rate, inregs,
+               label, libraries, load_base, max_iterations, max_len,
+               max_packed_len, max_start_len, max_steps, migration,
+               min_start_len, mode, num_demes, outregs, payload_offset,
+               population_size, random_override, reward_visitation_diversity,
+               sample_ratio, save_period, season_divisor, seed,
+               selection_method, snapshot, stack_base, stack_fill,
+               stack_input_sampling, stack_size, stop_addr, t_size, threads,
+               ttl, use_edis, use_viscosity, use_dynamic_crash_penalty,
+               verbose, visitation_diversity_weight);
+
+#[derive(Debug,Clone)]
+pub struct Config {
+        entries: Vec<(String, Sexp)>,
+}
+
+impl Config {
+        pub fn new () -> Config {
+            Config { entries: Vec::new() }
+        }
+
+        pub fn parse (text: &str) -> result::Result<Config, String> {
+            let mut entries = Vec::new();
+            for form in read_sexps(text)? {
+                let v = match form {
+                    Sexp::List(v) => v,
+                    x => return Err(format!("expected (name value), got {}", x)),
+                };
+                let def = match v.first() {
+                    Some(&Sexp::Atom(ref h)) => h == "defparameter" || h == "defvar",
+                    _ => false,
+                };
+                let v = if def { v[1..].to_vec() } else { v };
+                match (v.get(0), v.get(1)) {
+                    (Some(&Sexp::Atom(ref k)), Some(val)) =>
+                        entries.push((normalize_key(k), val.clone())),
+                    _ => return Err(format!("expected (name value), got {}",
+                                            Sexp::List(v.clone()))),
+                }
+            }
+            Ok(Config { entries: entries })
+        }
+
+        pub fn load (path: &str) -> result::Result<Config, String> {
+            let mut text = String::new();
+            File::open(path)
+                .and_then(|mut f| f.read_to_string(&mut text))
+                .map_err(|e| format!("{}: {}", path, e))?;
+            Config::parse(&text)
+        }
+
+        /* The last word on a name is the one that counts */
+        fn lookup (&self, key: &str) -> Option<&Sexp> {
+            self.entries
+                .iter()
+                .rev()
+                .find(|&&(ref k, _)| k == key)
+                .map(|&(_, ref v)| v)
+        }
+
+        pub fn get_opt <T: FromSexp> (&self, key: &str) -> Option<T> {
+            self.lookup(key).map(|v| {
+                T::from_sexp(v)
+                  .unwrap_or_else(|| panic!("Bad value in config for {}: {}", key, v))
+            })
+        }
+
+        pub fn get <T: FromSexp> (&self, key: &str, default: T) -> T {
+            self.get_opt(key).unwrap_or(default)
+        }
+
+        /* Sets every Params field the config mentions. An unknown
+         * name is more likely a typo than anything else, so we stop. */
+        pub fn apply (&self, params: &mut Params) {
+            for &(ref k, ref v) in &self.entries {
+                if !set_field(params, k, v) && !RUN_KEYS.contains(&k.as_str()) {
+                    panic!("Unknown parameter in config: {}", k);
+                }
+            }
+        }
+}
+
+/* Writes the parameters a run actually used, along with the task,
+ * as a config that will reproduce it. */
+pub fn write_config (params: &Params, task: &Vec<(String, String)>, path: &str) {
+        let mut s = format!(";; ROPER parameters for {}, {} {}\n",
+                            params.label, params.date_dir, params.timestamp);
+        for &(ref k, ref v) in task {
+            s.push_str(&format!("({} {})\n", k.replace("_", "-"), v));
+        }
+        s.push_str(&params_to_config(params));
+        let mut file = OpenOptions::new()
+                        .truncate(true)
+                        .write(true)
+                        .create(true)
+                        .open(path)
+                        .expect(&format!("Failed to open {} for config", path));
+        file.write_all(s.as_bytes())
+            .expect(&format!("Failed to write {}", path));
+}
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+
+        #[test]
+        fn reads_a_config () {
+            let c = Config::parse("
+                ;; iris, with a bigger brood
+                (binary-path \"/tmp/openssl\")
+                (<population-size> 2048)
+                (defparameter *brood-size* 4 \"docstring\")
+                (selection-method :roulette)
+                (bad-bytes '(#x00 0x0a 13))
+                (fitness-sharing t)
+                (payload-offset nil)
+                (brood-size 6)").unwrap();
+            let mut p = Params::new("test");
+            c.apply(&mut p);
+            assert_eq!(p.binary_path, "/tmp/openssl");
+            assert_eq!(p.population_size, 2048);
+            assert_eq!(p.brood_size, 6);
+            assert_eq!(p.selection_method, SelectionMethod::Roulette);
+            assert_eq!(p.bad_bytes, vec![0x00, 0x0a, 13]);
+            assert!(p.fitness_sharing);
+            assert_eq!(p.payload_offset, None);
+        }
+
+        #[test]
+        fn rejects_malformed_configs () {
+            assert!(read_sexps("(a (b c)").is_err());
+            assert!(read_sexps("(a b))").is_err());
+            assert!(read_sexps("(a \"b)").is_err());
+            assert!(Config::parse("population-size").is_err());
+            assert!(Config::parse("(population-size)").is_err());
+        }
+
+        #[test]
+        fn integers_must_fit () {
+            let atom = |s: &str| Sexp::Atom(s.to_string());
+            assert_eq!(u8::from_sexp(&atom("0xff")), Some(0xff));
+            assert_eq!(u8::from_sexp(&atom("0x1ff")), None);
+            assert_eq!(u8::from_sexp(&atom("256")), None);
+            assert_eq!(u32::from_sexp(&atom("#x100000000")), None);
+            assert_eq!(u64::from_sexp(&atom("#xffffffffffffffff")),
+                       Some(0xffffffffffffffff));
+            assert_eq!(u32::from_sexp(&atom("-1")), None);
+        }
+
+        #[test]
+        #[should_panic(expected = "Bad value in config for stack_fill")]
+        fn applying_an_oversized_byte_panics () {
+            let c = Config::parse("(stack-fill (#x41 #x1ff))").unwrap();
+            c.apply(&mut Params::new("test"));
+        }
+
+        #[test]
+        fn params_round_trip () {
+            let mut p = Params::new("test");
+            p.binary_path = "/tmp/some \"quoted\" path".to_string();
+            p.bad_bytes = vec![0, 10];
+            p.class_masks = vec![(0xf0, 1), (0x0f, 2)];
+            p.crossover_rate = 0.375;
+            p.entry = Some(0x8000);
+            p.libraries = vec![("libc.so".to_string(), 0x40000000)];
+            p.mode = MachineMode::THUMB;
+            p.image_format = ImageFormat::SRec;
+            p.selection_method = SelectionMethod::Roulette;
+            p.payload_offset = Some(16);
+            p.fitness_sharing = !p.fitness_sharing;
+            let text = params_to_config(&p);
+            let mut q = Params::new("test");
+            Config::parse(&text).unwrap().apply(&mut q);
+            assert_eq!(params_to_config(&q), text);
+            assert_eq!(q.binary_path, p.binary_path);
+            assert_eq!(q.class_masks, p.class_masks);
+            assert_eq!(q.libraries, p.libraries);
+            assert_eq!(q.mode, MachineMode::THUMB);
+            assert_eq!(q.image_format, ImageFormat::SRec);
+        }
+}
@@ -0,0 +1,452 @@
+// Saving and restoring chains, clumps, populations and parameters.
+// Everything that matters derives serde's Serialize and Deserialize,
+// and round-trips without loss, either as JSON, for people and other
+// tools, or as bincode, which is compact and quick, for archives and
+// checkpoints. A couple of field types need a hand: JSON only allows
+// string keys, so maps keyed on problems are written as lists of
+// pairs, and indextree's Arena knows nothing about serde at all.
+// Nor does JSON have NaN or the infinities, which serde_json quietly
+// writes as null, so float fields that can go non-finite (a fitness
+// of 0/0, say) are written through the float modules below.
+extern crate serde;
+extern crate serde_json;
+extern crate bincode;
+extern crate indextree;
+
+use std::f32;
+use std::fmt;
+use std::fs::{File,OpenOptions};
+use std::io::prelude::*;
+use std::result;
+
+use self::serde::{Serialize,Serializer,Deserialize,Deserializer};
+use self::serde::de::{self,DeserializeOwned,Visitor};
+
+pub type SerialResult<T> = result::Result<T, String>;
+
+/* An f32 that spells out NaN and the infinities as "NaN", "inf"
+ * and "-inf" in JSON, and reads them back, along with the nulls
+ * that serde_json used to write in their place. Bincode gets the
+ * bits as they are. */
+struct Float (f32);
+
+impl Serialize for Float {
+        fn serialize <S: Serializer> (&self, s: S) -> result::Result<S::Ok, S::Error> {
+            let x = self.0;
+            if x.is_finite() || !s.is_human_readable() {
+                s.serialize_f32(x)
+            } else if x.is_nan() {
+                s.serialize_str("NaN")
+            } else if x > 0.0 {
+                s.serialize_str("inf")
+            } else {
+                s.serialize_str("-inf")
+            }
+        }
+}
+
+struct FloatVisitor;
+
+impl <'de> Visitor<'de> for FloatVisitor {
+        type Value = Float;
+
+        fn expecting (&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a number, or \"NaN\", \"inf\" or \"-inf\"")
+        }
+        fn visit_f64 <E: de::Error> (self, x: f64) -> result::Result<Float, E> {
+            Ok(Float(x as f32))
+        }
+        fn visit_i64 <E: de::Error> (self, x: i64) -> result::Result<Float, E> {
+            Ok(Float(x as f32))
+        }
+        fn visit_u64 <E: de::Error> (self, x: u64) -> result::Result<Float, E> {
+            Ok(Float(x as f32))
+        }
+        fn visit_unit <E: de::Error> (self) -> result::Result<Float, E> {
+            Ok(Float(f32::NAN))
+        }
+        fn visit_str <E: de::Error> (self, s: &str) -> result::Result<Float, E> {
+            match s {
+                "NaN"  => Ok(Float(f32::NAN)),
+                "inf"  => Ok(Float(f32::INFINITY)),
+                "-inf" => Ok(Float(f32::NEG_INFINITY)),
+                _      => Err(E::invalid_value(de::Unexpected::Str(s), &self)),
+            }
+        }
+}
+
+impl <'de> Deserialize<'de> for Float {
+        fn deserialize <D: Deserializer<'de>> (d: D) -> result::Result<Float, D::Error> {
+            if d.is_human_readable() {
+                d.deserialize_any(FloatVisitor)
+            } else {
+                f32::deserialize(d).map(Float)
+            }
+        }
+}
+
+/* For f32 fields */
+pub mod float {
+        use std::result::Result;
+        use super::Float;
+        use super::serde::{Serialize,Serializer,Deserialize,Deserializer};
+
+        pub fn serialize <S: Serializer> (x: &f32, s: S) -> Result<S::Ok, S::Error> {
+            Float(*x).serialize(s)
+        }
+
+        pub fn deserialize <'de, D: Deserializer<'de>> (d: D) -> Result<f32, D::Error> {
+            Float::deserialize(d).map(|f| f.0)
+        }
+}
+
+/* For Option<f32> fields */
+pub mod float_opt {
+        use std::result::Result;
+        use super::Float;
+        use super::serde::{Serialize,Serializer,Deserialize,Deserializer};
+
+        pub fn serialize <S: Serializer> (x: &Option<f32>, s: S) -> Result<S::Ok, S::Error> {
+            x.map(Float).serialize(s)
+        }
+
+        pub fn deserialize <'de, D: Deserializer<'de>> (d: D) -> Result<Option<f32>, D::Error> {
+            let x : Option<Float> = Deserialize::deserialize(d)?;
+            Ok(x.map(|f| f.0))
+        }
+}
+
+/* For Vec<f32> fields */
+pub mod floats {
+        use std::result::Result;
+        use super::Float;
+        use super::serde::{Serializer,Deserialize,Deserializer};
+
+        pub fn serialize <S: Serializer> (v: &Vec<f32>, s: S) -> Result<S::Ok, S::Error> {
+            s.collect_seq(v.iter().map(|&x| Float(x)))
+        }
+
+        pub fn deserialize <'de, D: Deserializer<'de>> (d: D) -> Result<Vec<f32>, D::Error> {
+            let v : Vec<Float> = Deserialize::deserialize(d)?;
+            Ok(v.into_iter().map(|f| f.0).collect())
+        }
+}
+
+/* For lists of (a, b, f32), like RPattern's difficulties */
+pub mod float_triples {
+        use std::result::Result;
+        use super::Float;
+        use super::serde::{Serialize,Serializer,Deserialize,Deserializer};
+
+        pub fn serialize <A, B, S> (v: &Vec<(A,B,f32)>, s: S) -> Result<S::Ok, S::Error>
+            where A: Serialize,
+                  B: Serialize,
+                  S: Serializer {
+            s.collect_seq(v.iter().map(|&(ref a, ref b, x)| (a, b, Float(x))))
+        }
+
+        pub fn deserialize <'de, A, B, D> (d: D) -> Result<Vec<(A,B,f32)>, D::Error>
+            where A: Deserialize<'de>,
+                  B: Deserialize<'de>,
+                  D: Deserializer<'de> {
+            let v : Vec<(A,B,Float)> = Deserialize::deserialize(d)?;
+            Ok(v.into_iter().map(|(a,b,f)| (a,b,f.0)).collect())
+        }
+}
+
+/* Maps with keys that aren't strings, written as [[key, value], ...] */
+pub mod pairs {
+        use std::collections::HashMap;
+        use std::hash::Hash;
+        use std::result::Result;
+        use super::serde::{Serialize,Serializer,Deserialize,Deserializer};
+
+        pub fn serialize <K, V, S> (m: &HashMap<K,V>, s: S) -> Result<S::Ok, S::Error>
+            where K: Serialize + Eq + Hash,
+                  V: Serialize,
+                  S: Serializer {
+            s.collect_seq(m.iter())
+        }
+
+        pub fn deserialize <'de, K, V, D> (d: D) -> Result<HashMap<K,V>, D::Error>
+            where K: Deserialize<'de> + Eq + Hash,
+                  V: Deserialize<'de>,
+                  D: Deserializer<'de> {
+            let v : Vec<(K,V)> = Deserialize::deserialize(d)?;
+            Ok(v.into_iter().collect())
+        }
+}
+
+/* An Arena is written as a list of (data, children) nodes, in the
+ * order they were made, so that replaying new_node gives back the
+ * same ids, and the children lists restore the shape of the tree. */
+pub mod genealogy {
+        use std::collections::HashMap;
+        use std::result::Result;
+        use super::indextree::{Arena,NodeId};
+        use super::serde::{Serialize,Serializer,Deserialize,Deserializer};
+        use super::serde::de::Error;
+
+        /* NodeIds are plain indices that the Arena won't hand out
+         * except through new_node, so we mint them from a scratch
+         * arena of the same size. */
+        fn node_ids (n: usize) -> Vec<NodeId> {
+            let mut scratch : Arena<()> = Arena::new();
+            (0..n).map(|_| scratch.new_node(())).collect()
+        }
+
+        pub fn serialize <T, S> (a: &Arena<T>, s: S) -> Result<S::Ok, S::Error>
+            where T: Serialize,
+                  S: Serializer {
+            let ids = node_ids(a.count());
+            let index : HashMap<NodeId,usize> = ids.iter()
+                                                   .enumerate()
+                                                   .map(|(i,&n)| (n,i))
+                                                   .collect();
+            let nodes : Vec<(&T, Vec<usize>)> =
+                ids.iter()
+                   .map(|&n| (&a[n].data,
+                              n.children(a).map(|c| index[&c]).collect()))
+                   .collect();
+            nodes.serialize(s)
+        }
+
+        pub fn deserialize <'de, T, D> (d: D) -> Result<Arena<T>, D::Error>
+            where T: Deserialize<'de>,
+                  D: Deserializer<'de> {
+            let nodes : Vec<(T, Vec<usize>)> = Deserialize::deserialize(d)?;
+            let mut a = Arena::new();
+            let mut children = Vec::new();
+            let mut ids = Vec::new();
+            for (data, kids) in nodes {
+                ids.push(a.new_node(data));
+                children.push(kids);
+            }
+            for (i, kids) in children.iter().enumerate() {
+                for &k in kids {
+                    if k >= ids.len() || k == i {
+                        return Err(D::Error::custom(
+                                format!("bad genealogy node {} under {}", k, i)));
+                    }
+                    ids[i].append(ids[k], &mut a);
+                }
+            }
+            Ok(a)
+        }
+}
+
+pub fn to_json <T: Serialize> (t: &T) -> SerialResult<String> {
+        serde_json::to_string_pretty(t).map_err(|e| e.to_string())
+}
+
+pub fn from_json <T: DeserializeOwned> (s: &str) -> SerialResult<T> {
+        serde_json::from_str(s).map_err(|e| e.to_string())
+}
+
+pub fn to_binary <T: Serialize> (t: &T) -> SerialResult<Vec<u8>> {
+        bincode::serialize(t).map_err(|e| e.to_string())
+}
+
+pub fn from_binary <T: DeserializeOwned> (b: &[u8]) -> SerialResult<T> {
+        bincode::deserialize(b).map_err(|e| e.to_string())
+}
+
+fn is_json_path (path: &str) -> bool {
+        path.ends_with(".json")
+}
+
+/* Writes JSON if the path ends in .json, and bincode otherwise */
+pub fn save_to <T: Serialize> (t: &T, path: &str) -> SerialResult<()> {
+        let bytes = if is_json_path(path) {
+            to_json(t)?.into_bytes()
+        } else {
+            to_binary(t)?
+        };
+        let mut file = OpenOptions::new()
+                        .truncate(true)
+                        .write(true)
+                        .create(true)
+                        .open(path)
+                        .map_err(|e| format!("{}: {}", path, e))?;
+        file.write_all(&bytes).map_err(|e| format!("{}: {}", path, e))?;
+        file.flush().map_err(|e| format!("{}: {}", path, e))
+}
+
+/* The inverse of save_to, going by the same file extensions */
+pub fn load_from <T: DeserializeOwned> (path: &str) -> SerialResult<T> {
+        let mut file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).map_err(|e| format!("{}: {}", path, e))?;
+        if is_json_path(path) {
+            let s = String::from_utf8(bytes).map_err(|e| e.to_string())?;
+            from_json(&s)
+        } else {
+            from_binary(&bytes)
+        }
+}
+
+#[cfg(test)]
+mod tests {
+        use std::env;
+        use std::f32;
+        use std::fs;
+        use std::process;
+        use roper::phylostructs::*;
+        use super::*;
+        use super::indextree::Arena;
+
+        fn scratch_path (ext: &str) -> String {
+            env::temp_dir()
+                .join(format!("roper-serial-test-{}.{}", process::id(), ext))
+                .to_string_lossy()
+                .into_owned()
+        }
+
+        fn odd_chain () -> Chain {
+            let mut c = Chain::default();
+            c.fitness = Some(f32::NAN);
+            c.ab_fitness = Some(0.25);
+            c.p_fitness = vec![f32::INFINITY, 0.5, f32::NEG_INFINITY];
+            c.ratio_run = f32::NAN;
+            c.runtime = None;
+            c
+        }
+
+        fn check_chain (c: &Chain) {
+            assert!(c.fitness.unwrap().is_nan());
+            assert_eq!(c.ab_fitness, Some(0.25));
+            assert_eq!(c.p_fitness, vec![f32::INFINITY, 0.5, f32::NEG_INFINITY]);
+            assert!(c.ratio_run.is_nan());
+            assert_eq!(c.runtime, None);
+        }
+
+        #[test]
+        fn non_finite_floats_round_trip () {
+            for ext in &["json", "bin"] {
+                let path = scratch_path(ext);
+                save_to(&odd_chain(), &path).unwrap();
+                let c : SerialResult<Chain> = load_from(&path);
+                fs::remove_file(&path).unwrap();
+                check_chain(&c.unwrap());
+            }
+        }
+
+        /* Saves t both ways, and checks that what comes back saves
+         * the same again. HashMaps are written in whatever order they
+         * iterate in, so the ones here have one entry apiece. */
+        fn round_trip <T: Serialize + DeserializeOwned> (t: &T) -> Vec<T> {
+            let json = to_json(t).unwrap();
+            ["json", "bin"].iter().map(|ext| {
+                let path = scratch_path(ext);
+                save_to(t, &path).unwrap();
+                let back : SerialResult<T> = load_from(&path);
+                fs::remove_file(&path).unwrap();
+                let back = back.unwrap();
+                assert_eq!(to_json(&back).unwrap(), json, "through {}", ext);
+                back
+            }).collect()
+        }
+
+        fn busy_clump () -> Clump {
+            let mut c = Clump::new();
+            c.words = vec![0x8000, 0x41414141, 0x42424242];
+            c.ret_addr = 0x8010;
+            c.sp_delta = 3;
+            c.ret_offset = 2;
+            c.input_slots = vec![(1, 0), (2, 1)];
+            c.link_age = 7;
+            c.link_fit = Some(0.125);
+            c.ttl = 9;
+            c.section = ".text".to_string();
+            c
+        }
+
+        fn family_chain () -> Chain {
+            let mut c = Chain::default();
+            c.clumps = vec![busy_clump(), busy_clump()];
+            c.fitness = Some(0.5);
+            c.p_fitness = vec![0.25, 0.75];
+            c.name = "ba-be-bi".to_string();
+            c.input_slots = vec![(1, 0)];
+            /* grandparent, two parents, and the chain itself under the
+             * second, made out of order, so the ids matter */
+            let mut g = Arena::new();
+            let me = g.new_node(("ba-be-bi".to_string(), 0.5, 0.5, false));
+            let ma = g.new_node(("mo-mo".to_string(), 0.25, 0.5, true));
+            let pa = g.new_node(("po-po".to_string(), 0.75, 1.0, false));
+            let gran = g.new_node(("ga-ga".to_string(), 1.0, 1.0, true));
+            gran.append(ma, &mut g);
+            gran.append(pa, &mut g);
+            pa.append(me, &mut g);
+            c.genealogy = g;
+            let prob = Problem::new(vec![1, 2], Target::Exact(RPattern::new("0b,_,&68732f")));
+            c.visited_map.insert(prob.clone(), vec![0x8000, 0x8010]);
+            c.register_map.insert(prob, (vec![0xb, 0], vec![None, Some(vec![0x2f, 0x73])]));
+            c
+        }
+
+        #[test]
+        fn chains_round_trip_with_their_genealogy () {
+            for c in round_trip(&family_chain()) {
+                let g = &c.genealogy;
+                assert_eq!(g.count(), 4);
+                /* the tree comes back the same shape, under the same ids */
+                let orig = family_chain().genealogy;
+                let mut scratch = Arena::new();
+                let ids : Vec<_> = (0..4).map(|_| scratch.new_node(())).collect();
+                for &n in &ids {
+                    assert_eq!(g[n].data, orig[n].data);
+                    assert_eq!(n.children(g).collect::<Vec<_>>(),
+                               n.children(&orig).collect::<Vec<_>>());
+                }
+                assert_eq!(ids[3].children(g).collect::<Vec<_>>(), vec![ids[1], ids[2]]);
+                assert_eq!(ids[2].children(g).collect::<Vec<_>>(), vec![ids[0]]);
+                assert_eq!(c.clumps[1].input_slots, vec![(1, 0), (2, 1)]);
+                assert_eq!((c.clumps[1].link_age, c.clumps[1].ttl), (7, 9));
+            }
+        }
+
+        #[test]
+        fn populations_round_trip () {
+            let mut params = Params::new("serial-test");
+            params.training_ht.insert(vec![1, 2], 3);
+            params.io_targets = IoTargets::from_vec(
+                TargetKind::PatternMatch,
+                vec![Problem::new(vec![1, 2],
+                                  Target::Exact(RPattern::new("0b,_,&68732f")))],
+                0);
+            params.crossover_rate = f32::INFINITY;
+            let pop = Population {
+                deme: vec![family_chain(), Chain::default()],
+                best: Some(family_chain()),
+                iteration: 12,
+                season: 3,
+                params: params,
+                primordial_ooze: vec![busy_clump()],
+            };
+            for p in round_trip(&pop) {
+                assert_eq!(p.deme.len(), 2);
+                assert_eq!(p.deme[0].genealogy.count(), 4);
+                assert_eq!(p.best.unwrap().name, "ba-be-bi");
+                assert_eq!((p.iteration, p.season), (12, 3));
+                assert_eq!(p.params, pop.params);
+                assert_eq!(p.primordial_ooze[0].input_slots, vec![(1, 0), (2, 1)]);
+            }
+        }
+
+        #[test]
+        fn non_finite_floats_are_spelled_out () {
+            let json = to_json(&odd_chain()).unwrap();
+            assert!(json.contains("\"NaN\""));
+            assert!(json.contains("\"-inf\""));
+        }
+
+        #[test]
+        fn null_floats_load_as_nan () {
+            let json = to_json(&Chain::default()).unwrap()
+                        .replace("\"ratio_run\": 0.0", "\"ratio_run\": null");
+            assert!(json.contains("\"ratio_run\": null"));
+            let c : Chain = from_json(&json).unwrap();
+            assert!(c.ratio_run.is_nan());
+        }
+}
@@ -0,0 +1,386 @@
+// A live process, frozen at the moment its control flow is hijacked,
+// as the place to run chains in. A clean image with zeroed registers
+// is a poor stand-in for the real thing, where the heap is laid out,
+// the globals are initialised, and the registers still hold whatever
+// pointers the vulnerable function left there. A snapshot is either
+// an ELF core file, whose PT_LOAD segments are memory and whose first
+// NT_PRSTATUS note gives the registers of the thread that faulted; or
+// a copy of /proc/pid/maps, with beside it a file per region named
+// for its range as maps gives it (7ffd1000-7ffd3000, or with .bin on
+// the end), and a file named regs, with a register and its value on
+// each line, the way gdb's "info registers" prints them. A directory
+// holding all three will do in place of the maps file.
+//
+// The snapshot's memory is written over the image, and mapped where
+// the image has nothing, and its registers are where hatch_chain
+// starts from instead of zero. The region its stack pointer is in
+// becomes the stack: the chain is written where the stack pointer
+// points, and the region is put back the way the snapshot had it
+// before each run. For the two to agree, load the binary
+// and its libraries where the process had them, with --load_base and
+// --lib. Regions the snapshot holds no bytes for, such as file-backed
+// text left out of a core, are left to the image.
+extern crate elf;
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path,PathBuf};
+use std::result;
+use unicorn::*;
+
+use roper::util::get_word_endian;
+use roper::ontostructs::*;
+use roper::isa::Isa;
+use roper::loader::elf_endian;
+use roper::firmware::parse_perms;
+use roper::profiler::reg_name;
+use roper::phylostructs::{Endian,MachineMode};
+
+const NT_PRSTATUS : u32 = 1;
+
+#[derive(Debug,Clone)]
+pub struct SnapRegion {
+        pub name:  String,
+        pub begin: u64,
+        pub end:   u64, /* exclusive */
+        pub perm:  Protection,
+        pub data:  Vec<u8>, /* empty if the snapshot didn't keep it */
+}
+
+#[derive(Debug,Clone)]
+pub struct Snapshot {
+        pub path:    String,
+        pub regs:    Vec<u64>, /* in register-number order */
+        pub regions: Vec<SnapRegion>,
+}
+
+fn read_text (path: &Path) -> result::Result<String, String> {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(text)
+}
+
+/* The notes in a PT_NOTE segment, as (type, descriptor) */
+fn read_notes (seg: &Vec<u8>, endian: Endian) -> Vec<(u32, Vec<u8>)> {
+        let align = |n: usize| (n + 3) & !3;
+        let mut notes = Vec::new();
+        let mut i = 0;
+        while i + 12 <= seg.len() {
+            let namesz = get_word_endian(seg, i, 4, endian) as usize;
+            let descsz = get_word_endian(seg, i + 4, 4, endian) as usize;
+            let ntype  = get_word_endian(seg, i + 8, 4, endian) as u32;
+            let desc   = i + 12 + align(namesz);
+            if desc + descsz > seg.len() {
+                break;
+            }
+            notes.push((ntype, seg[desc..desc + descsz].to_vec()));
+            i = desc + align(descsz);
+        }
+        notes
+}
+
+/* pr_reg sits after the signal info, pids and times in struct
+ * elf_prstatus, and holds the registers in the kernel's order,
+ * which is ours, but for MIPS, which has six words of padding in
+ * front and the pc in cp0_epc, after lo and hi. */
+fn prstatus_regs (desc: &Vec<u8>, mode: MachineMode, endian: Endian) -> Option<Vec<u64>> {
+        let ws = mode.word_size();
+        let (off, slots) : (usize, Vec<usize>) = match mode {
+            MachineMode::AARCH64 => (112, (0..33).collect()),
+            MachineMode::MIPS |
+            MachineMode::MIPSEL  => (72, (6..38).chain(Some(40)).collect()),
+            _                    => (72, (0..16).collect()),
+        };
+        let last = slots.iter().max().unwrap();
+        if desc.len() < off + (last + 1) * ws {
+            return None;
+        }
+        Some(slots.iter()
+                  .map(|&s| get_word_endian(desc, off + s * ws, ws, endian))
+                  .collect())
+}
+
+pub fn read_core (path: &str, mode: MachineMode) -> result::Result<Snapshot, String> {
+        let file = elf::File::open_path(&PathBuf::from(path))
+                       .map_err(|e| format!("{:?}", e))?;
+        if file.ehdr.elftype != elf::types::ET_CORE {
+            return Err("not a core file".to_string());
+        }
+        let endian = elf_endian(&file);
+        let raw = load_file(path);
+        let mut regs = None;
+        let mut regions = Vec::new();
+        for phdr in file.phdrs.iter() {
+            let lo = (phdr.offset as usize).min(raw.len());
+            let hi = (lo + phdr.filesz as usize).min(raw.len());
+            let bytes = raw[lo..hi].to_vec();
+            match SegType::new(phdr.progtype.0) {
+                SegType::Load => regions.push(SnapRegion {
+                    name:  format!("core@{:08x}", phdr.vaddr),
+                    begin: phdr.vaddr,
+                    end:   phdr.vaddr + phdr.memsz,
+                    perm:  elf_to_uni_perms(phdr.flags.0),
+                    data:  bytes,
+                }),
+                /* the first thread's is the one that took the signal */
+                SegType::Note if regs.is_none() => {
+                    regs = read_notes(&bytes, endian)
+                               .into_iter()
+                               .filter(|&(t, _)| t == NT_PRSTATUS)
+                               .filter_map(|(_, d)| prstatus_regs(&d, mode, endian))
+                               .next();
+                },
+                _ => (),
+            }
+        }
+        Ok(Snapshot {
+            path:    path.to_string(),
+            regs:    regs.ok_or("no NT_PRSTATUS note for this architecture".to_string())?,
+            regions: regions,
+        })
+}
+
+/* r11 is fp and r12 ip to gdb; $s8 is $fp */
+fn reg_index (name: &str, mode: MachineMode) -> Option<usize> {
+        let name = name.trim_left_matches('$').to_lowercase();
+        let alias = match (mode, name.as_str()) {
+            (MachineMode::ARM, "fp")   | (MachineMode::THUMB, "fp")   => Some(11),
+            (MachineMode::ARM, "ip")   | (MachineMode::THUMB, "ip")   => Some(12),
+            (MachineMode::MIPS, "s8")  | (MachineMode::MIPSEL, "s8")  => Some(30),
+            (MachineMode::AARCH64, "fp") => Some(29),
+            (MachineMode::AARCH64, "lr") => Some(30),
+            _ => None,
+        };
+        alias.or((0..mode.num_registers()).find(|&r| {
+            reg_name(r, mode).trim_left_matches('$') == name || format!("r{}", r) == name
+        }))
+}
+
+/* name value [anything], with the value in hex or decimal. Registers
+ * we don't model, like cpsr and the FP registers, are passed over. */
+pub fn read_regs (text: &str, mode: MachineMode) -> result::Result<Vec<u64>, String> {
+        let mut regs = vec![0; mode.num_registers()];
+        for (n, line) in text.lines().enumerate() {
+            let mut cols = line.split_whitespace();
+            let (name, val) = match (cols.next(), cols.next()) {
+                (Some(name), Some(val)) => (name, val),
+                _ => continue,
+            };
+            let r = match reg_index(name, mode) {
+                Some(r) => r,
+                None    => continue,
+            };
+            let v = if val.starts_with("0x") {
+                u64::from_str_radix(&val[2..], 16)
+            } else {
+                val.parse::<u64>()
+            };
+            regs[r] = v.map_err(|_| format!("line {}: bad value for {}", n + 1, name))?;
+        }
+        Ok(regs)
+}
+
+pub fn read_proc_dump (maps_path: &str, mode: MachineMode) -> result::Result<Snapshot, String> {
+        let maps = Path::new(maps_path);
+        let dir  = maps.parent().unwrap_or(Path::new("."));
+        let mut regions = Vec::new();
+        for (n, line) in read_text(maps)?.lines().enumerate() {
+            /* 00010000-00012000 r-xp 00000000 08:01 1234   /bin/foo */
+            let cols : Vec<&str> = line.split_whitespace().collect();
+            if cols.len() < 2 {
+                continue;
+            }
+            let err = || format!("{}, line {}: can't read {:?}", maps_path, n + 1, line);
+            let range : Vec<&str> = cols[0].split('-').collect();
+            if range.len() != 2 || cols[1].len() < 3 {
+                return Err(err());
+            }
+            let begin = u64::from_str_radix(range[0], 16).map_err(|_| err())?;
+            let end   = u64::from_str_radix(range[1], 16).map_err(|_| err())?;
+            let perm  = parse_perms(&cols[1][..3]).ok_or(err())?;
+            let data  = [cols[0].to_string(), format!("{}.bin", cols[0])]
+                            .iter()
+                            .map(|f| dir.join(f))
+                            .find(|p| p.is_file())
+                            .map(|p| {
+                                let mut d = load_file(p.to_str().unwrap());
+                                d.truncate((end - begin) as usize);
+                                d
+                            })
+                            .unwrap_or(Vec::new());
+            regions.push(SnapRegion {
+                name:  cols.get(5).unwrap_or(&"[anon]").to_string(),
+                begin: begin,
+                end:   end,
+                perm:  perm,
+                data:  data,
+            });
+        }
+        let regs_path = dir.join("regs");
+        let regs = if regs_path.is_file() {
+            read_regs(&read_text(&regs_path)?, mode)?
+        } else {
+            println!("[-] No registers in {}; starting from zero", dir.display());
+            vec![0; mode.num_registers()]
+        };
+        Ok(Snapshot {
+            path:    maps_path.to_string(),
+            regs:    regs,
+            regions: regions,
+        })
+}
+
+pub fn load_snapshot (path: &str, mode: MachineMode) -> Snapshot {
+        let p = Path::new(path);
+        let res = if p.is_dir() {
+            read_proc_dump(p.join("maps").to_str().unwrap(), mode)
+        } else {
+            let mut magic = [0; 4];
+            let is_elf = File::open(p)
+                             .and_then(|mut f| f.read_exact(&mut magic))
+                             .is_ok() && &magic == b"\x7fELF";
+            if is_elf { read_core(path, mode) } else { read_proc_dump(path, mode) }
+        };
+        let snap = res.unwrap_or_else(|e| panic!("Failed to load snapshot {}: {}", path, e));
+        let kept = snap.regions.iter().filter(|r| !r.data.is_empty()).count();
+        println!("[*] Snapshot {}: {} regions, {} with contents",
+                 path, snap.regions.len(), kept);
+        snap
+}
+
+/* Map whatever pages of [begin, end) aren't mapped already */
+fn map_gaps <A: Isa> (uc: &mut Emu<A>, begin: u64, end: u64, perm: Protection) {
+        let regions = uc.mem_regions().expect("Failed to read memory regions");
+        let mapped = |page: u64| regions.iter().any(|r| r.begin <= page && page <= r.end);
+        let mut page = begin & !0xFFF;
+        while page < end {
+            if mapped(page) {
+                page += 0x1000;
+                continue;
+            }
+            let start = page;
+            while page < end && !mapped(page) {
+                page += 0x1000;
+            }
+            uc.mem_map(start, (page - start) as usize, perm)
+              .expect(&format!("Failed to map snapshot memory at {:08x}", start));
+        }
+}
+
+/* Lays the snapshot over an engine built from the image, and moves
+ * the stack to the snapshot's own, if the stack pointer is in one of
+ * the regions it kept. */
+pub fn restore_snapshot <A: Isa> (uc: &mut Emu<A>, snap: &Snapshot) {
+        let hook_end = uc.settings().hook_end();
+        for r in snap.regions.iter().filter(|r| !r.data.is_empty()) {
+            if r.end > hook_end {
+                println!("[!] Snapshot region {} at {:08x} is past the reach of the hooks",
+                         r.name, r.begin);
+            };
+            map_gaps(uc, r.begin, r.end, r.perm);
+            uc.mem_write(r.begin, &r.data)
+              .expect(&format!("Failed to write snapshot region {} at {:08x}",
+                               r.name, r.begin));
+        }
+        uc.set_start_regs(snap.regs.clone());
+        let sp = snap.regs[uc.mode.sp_num()];
+        match snap.regions.iter().find(|r| !r.data.is_empty() && r.begin <= sp && sp < r.end) {
+            Some(r) => {
+                let mut image = r.data.clone();
+                image.resize((r.end - r.begin) as usize, 0);
+                uc.set_snapshot_stack(MemRegion { begin: r.begin, end: r.end - 1, perms: r.perm },
+                                      image);
+            },
+            None    => println!("[!] The snapshot's stack pointer {:08x} isn't in any region it kept; \
+                                 chains will run on the engine's own stack", sp),
+        };
+}
+
+#[cfg(test)]
+mod tests {
+        use super::*;
+        use roper::util::pack_word_endian;
+
+        fn note (ntype: u32, name: &str, desc: &Vec<u8>, endian: Endian) -> Vec<u8> {
+            let mut v = Vec::new();
+            v.extend(pack_word_endian(name.len() as u64 + 1, 4, endian));
+            v.extend(pack_word_endian(desc.len() as u64, 4, endian));
+            v.extend(pack_word_endian(ntype as u64, 4, endian));
+            v.extend(name.bytes());
+            v.push(0);
+            while v.len() % 4 != 0 { v.push(0) };
+            v.extend(desc.iter().cloned());
+            while v.len() % 4 != 0 { v.push(0) };
+            v
+        }
+
+        /* an elf_prstatus, with word w of pr_reg holding 0x100 + w */
+        fn prstatus (off: usize, words: usize, ws: usize, endian: Endian) -> Vec<u8> {
+            let mut d = vec![0xEE; off];
+            for w in 0..words {
+                d.extend(pack_word_endian(0x100 + w as u64, ws, endian));
+            }
+            d
+        }
+
+        #[test]
+        fn reads_notes () {
+            for &endian in &[Endian::LITTLE, Endian::BIG] {
+                let mut seg = note(3, "CORE", &vec![1, 2, 3], endian);
+                seg.extend(note(NT_PRSTATUS, "CORE", &vec![9; 8], endian));
+                seg.extend(vec![0; 6]); // a truncated header
+                assert_eq!(read_notes(&seg, endian),
+                           vec![(3, vec![1, 2, 3]), (NT_PRSTATUS, vec![9; 8])]);
+            }
+            let mut cut = note(NT_PRSTATUS, "CORE", &vec![9; 8], Endian::LITTLE);
+            cut.truncate(20);
+            assert_eq!(read_notes(&cut, Endian::LITTLE), vec![]);
+        }
+
+        #[test]
+        fn reads_prstatus_regs () {
+            let arm = prstatus(72, 18, 4, Endian::LITTLE);
+            let regs = prstatus_regs(&arm, MachineMode::ARM, Endian::LITTLE).unwrap();
+            assert_eq!(regs, (0x100..0x110).collect::<Vec<u64>>());
+
+            let a64 = prstatus(112, 34, 8, Endian::LITTLE);
+            let regs = prstatus_regs(&a64, MachineMode::AARCH64, Endian::LITTLE).unwrap();
+            assert_eq!(regs.len(), 33);
+            assert_eq!((regs[0], regs[31], regs[32]), (0x100, 0x11f, 0x120));
+
+            /* six words of padding, the gprs, lo, hi, then cp0_epc */
+            let mips = prstatus(72, 45, 4, Endian::BIG);
+            let regs = prstatus_regs(&mips, MachineMode::MIPS, Endian::BIG).unwrap();
+            assert_eq!(regs.len(), 33);
+            assert_eq!((regs[0], regs[31], regs[32]), (0x106, 0x125, 0x128));
+
+            assert_eq!(prstatus_regs(&prstatus(72, 15, 4, Endian::LITTLE),
+                                     MachineMode::ARM, Endian::LITTLE), None);
+        }
+
+        #[test]
+        fn reads_gdb_registers () {
+            let regs = read_regs("\
+                r0             0x1                 1
+                r1             0x7efff6b4          2130704052
+                fp             0x7efff5fc          0x7efff5fc
+                ip             12                  12
+                sp             0x7efff5e8          0x7efff5e8
+                lr             0x76e8f678          1994979960
+                pc             0x10474             0x10474 <main+8>
+                cpsr           0x60000010          1610612752
+                ", MachineMode::ARM).unwrap();
+            assert_eq!(regs.len(), 16);
+            assert_eq!((regs[0], regs[1], regs[2]), (1, 0x7efff6b4, 0));
+            assert_eq!((regs[11], regs[12]), (0x7efff5fc, 12));
+            assert_eq!((regs[13], regs[14], regs[15]), (0x7efff5e8, 0x76e8f678, 0x10474));
+
+            let regs = read_regs("$s8 0x10\n$sp 0x20\nx 1", MachineMode::MIPSEL).unwrap();
+            assert_eq!((regs[29], regs[30]), (0x20, 0x10));
+
+            assert_eq!(read_regs("r0 0x1\nr1 zz", MachineMode::ARM),
+                       Err("line 2: bad value for r1".to_string()));
+        }
+}
@@ -1,5 +1,6 @@
-// TODO: maybe I should treat thumb instructions as u32s
-//       since not all of them will be u16.
+// Thumb-2 code mixes 16 and 32-bit instructions, so the scanner
+// first sweeps the halfwords into a stream of ThInsts, and works
+// over that instead of over the raw u16s.
 //
 
 use roper::util::*;
@@ -32,6 +33,13 @@ enum Lay {
         CB,
         UB,
         LBL,
+        /* 32-bit Thumb-2 layouts */
+        LDMW,   // ldm.w / pop.w
+        LDMDB,
+        LDRW,   // ldr.w, immediate offset forms
+        BMC,    // branches and miscellaneous control
+        TB,     // tbb / tbh
+        WIDE,   // anything else 32 bits wide
         RAWDATA,
 }
 
@@ -57,6 +65,16 @@ static MASK_VEC : &'static [(u16,u16,Lay)] = &[
             (0b1110000000000000,0b0000000000000000,Lay::MSR),
 ];
 
+// Masked against (hw1 << 16) | hw2. Order matters, as with MASK_VEC.
+static MASK_VEC_W : &'static [(u32,u32,Lay)] = &[
+            (0xFFF0FFE0,0xE8D0F000,Lay::TB),
+            (0xFFD00000,0xE8900000,Lay::LDMW),
+            (0xFFD00000,0xE9100000,Lay::LDMDB),
+            (0xFFF00800,0xF8500800,Lay::LDRW),
+            (0xFFF00000,0xF8D00000,Lay::LDRW),
+            (0xF8008000,0xF0008000,Lay::BMC),
+];
+
 fn what_layout (w: u16) -> Lay
 {
         for &(mask,sig,lay) in MASK_VEC.iter() {
@@ -65,6 +83,100 @@ fn what_layout (w: u16) -> Lay
         Lay::RAWDATA
 }
 
+fn what_layout_w (hw1: u16, hw2: u16) -> Lay
+{
+        let w : u32 = ((hw1 as u32) << 16) | hw2 as u32;
+        for &(mask,sig,lay) in MASK_VEC_W.iter() {
+            if mask & w == sig { return lay }
+        }
+        Lay::WIDE
+}
+
+/// A 32-bit instruction is flagged by 0b11101, 0b11110 or 0b11111
+/// in the top five bits of its first halfword.
+pub fn th_is_wide (hw: u16) -> bool {
+        hw >> 11 >= 0b11101
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+struct ThInst {
+        off : usize,        // offset from start of code, in halfwords
+        hw1 : u16,
+        hw2 : Option<u16>,  // second halfword, if 32 bits wide
+}
+
+impl ThInst {
+        fn layout (&self) -> Lay {
+            match self.hw2 {
+                None      => what_layout(self.hw1),
+                Some(hw2) => what_layout_w(self.hw1, hw2),
+            }
+        }
+}
+
+// Linear sweep from the start of the section. Thumb-2 can't be
+// decoded backwards reliably, since the second half of a wide
+// instruction can look like anything.
+fn th_decode (hws: &Vec<u16>) -> Vec<ThInst> {
+        let mut insts = Vec::new();
+        let mut i = 0;
+        while i < hws.len() {
+            if th_is_wide(hws[i]) && i + 1 < hws.len() {
+                insts.push(ThInst {off: i, hw1: hws[i], hw2: Some(hws[i+1])});
+                i += 2;
+            } else {
+                insts.push(ThInst {off: i, hw1: hws[i], hw2: None});
+                i += 1;
+            }
+        }
+        insts
+}
+
+fn ldmw_rn (hw1: u16) -> usize {
+        (hw1 & 0x0F) as usize
+}
+
+fn ldmw_wback (hw1: u16) -> bool {
+        hw1 & (1 << 5) != 0
+}
+
+// bit 15 is PC, bit 14 LR, and bit 13 (SP) must be clear
+fn ldmw_rlist (hw2: u16) -> Vec<usize> {
+        (0..16).filter(|&i: &usize| hw2 & (1 << i) != 0).collect()
+}
+
+fn ldmw_is_pop (hw1: u16) -> bool {
+        ldmw_rn(hw1) == SP && ldmw_wback(hw1)
+}
+
+fn ldrw_rt (hw2: u16) -> usize {
+        (hw2 >> 12) as usize
+}
+
+// ldr.w rt, [sp], #4 is the single-register pop
+fn ldrw_is_pop (hw1: u16, hw2: u16) -> bool {
+        ldmw_rn(hw1) == SP && hw1 & 0xFFF0 == 0xF850 && hw2 & 0x0FFF == 0x0B04
+}
+
+// T4 encoding with writeback, i.e. pre- or post-indexed
+fn ldrw_wback (hw1: u16, hw2: u16) -> bool {
+        hw1 & 0xFFF0 == 0xF850 && hw2 & 0x0100 != 0
+}
+
+/// Registers popped off the stack by inst, in the order they're
+/// taken from it, if inst is a pop of some kind.
+fn th_pop_rlist (inst: &ThInst) -> Option<Vec<usize>> {
+        match (inst.layout(), inst.hw2) {
+            (Lay::PPR, None) if ppr_is_pop(inst.hw1) 
+                => Some(ppr_rlist(inst.hw1)),
+            (Lay::LDMW, Some(hw2)) if ldmw_is_pop(inst.hw1) 
+                => Some(ldmw_rlist(hw2)),
+            (Lay::LDRW, Some(hw2)) if ldrw_is_pop(inst.hw1, hw2) 
+                => Some(vec![ldrw_rt(hw2)]),
+            _   => None,
+        }
+}
+
 fn ppr_rlist (w: u16) -> Vec<usize> {
         (0..9).filter(|&i: &usize| w & (1 << i) != 0)
                     .map(|x| match x == 8 {
@@ -106,8 +218,16 @@ fn bx_reg (w: u16) -> Option<usize> {
         }
 }
 
+// Rm sits in bits 6:3 of a BX, with bit 6 acting as the hi-register
+// flag, so the four bits can just be read off together.
 fn bx_reg_hrob (w: u16) -> usize {
-        (w as usize & 0b00111000) << (if w & (1 << 6) != 0 {8} else {0})
+        ((w >> 3) & 0x0F) as usize
+}
+
+// HROB also covers hi-register ADD, CMP and MOV. We only want BX
+// proper here, not BLX, which would clobber LR on its way out.
+fn is_bx (w: u16) -> bool {
+        w & 0xFF87 == 0x4700
 }
 
 
@@ -119,66 +239,77 @@ fn bx_reg_hrob (w: u16) -> usize {
 // clump. Follow it up with an "expand clump" function, that
 // will do the backwards walk, and then a "saturate clump"
 // function that will populate words.
-fn th_is_ctrl (w: u16) -> bool {
+fn th_is_ctrl (inst: &ThInst) -> bool {
         // check for control-flow instructions
         // i.e. the kind we don't typically want in gadgets
         // at least not yet
-            // stub
-        match what_layout(w) {
-            Lay::SWI  => true,
-            Lay::HROB => true,  // bit sloppy
-            Lay::PPR  => true,
-            Lay::UB   => true,
-            Lay::LBL  => true,
-            _         => false,
+        match (inst.layout(), inst.hw2) {
+            (Lay::SWI, _)   => true,
+            (Lay::HROB, _)  => true,  // bit sloppy
+            (Lay::PPR, _)   => true,
+            (Lay::UB, _)    => true,
+            (Lay::LBL, _)   => true,
+            (Lay::BMC, _)   => true,
+            (Lay::TB, _)    => true,
+            // anything that loads pc, or moves sp about
+            (Lay::LDMW, Some(hw2)) 
+            | (Lay::LDMDB, Some(hw2)) => ldmw_rlist(hw2).contains(&PC) 
+                                         || ldmw_rn(inst.hw1) == SP,
+            (Lay::LDRW, Some(hw2)) => ldrw_rt(hw2) == PC
+                                      || (ldmw_rn(inst.hw1) == SP 
+                                          && ldrw_wback(inst.hw1, hw2)),
+            _               => false,
         }
 }
 
-fn th_scan_for_rets (ws: &Vec<u16>) 
+fn th_scan_for_rets (insts: &Vec<ThInst>) 
                                                 -> Vec<Clump> {
-        // rets will be triplets of integers:
-        // prior padding, offset of ret inst, post padding
+        // the clumps returned carry the index of the ret
+        // instruction, in insts, as their only word
         let mut rets : Vec<Clump> = Vec::new();
-        let mut i    : usize = ws.len() as usize;
+        let mut i    : usize = insts.len() as usize;
         let mut bxr  : Option<(usize,usize)>  = None;
         while i > 0 {
             i -= 1;
-            match what_layout(ws[i]) {
-                // check to see if bxr, not just hrob
-                // fix this
-                Lay::HROB => {bxr = Some((bx_reg_hrob(ws[i]),i));}
-                Lay::PPR  => {
-                    let rs = ppr_rlist(ws[i]);
-                    if rs.contains(&PC) { // you can't push pc in thumb
-                        rets.push(Clump {
-                            exchange:   false,
-                            sp_delta:   rs.len() as i32,
-                            ret_offset: rs.len(),
-                            words:      vec![i as u32],
-                            mode:       MachineMode::THUMB,
-                            ..Default::default()
-                        });//(rs.len(),i,0,false));
-                    } else {
-                        match bxr {
-                            Some((r,o)) if rs.contains(&r) => {
-                                rets.push(Clump {
-                                    exchange:   true,
-                                    sp_delta:   rs.len() as i32,
-                                    ret_offset: (rs.index_of(r)+1),
-                                    words:      vec![o as u32],
-                                    mode:       MachineMode::THUMB,
-                                    ..Default::default()
-                                });
-                                /*(rs.len(), 
-                                                      o, 
-                                                      (rs.len()-(rs.index(r))),
-                                                      true)) */
-                            },
-                            _                              => {bxr = None;},
-                        }
+            let inst = &insts[i];
+            if inst.hw2.is_none() && is_bx(inst.hw1) {
+                bxr = Some((bx_reg_hrob(inst.hw1),i));
+                continue;
+            }
+            match th_pop_rlist(inst) {
+                Some(ref rs) if rs.contains(&PC) => {
+                    // On ARMv5T and up, popping into PC interworks,
+                    // so the LSB of the popped address picks the mode.
+                    // This goes for pop.w and ldr.w pc, [sp], #4 too.
+                    rets.push(Clump {
+                        exchange:   true,
+                        sp_delta:   rs.len() as i32,
+                        ret_offset: rs.len(),
+                        words:      vec![i as u64],
+                        mode:       MachineMode::THUMB,
+                        ..Default::default()
+                    });
+                    bxr = None;
+                },
+                Some(ref rs) => {
+                    match bxr {
+                        Some((r,o)) if rs.contains(&r) => {
+                            rets.push(Clump {
+                                exchange:   true,
+                                sp_delta:   rs.len() as i32,
+                                ret_offset: (rs.index_of(r)+1),
+                                words:      vec![o as u64],
+                                mode:       MachineMode::THUMB,
+                                kind:       GadgetKind::JumpReg,
+                                ..Default::default()
+                            });
+                        },
+                        _ => (),
                     }
+                    bxr = None;
                 },
-                _         => (),
+                None if th_is_ctrl(inst) => {bxr = None;},
+                None => (),
             }
         }
         rets
@@ -188,32 +319,35 @@ fn th_scan_for_rets (ws: &Vec<u16>)
 // will do the backwards walk, and then a "saturate clump"
 // function that will populate words.
 pub fn reap_thumb_gadgets (code: &Vec<u8>, 
-                                                          start_addr: u32) 
+                                                          start_addr: u64) 
                                                           -> Vec<Clump> {
         let mut gads : Vec<Clump> = Vec::new();
-        let insts : Vec<u16>      = u8s_to_u16s(code, Endian::LITTLE);
-        let mut rets : Vec<Clump> = th_scan_for_rets(&insts); 
+        let halves : Vec<u16>     = u8s_to_u16s(code, Endian::LITTLE);
+        let insts : Vec<ThInst>   = th_decode(&halves);
+        let rets : Vec<Clump>     = th_scan_for_rets(&insts); 
 
-        // rets = Vec<(prior padding, offset, post padding)>
         for clump in rets {
             // now start walking up from offset until you hit a 
-            // control instruction
-            let from : u32 = clump.words[0];
-            let mut o = from.clone() as usize;
-            while o > 0 && o > (from as usize - 8) {
+            // control instruction. o and from index insts, not
+            // halfwords, so the walk steps over wide instructions
+            // whole.
+            let from = clump.words[0] as usize;
+            let mut o = from;
+            while o > 0 && o > from.saturating_sub(8) {
                 // any additional sp changes affect prior, but not post
                 // so i could mutate p here, relative to insts[o]
                 // but for now, we'll keep it simple
-                if th_is_ctrl(insts[o - 1]) { break } else { o -= 1 }
+                if th_is_ctrl(&insts[o - 1]) { break } else { o -= 1 }
             }
-            let a = start_addr + (2 * o as u32);
+            let a = start_addr + (2 * insts[o].off as u64);
             gads.push(Clump { 
                 sp_delta   : clump.sp_delta,
                 ret_offset : clump.ret_offset,
                 words      : vec![a],
-                ret_addr   : start_addr + (4 * from),
+                ret_addr   : start_addr + (2 * insts[from].off as u64),
                 mode       : clump.mode,
                 exchange   : clump.exchange,
+                kind       : clump.kind,
                 ..Default::default()
             });
         }
@@ -1,6 +1,7 @@
 #[allow(dead_code)]
 
 use roper::phylostructs::*;
+use roper::isa::Isa;
 use rand::*;
 use std::cmp::*;
 use capstone::*;
@@ -14,15 +15,15 @@ fn cs_insn_to_string (insn: &Insn) -> String {
                              .unwrap_or("?")) 
 }
 
-pub fn disas (insts: &Vec<u8>, mode: MachineMode) -> String {
+pub fn disas <A: Isa> (insts: &Vec<u8>, mode: A) -> String {
         let cs : Capstone = 
-            Capstone::new(CsArch::ARCH_ARM, mode.cs()).unwrap();
+            Capstone::new(mode.cs_arch(), mode.cs_mode()).unwrap();
         let dissed : Vec<String> = 
             match cs.disasm(insts, 0, 0) {
                 Some(s)  => s.iter().map(|x| cs_insn_to_string(&x)).collect(),
                 _        => {
                     if insts.len() == 4 {
-                        let w = get_word32le(&insts, 0);
+                        let w = get_word_endian(&insts, 0, 4, mode.endian());
                         vec![format!("[{:32b}]",w)]
                     } else {
                         vec!["[?]".to_string()]
@@ -32,8 +33,8 @@ pub fn disas (insts: &Vec<u8>, mode: MachineMode) -> String {
         dissed.join("; ")      
 }
 
-pub fn disas32 (inst: u32, mode: MachineMode) -> String {
-        let v8 = pack_word32le(inst);
+pub fn disas32 <A: Isa> (inst: u32, mode: A) -> String {
+        let v8 = pack_word_endian(inst as u64, 4, mode.endian());
         disas(&v8, mode)
 }
 
@@ -81,6 +82,57 @@ pub fn pack_word32le_vec (v: &Vec<u32>) -> Vec<u8> {
         p
 }
 
+/* Little-endian words of either width, 4 or 8 bytes. */
+pub fn get_word (a: &Vec<u8>, offset: usize, width: usize) -> u64 {
+        let mut s : u64 = 0;
+        for i in 0..width {
+            s |= (a[i+offset] as u64) << (i*8);
+        }
+        s
+}
+
+pub fn pack_word (n: u64, width: usize) -> Vec<u8> {
+        (0..width).map(|i| ((n >> (i*8)) & 0xFF) as u8)
+                  .collect()
+}
+
+pub fn word_mask (width: usize) -> u64 {
+        if width >= 8 { !0 } else { (1 << (width*8)) - 1 }
+}
+
+pub fn sign_extend (n: u64, width: usize) -> i64 {
+        let shift = 64 - (width*8);
+        ((n << shift) as i64) >> shift
+}
+
+pub fn pack_words_le (v: &Vec<u64>, width: usize) -> Vec<u8> {
+        pack_words(v, width, Endian::LITTLE)
+}
+
+/* The same, in whichever byte order the machine wants. Only MIPS
+ * gives us big-endian words. */
+pub fn get_word_endian (a: &Vec<u8>, offset: usize, width: usize, 
+                        endian: Endian) -> u64 {
+        match endian {
+            Endian::LITTLE => get_word(a, offset, width),
+            Endian::BIG    => (0..width).fold(0, |s, i| (s << 8) | a[i+offset] as u64),
+        }
+}
+
+pub fn pack_word_endian (n: u64, width: usize, endian: Endian) -> Vec<u8> {
+        let mut v = pack_word(n, width);
+        if endian == Endian::BIG { v.reverse() };
+        v
+}
+
+pub fn pack_words (v: &Vec<u64>, width: usize, endian: Endian) -> Vec<u8> {
+        let mut p : Vec<u8> = Vec::new();
+        for w in v {
+            p.extend(pack_word_endian(*w, width, endian).iter())
+        }
+        p
+}
+
 
 pub fn get_word16le (a: &Vec<u8>, offset: usize) -> u16 {
         let mut s : u16 = 0;
@@ -90,14 +142,14 @@ pub fn get_word16le (a: &Vec<u8>, offset: usize) -> u16 {
         s
 }
 // same as hexvec but w/o leading 0s
-pub fn hexvec_ (v: &Vec<u32>) -> String{
+pub fn hexvec_ (v: &Vec<u64>) -> String{
         let vs : Vec<String> = v.iter()
                                 .map(|x| format!("{:x}",x))
                                 .collect();
         vs.join(" ")
 }
 // pretty-print the contents of a vector in hex
-pub fn hexvec (v: &Vec<u32>) -> String{
+pub fn hexvec (v: &Vec<u64>) -> String{
         let vs : Vec<String> = v.iter()
                                 .map(|x| format!("{:08x}",x))
                                 .collect();
@@ -172,7 +224,7 @@ pub fn u8s_to_u16s (bytes: &Vec<u8>, endian: Endian) -> Vec<u16> {
         let l = bytes.len();
         let mut i = 0;
         let mut out = Vec::new();
-        while i < l {
+        while i + 1 < l {
             out.push(((bytes[i] as u16) << be) | ((bytes[i+1] as u16) << le));
             i += 2;
         }
@@ -180,29 +232,58 @@ pub fn u8s_to_u16s (bytes: &Vec<u8>, endian: Endian) -> Vec<u16> {
 }
 
 pub fn u8s_to_u32s (bytes: &Vec<u8>, endian: Endian) -> Vec<u32> {
-        let getter = get_word32le; // check endian and use be version too
         let l = bytes.len();
         let mut i = 0;
         let step = 4;
         let mut out = Vec::new();
-        while i < l {
-            out.push(getter(bytes, i));
+        while i + step <= l {
+            out.push(get_word_endian(bytes, i, step, endian) as u32);
             i += step;
         }
         out
 }
 
-pub fn deref_mang (x: u32, 
-                                          data: &Vec<u32>, 
-                                          offset: u32) -> u32 {
+pub fn deref_mang (x: u64, 
+                                          data: &Vec<u64>, 
+                                          offset: u64) -> u64 {
         match data.index_opt(x) {
-            Some(p) => (p as u32 * 4 as u32) + offset,
+            Some(p) => (p as u64 * 4 as u64) + offset,
             None    => x,
         }
 }
 
-pub fn mang (ux: u32, rng: &mut ThreadRng) -> u32 {
-        let x = ux as i32;
+/* Every random choice in a run is drawn from one of these, all of
+ * them descended from the master seed in Params, so that the same
+ * seed and thread count give the same run. */
+pub type RoperRng = XorShiftRng;
+
+/* Spreads a 64-bit seed over xorshift's 128 bits of state with
+ * splitmix64, so that nearby seeds give unrelated streams. */
+pub fn seeded_rng (seed: u64) -> RoperRng {
+        let mut x = seed;
+        let mut next = || {
+            x = x.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            z ^ (z >> 31)
+        };
+        let (a, b) = (next(), next());
+        let mut st = [a as u32, (a >> 32) as u32, b as u32, (b >> 32) as u32];
+        if st == [0; 4] { st[0] = 1 }; // xorshift's one bad seed
+        XorShiftRng::from_seed(st)
+}
+
+/* A fresh, independent stream, for handing to a worker */
+pub fn child_rng (rng: &mut RoperRng) -> RoperRng {
+        let seed = rng.gen::<u64>();
+        seeded_rng(seed)
+}
+
+// Only the low 32 bits get mangled. The high half of a 64-bit
+// word, if any, is left as it was.
+pub fn mang (ux: u64, rng: &mut RoperRng) -> u64 {
+        let x = ux as u32 as i32;
         let die : u8 = rng.gen::<u8>() % 40;
         let r = match die {
             /* eliminating non-involutable operations */
@@ -231,28 +312,48 @@ pub fn mang (ux: u32, rng: &mut ThreadRng) -> u32 {
             30 => x ^ (1 << (rng.gen::<usize>() % 32)), // random bit flip
             _  => x,
         };
-        r as u32
+        (ux & !0xFFFFFFFF) | (r as u32 as u64)
+}
+
+/* Does w, packed into width bytes, steer clear of every bad byte?
+ * Byte order doesn't matter for that. */
+pub fn word_is_clean (w: u64, width: usize, bad: &Vec<u8>) -> bool {
+        bad.is_empty() || pack_word(w, width).iter().all(|b| !bad.contains(b))
+}
+
+pub fn count_bad_bytes (bytes: &Vec<u8>, bad: &Vec<u8>) -> usize {
+        bytes.iter().filter(|b| bad.contains(b)).count()
 }
 
 pub struct Mangler {
-        pub words: Vec<u32>,
-        pub rng:   ThreadRng,
+        pub words: Vec<u64>,
+        pub rng:   RoperRng,
         cursor:    usize,
 }
 
 impl Mangler {
-        pub fn new (ws: &Vec<u32>) -> Mangler {
+        pub fn new (ws: &Vec<u64>, rng: RoperRng) -> Mangler {
             Mangler {
                 words  : ws.clone(),
-                rng    : thread_rng(),
+                rng    : rng,
                 cursor : 0,
             }
         }
+        /* Only hand out words free of bad bytes, unless there are
+         * none of those, in which case the fitness penalty will have
+         * to sort it out. */
+        pub fn avoiding (ws: &Vec<u64>, width: usize, bad: &Vec<u8>, rng: RoperRng) -> Mangler {
+            let clean : Vec<u64> = ws.iter()
+                                     .cloned()
+                                     .filter(|&w| word_is_clean(w, width, bad))
+                                     .collect();
+            Mangler::new(if clean.is_empty() { ws } else { &clean }, rng)
+        }
 }
 
 impl Iterator for Mangler {
-        type Item = u32;
-        fn next(&mut self) -> Option<u32> {
+        type Item = u64;
+        fn next(&mut self) -> Option<u64> {
             /*Some(mang(self.words[self.rng.gen::<usize>() % 
                                 self.words.len()], &mut self.rng))
                                 */